@@ -7,12 +7,20 @@
  *
  * Config (kwinrc):
  *   [Script-kwin-focus-helper]
- *   forceFocusClasses=google-chrome;google-chrome-stable;ProcletChrome
+ *   forceFocusClasses=google-chrome;google-chrome-stable;resourceClass:ProcletChrome
+ *   forceFocusRules=firefox:raise;steam:activate:dialogs
  *   mode=activate        # or: raise
  *   debug=false
  *
  * Notes:
  * - Matching is case-insensitive and strips a trailing ".desktop".
+ * - An entry may be limited to one property with a prefix: desktop: (or
+ *   desktopFileName:), class: (resourceClass:) or name: (resourceName:).
+ * - An entry may be a glob (proclet-*) or an anchored /regex/; exact entries
+ *   win over patterns.
+ * - A rule is class[:activate|raise][:normal|dialogs]; it overrides the global
+ *   mode and/or limits the class to one window type. Rules win over a plain
+ *   forceFocusClasses entry with the same key; unreadable rules are skipped.
  * - For best coverage, we match against desktopFileName, resourceClass, resourceName.
  */
 
@@ -34,6 +42,167 @@
     return s.toLowerCase();
   }
 
+  // Entry prefix -> short field name used in match keys.
+  var FIELD_PREFIXES = {
+    "desktop": "desktop", "desktopfile": "desktop", "desktopfilename": "desktop",
+    "class": "class", "resourceclass": "class",
+    "name": "name", "resourcename": "name"
+  };
+
+  function globToRegex(g) {
+    // proclet-* -> proclet-.*  (same as focusctl's pattern::glob_to_regex()).
+    var out = "";
+    for (var i = 0; i < g.length; i++) {
+      var c = g.charAt(i);
+      if (c === "*") {
+        out += ".*";
+      } else if (c === "?") {
+        out += ".";
+      } else if (c === "[") {
+        out += "[";
+        if (g.charAt(i + 1) === "!" || g.charAt(i + 1) === "^") {
+          out += "^";
+          i++;
+        }
+        var first = true;
+        var closed = false;
+        for (i++; i < g.length; i++) {
+          var d = g.charAt(i);
+          if (d === "]" && !first) { closed = true; break; }
+          if (d === "\\" || d === "[" || (d === "]" && first)) out += "\\";
+          out += d;
+          first = false;
+        }
+        if (!closed) return null;
+        out += "]";
+      } else if (/[!-\/:-@\[-`{-~]/.test(c) && c !== "_" && c !== "-") {
+        out += "\\" + c;
+      } else {
+        out += c;
+      }
+    }
+    return out;
+  }
+
+  function lowerSource(src) {
+    // Lowercase all but escaped characters (\D must stay \D).
+    var out = "";
+    for (var i = 0; i < src.length; i++) {
+      var c = src.charAt(i);
+      if (c === "\\" && i + 1 < src.length) {
+        out += c + src.charAt(i + 1);
+        i++;
+      } else {
+        out += c.toLowerCase();
+      }
+    }
+    return out;
+  }
+
+  function supportedPattern(body) {
+    // Only the regex subset focusctl's pattern module accepts: literals,
+    // escapes, ".", [...], \d \w \s, (...) / (?:...), "|" and single greedy
+    // * + ? quantifiers. Anything else is skipped like focusctl rejects it.
+    // A repeated group must match one way only (RegExp backtracks): no
+    // quantifier inside, and the alternatives of every group in it start
+    // with different literal characters. "(a|aa)*" is rejected.
+    var inClass = false;
+    var prev = ""; // "a" after an atom, "q" after a quantifier
+    var groups = []; // open groups, innermost last
+    function atom(literal) {
+      // literal: the character an alternative starts with, or null.
+      prev = "a";
+      var g = groups[groups.length - 1];
+      if (!g || !g.atStart) return;
+      g.atStart = false;
+      g.alts++;
+      if (literal === null || g.firsts.indexOf(literal) >= 0) g.odd = true;
+      else g.firsts += literal;
+    }
+    for (var i = 0; i < body.length; i++) {
+      var c = body.charAt(i);
+      if (c === "\\") {
+        var e = body.charAt(++i);
+        if (!/[dDwWsS!-\/:-@\[-`{-~]/.test(e)) return false;
+        if (!inClass) atom(/[dDwWsS]/.test(e) ? null : e);
+      } else if (inClass) {
+        if (c === "[") return false;
+        if (c === "]") inClass = false;
+      } else if (c === "[") {
+        inClass = true;
+        if (body.charAt(i + 1) === "^") i++;
+        atom(null);
+      } else if (c === "*" || c === "+" || c === "?") {
+        if (prev !== "a") return false;
+        prev = "q";
+        if (groups.length) groups[groups.length - 1].ambiguous = true;
+      } else if (c === "(") {
+        if (body.charAt(i + 1) === "?") {
+          if (body.charAt(i + 2) !== ":") return false;
+          i += 2;
+        }
+        atom(null);
+        groups.push({ atStart: true, alts: 0, firsts: "", odd: false, ambiguous: false });
+        prev = "";
+      } else if (c === ")") {
+        var g = groups.pop();
+        if (!g) return false;
+        var ambiguous = g.ambiguous || (g.alts > 1 && g.odd);
+        if (ambiguous && /[*+?]/.test(body.charAt(i + 1))) return false;
+        if (ambiguous && groups.length) groups[groups.length - 1].ambiguous = true;
+        prev = "a";
+      } else if (c === "|") {
+        if (groups.length) groups[groups.length - 1].atStart = true;
+        prev = "";
+      } else if (c === "{" || c === "^" || c === "$") {
+        return false;
+      } else {
+        atom(c === "." ? null : c);
+      }
+    }
+    return !inClass;
+  }
+
+  function compilePattern(body) {
+    // Anchored, case-insensitive.
+    if (!supportedPattern(body)) return null;
+    try { return new RegExp("^(?:" + body + ")$", "i"); } catch (_) { return null; }
+  }
+
+  function valueKey(v) {
+    // Class value -> match key: normClass(), or "/^...$/" for a glob or
+    // /regex/ (same as focusctl's class_key()). "" = unreadable.
+    v = safeStr(v).trim();
+    if (v.charAt(0) === "/") {
+      if (v.length < 3 || v.slice(-1) !== "/") return "";
+      var body = v.slice(1, -1);
+      if (body.charAt(0) === "^") body = body.slice(1);
+      if (body.slice(-1) === "$" && body.slice(-2, -1) !== "\\") body = body.slice(0, -1);
+      body = lowerSource(body);
+      return compilePattern(body) ? "/^" + body + "$/" : "";
+    }
+    v = normClass(v);
+    if (/[*?[]/.test(v)) {
+      var re = globToRegex(v);
+      return (re !== null && compilePattern(re)) ? "/^" + re + "$/" : "";
+    }
+    return v;
+  }
+
+  function normEntry(s) {
+    // "resourceClass:Foo" -> "class:foo"; unknown prefix -> "" (never matches).
+    // Same as focusctl's class_key().
+    // A /regex/ may itself contain ":" ("(?:...)").
+    s = safeStr(s).trim();
+    var at = s.indexOf(":");
+    if (at < 0 || s.charAt(0) === "/") return valueKey(s);
+    var field = FIELD_PREFIXES[s.slice(0, at).trim().toLowerCase()];
+    var rest = s.slice(at + 1);
+    if (!field || (rest.indexOf(":") >= 0 && rest.trim().charAt(0) !== "/")) return "";
+    rest = valueKey(rest);
+    return rest ? field + ":" + rest : "";
+  }
+
   function splitClasses(raw) {
     // Split on whitespace, ';' or ','.
     // Avoid fancy JS features for max compatibility.
@@ -41,7 +210,7 @@
     var set = Object.create(null);
     var list = [];
     for (var i = 0; i < parts.length; i++) {
-      var c = normClass(parts[i]);
+      var c = normEntry(parts[i]);
       if (c && !set[c]) {
         set[c] = true;
         list.push(c);
@@ -50,12 +219,49 @@
     return { set: set, list: list };
   }
 
+  function ruleMode(s) {
+    s = safeStr(s).trim().toLowerCase();
+    return (s === "activate" || s === "raise") ? s : "";
+  }
+
+  function ruleOnly(s) {
+    s = safeStr(s).trim().toLowerCase();
+    if (s === "normal" || s === "normal-only") return "normal";
+    if (s === "dialog" || s === "dialogs" || s === "dialogs-only") return "dialogs";
+    return "";
+  }
+
+  function parseRule(entry) {
+    // class[:mode][:only], options peeled off the right end.
+    // Same rules as focusctl's rules::parse_rule(); null = skip the entry.
+    var parts = safeStr(entry).trim().split(":");
+    var rule = { key: "", mode: "", only: "" };
+    while (parts.length > 1) {
+      var last = parts[parts.length - 1];
+      var m = ruleMode(last);
+      var o = ruleOnly(last);
+      if (m) {
+        if (rule.mode) return null;
+        rule.mode = m;
+      } else if (o) {
+        if (rule.only) return null;
+        rule.only = o;
+      } else {
+        break;
+      }
+      parts.pop();
+    }
+    rule.key = normEntry(parts.join(":"));
+    return rule.key ? rule : null;
+  }
+
   // -----------------------
   // Config
   // -----------------------
 
-  var forcedSet = Object.create(null);
+  var forcedSet = Object.create(null); // key -> { key, mode, only }
   var forcedList = [];
+  var forcedPatterns = []; // { field, re, rule }, in configured order
   var debug = false;
   var mode = "activate"; // "activate" or "raise"
 
@@ -66,10 +272,12 @@
 
   function reloadConfig() {
     var raw = "";
+    var rawRules = "";
     var rawDebug = "false";
     var rawMode = "activate";
 
     try { raw = readConfig("forceFocusClasses", ""); } catch (_) { raw = ""; }
+    try { rawRules = readConfig("forceFocusRules", ""); } catch (_) { rawRules = ""; }
     try { rawDebug = readConfig("debug", "false"); } catch (_) { rawDebug = "false"; }
     try { rawMode = readConfig("mode", "activate"); } catch (_) { rawMode = "activate"; }
 
@@ -77,9 +285,40 @@
     mode = normClass(rawMode) || "activate";
     if (mode !== "raise" && mode !== "activate") mode = "activate";
 
+    // Rules first, so they win over a plain class with the same key.
+    var set = Object.create(null);
+    var list = [];
+    var entries = safeStr(rawRules).split(/[\s;,]+/);
+    for (var i = 0; i < entries.length; i++) {
+      if (!entries[i]) continue;
+      var rule = parseRule(entries[i]);
+      if (!rule) {
+        log("skipping unreadable rule: " + entries[i]);
+        continue;
+      }
+      if (set[rule.key]) continue;
+      set[rule.key] = rule;
+      list.push(rule.key + (rule.mode ? ":" + rule.mode : "") + (rule.only ? ":" + rule.only : ""));
+    }
     var parsed = splitClasses(raw);
-    forcedSet = parsed.set;
-    forcedList = parsed.list;
+    for (var j = 0; j < parsed.list.length; j++) {
+      var key = parsed.list[j];
+      if (set[key]) continue;
+      set[key] = { key: key, mode: "", only: "" };
+      list.push(key);
+    }
+    var patterns = [];
+    for (var k in set) {
+      var at = k.indexOf(":");
+      var field = (at > 0 && k.charAt(0) !== "/") ? k.slice(0, at) : "";
+      var value = field ? k.slice(at + 1) : k;
+      if (value.charAt(0) !== "/") continue;
+      var re = compilePattern(value.slice(2, -2));
+      if (re) patterns.push({ field: field, re: re, rule: set[k] });
+    }
+    forcedSet = set;
+    forcedList = list;
+    forcedPatterns = patterns;
 
     log("config reloaded: forced=[" + forcedList.join(", ") + "], mode=" + mode);
   }
@@ -89,19 +328,19 @@
   // -----------------------
 
   function windowCandidates(w) {
-    // Return normalized candidates, best-first.
+    // Return normalized { field, key } candidates, best-first.
     var out = [];
-    function push(v) {
+    function push(field, v) {
       var n = normClass(v);
-      if (n) out.push(n);
+      if (n) out.push({ field: field, key: n });
     }
 
     if (!w) return out;
 
     // Prefer desktopFileName (Wayland-ish), then resourceClass (X11-ish), then resourceName.
-    try { if (w.desktopFileName) push(w.desktopFileName); } catch (_) {}
-    try { if (w.resourceClass) push(w.resourceClass); } catch (_) {}
-    try { if (w.resourceName) push(w.resourceName); } catch (_) {}
+    try { if (w.desktopFileName) push("desktop", w.desktopFileName); } catch (_) {}
+    try { if (w.resourceClass) push("class", w.resourceClass); } catch (_) {}
+    try { if (w.resourceName) push("name", w.resourceName); } catch (_) {}
 
     return out;
   }
@@ -109,9 +348,16 @@
   function matchForced(w) {
     var c = windowCandidates(w);
     for (var i = 0; i < c.length; i++) {
-      if (forcedSet[c[i]]) return c[i]; // matched key
+      // A field-limited entry wins over a plain one for the same value,
+      // and exact entries win over patterns.
+      var r = forcedSet[c[i].field + ":" + c[i].key] || forcedSet[c[i].key];
+      if (r) return r; // matched rule
+      for (var j = 0; j < forcedPatterns.length; j++) {
+        var p = forcedPatterns[j];
+        if ((!p.field || p.field === c[i].field) && p.re.test(c[i].key)) return p.rule;
+      }
     }
-    return "";
+    return null;
   }
 
   function isDeleted(w) {
@@ -136,6 +382,17 @@
     return false;
   }
 
+  function ruleAllows(rule, w) {
+    // "normal" / "dialogs" restrict a rule to one window type.
+    var dialog = false;
+    var normal = false;
+    try { dialog = !!w.dialog; } catch (_) {}
+    try { normal = !!w.normalWindow; } catch (_) {}
+    if (rule.only === "dialogs") return dialog;
+    if (rule.only === "normal") return normal;
+    return true;
+  }
+
   function isAlreadyActive(w) {
     try { return workspace.activeWindow === w; } catch (_) { return false; }
   }
@@ -193,22 +450,33 @@
     if (!w) return;
     if (isDeleted(w)) return;
 
-    var matched = matchForced(w);
-    if (!matched) return;
+    var rule = matchForced(w);
+    if (!rule) return;
 
-    if (!isEligibleWindow(w)) return;
-    if (isMinimized(w)) return;
+    if (!isEligibleWindow(w)) {
+      log("skip (not eligible): " + rule.key + " (" + why + ")");
+      return;
+    }
+    if (isMinimized(w)) {
+      log("skip (minimized): " + rule.key + " (" + why + ")");
+      return;
+    }
+    if (!ruleAllows(rule, w)) {
+      log("skip (" + rule.only + " only): " + rule.key + " (" + why + ")");
+      return;
+    }
 
     // Don’t fight the user if it’s already active.
     if (isAlreadyActive(w)) {
-      log("skip (already active): " + matched + " (" + why + ")");
+      log("skip (already active): " + rule.key + " (" + why + ")");
       return;
     }
 
     // Perform action
-    log("apply " + mode + ": class=" + matched + " (" + why + ")");
+    var m = rule.mode || mode;
+    log("apply " + m + ": class=" + rule.key + " (" + why + ")");
     doRaise(w);
-    if (mode === "activate") doActivate(w);
+    if (m === "activate") doActivate(w);
   }
 
   function forceSoon(w, why) {
@@ -246,7 +514,8 @@
   function onWindowActivated(w) {
     // Raise forced window if it’s being activated (no focus fight).
     if (!w) return;
-    if (!matchForced(w)) return;
+    var rule = matchForced(w);
+    if (!rule || !ruleAllows(rule, w)) return;
 
     // Only stacking correction, activation already happened.
     doRaise(w);
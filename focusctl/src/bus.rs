@@ -0,0 +1,219 @@
+// -------------------------------
+// The target's session bus (root-friendly)
+// -------------------------------
+// Finding the target user's session bus, connecting to it as that user, and
+// the KWin calls focusctl makes there: reconfigure, window listing and the
+// interactive window picker.
+
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use crate::creds;
+use crate::dbus::Connection;
+use crate::kwin::{self, KWIN_SERVICE, WindowInfo};
+use crate::session;
+use crate::target::{Target, current_uid};
+
+/// qdbus binaries tried (in order) when the native call fails.
+pub const QDBUS_PROGRAMS: &[&str] = &["qdbus6", "qdbus-qt6", "qdbus-qt5", "qdbus"];
+
+/// How a reconfigure request reached KWin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reconfigured {
+    /// Our own D-Bus connection.
+    DBus,
+    /// The named qdbus fallback.
+    Qdbus(&'static str),
+}
+
+pub fn have_cmd(name: &str) -> bool {
+    Command::new("sh")
+        .arg("-lc")
+        .arg(format!("command -v {} >/dev/null 2>&1", name))
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/// Find active graphical session env for a target uid:
+/// returns (XDG_RUNTIME_DIR, DBUS_SESSION_BUS_ADDRESS)
+pub fn detect_session_env_for_uid(uid: u32) -> io::Result<Option<(String, String)>> {
+    Ok(session::active_graphical(Some(uid))?
+        .map(|s| (s.runtime_dir.display().to_string(), s.bus_address())))
+}
+
+fn run_as_target(target: &Target, mut cmd: Command) -> io::Result<std::process::ExitStatus> {
+    let self_uid = current_uid();
+    if self_uid == 0 && target.uid != 0 && have_cmd("sudo") {
+        let mut sudo = Command::new("sudo");
+        sudo.arg("-u").arg(format!("#{}", target.uid)).arg("-H");
+        let prog = cmd.get_program().to_os_string();
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_os_string()).collect();
+        sudo.arg(prog);
+        for a in args {
+            sudo.arg(a);
+        }
+        sudo.stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+        return sudo.status();
+    }
+
+    cmd.stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+    cmd.status()
+}
+
+/// Session bus address for the target:
+/// `DBUS_SESSION_BUS_ADDRESS` (own session), the logind session env,
+/// then `$XDG_RUNTIME_DIR/bus` (or `/run/user/<uid>/bus`).
+pub fn session_bus_address(target: &Target, session_env: Option<&(String, String)>) -> String {
+    let own = target.uid == current_uid();
+    if own
+        && let Ok(a) = env::var("DBUS_SESSION_BUS_ADDRESS")
+        && !a.is_empty()
+    {
+        return a;
+    }
+
+    if let Some((xdg, dbus)) = session_env {
+        if !dbus.is_empty() {
+            return dbus.clone();
+        }
+        if !xdg.is_empty() {
+            return format!("unix:path={}/bus", xdg);
+        }
+    }
+
+    let runtime = match env::var("XDG_RUNTIME_DIR") {
+        Ok(d) if own && !d.is_empty() => d,
+        _ => format!("/run/user/{}", target.uid),
+    };
+    format!("unix:path={}/bus", runtime)
+}
+
+/// Connect to the target's session bus, authenticated as the target uid.
+pub fn open_session_bus(
+    target: &Target,
+    session_env: Option<&(String, String)>,
+) -> io::Result<Connection> {
+    let address = session_bus_address(target, session_env);
+    creds::with_euid(target.uid, || {
+        Connection::open_address(&address, target.uid)
+    })?
+}
+
+/// Where to drop temporary KWin scripts: somewhere the target's KWin can read.
+fn script_dir_for(target: &Target, session_env: Option<&(String, String)>) -> PathBuf {
+    if let Some((xdg, _)) = session_env
+        && !xdg.is_empty()
+    {
+        return PathBuf::from(xdg);
+    }
+    if target.uid == current_uid()
+        && let Some(d) = env::var_os("XDG_RUNTIME_DIR")
+    {
+        return PathBuf::from(d);
+    }
+    env::temp_dir()
+}
+
+/// All windows the target's KWin manages.
+pub fn list_kwin_windows(target: &Target) -> io::Result<Vec<WindowInfo>> {
+    let session_env = detect_session_env_for_uid(target.uid).ok().flatten();
+    let mut bus = open_session_bus(target, session_env.as_ref())?;
+    kwin::list_windows(&mut bus, target, &script_dir_for(target, session_env.as_ref()))
+}
+
+/// Ask for the target's windows every `interval` until `check` returns
+/// `Some`, over one bus connection.
+pub fn watch_kwin_windows<T>(
+    target: &Target,
+    interval: Duration,
+    mut check: impl FnMut(&[WindowInfo]) -> Option<T>,
+) -> io::Result<T> {
+    let session_env = detect_session_env_for_uid(target.uid).ok().flatten();
+    let mut bus = open_session_bus(target, session_env.as_ref())?;
+    let dir = script_dir_for(target, session_env.as_ref());
+    loop {
+        if let Some(out) = check(&kwin::list_windows(&mut bus, target, &dir)?) {
+            return Ok(out);
+        }
+        thread::sleep(interval);
+    }
+}
+
+/// Interactive crosshair pick; `None` if the user cancelled.
+pub fn pick_kwin_window(target: &Target) -> io::Result<Option<WindowInfo>> {
+    let session_env = detect_session_env_for_uid(target.uid).ok().flatten();
+    let mut bus = open_session_bus(target, session_env.as_ref())?;
+    kwin::query_window_info(&mut bus)
+}
+
+/// The calls `reload_kwin_config` would make, in order, for `--dry-run`. Each
+/// qdbus line is only tried if the one before it fails.
+pub fn reload_calls(target: &Target) -> Vec<String> {
+    let session_env = detect_session_env_for_uid(target.uid).ok().flatten();
+    let mut calls = vec![format!(
+        "{} /KWin {}.reconfigure on {} as uid {}",
+        KWIN_SERVICE,
+        KWIN_SERVICE,
+        session_bus_address(target, session_env.as_ref()),
+        target.uid
+    )];
+    let sudo = current_uid() == 0 && target.uid != 0 && have_cmd("sudo");
+    for prog in QDBUS_PROGRAMS.iter().filter(|p| have_cmd(p)) {
+        let cmd = format!("{} {} /KWin reconfigure", prog, KWIN_SERVICE);
+        calls.push(if sudo {
+            format!("fallback: sudo -u #{} -H {}", target.uid, cmd)
+        } else {
+            format!("fallback: {}", cmd)
+        });
+    }
+    calls
+}
+
+/// Ask the target's KWin to reload its configuration: natively first, then
+/// through a qdbus binary (as the target user). The error carries the reason
+/// the native call failed.
+pub fn reload_kwin_config(target: &Target) -> io::Result<Reconfigured> {
+    let session_env = detect_session_env_for_uid(target.uid).ok().flatten();
+
+    let native = open_session_bus(target, session_env.as_ref())
+        .and_then(|mut bus| bus.call(KWIN_SERVICE, "/KWin", KWIN_SERVICE, "reconfigure", vec![]));
+    let native_err = match native {
+        Ok(_) => return Ok(Reconfigured::DBus),
+        Err(e) => e,
+    };
+
+    // Fallback: a qdbus binary, in case the bus needs something we don't speak.
+    for prog in QDBUS_PROGRAMS {
+        if !have_cmd(prog) {
+            continue;
+        }
+
+        let mut c = Command::new(prog);
+        c.args([KWIN_SERVICE, "/KWin", "reconfigure"]);
+
+        if let Some((ref xdg, ref dbus)) = session_env {
+            c.env("XDG_RUNTIME_DIR", xdg);
+            c.env("DBUS_SESSION_BUS_ADDRESS", dbus);
+        }
+
+        if let Ok(st) = run_as_target(target, c)
+            && st.success()
+        {
+            return Ok(Reconfigured::Qdbus(prog));
+        }
+    }
+
+    Err(io::Error::new(
+        native_err.kind(),
+        format!("could not reach KWin over D-Bus ({})", native_err),
+    ))
+}
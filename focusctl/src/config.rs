@@ -0,0 +1,766 @@
+// -------------------------------
+// KWin config operations
+// -------------------------------
+// Reading and editing the target's kwinrc: the forced-focus class list, the
+// per-class rules and other keys in `[Script-kwin-focus-helper]`, and the `[Plugins]` enabled
+// flag. Every edit is a locked, conflict-checked read-modify-write
+// (`update_document`). Nothing here talks to KWin; call
+// `bus::reload_kwin_config` after writing so a running KWin picks the change up.
+
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::dryrun;
+use crate::fileops;
+use crate::history;
+use crate::kconfig::Document;
+use crate::kwin::WindowInfo;
+use crate::pattern::{self, Regex};
+use crate::rules::{self, Rule, join_rules, parse_rules};
+use crate::session::{self, SessionEnv};
+use crate::target::{self, Target};
+
+/// KWin script plugin id.
+pub const SCRIPT_ID: &str = "kwin-focus-helper";
+/// Group holding the script's settings.
+pub const GROUP_NAME: &str = "Script-kwin-focus-helper";
+/// Key holding the forced-focus class list.
+pub const KEY_NAME: &str = "forceFocusClasses";
+/// Key holding per-class rules (`class[:mode][:only]`).
+pub const RULES_KEY: &str = "forceFocusRules";
+/// Group holding `<plugin>Enabled` flags.
+pub const PLUGINS_GROUP: &str = "Plugins";
+
+// -------------------------------
+// Path + IO
+// -------------------------------
+
+/// The target's config directory and where it was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigHome {
+    pub dir: PathBuf,
+    /// `$XDG_CONFIG_HOME`, `session: plasmashell pid 1460`, `default`.
+    pub source: String,
+}
+
+/// The target's XDG config directory, the one their KWin reads kwinrc from:
+/// `$XDG_CONFIG_HOME` when the target is the current user and it is set,
+/// else XDG_CONFIG_HOME from their running session (see
+/// `session::environment`), else `~/.config`. Resolved once per target.
+pub fn config_home_for(target: &Target) -> ConfigHome {
+    static CACHE: Mutex<Vec<(u32, PathBuf, ConfigHome)>> = Mutex::new(Vec::new());
+
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, _, home)) = cache
+        .iter()
+        .find(|(uid, home, _)| *uid == target.uid && *home == target.home)
+    {
+        return home.clone();
+    }
+    let resolved = resolve_config_home(target);
+    cache.push((target.uid, target.home.clone(), resolved.clone()));
+    resolved
+}
+
+fn resolve_config_home(target: &Target) -> ConfigHome {
+    let me = target::current_target();
+    if me.uid == target.uid
+        && me.home == target.home
+        && let Some(dir) = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        && dir.is_absolute()
+    {
+        return ConfigHome {
+            dir,
+            source: "$XDG_CONFIG_HOME".to_string(),
+        };
+    }
+    if let Some(env) = session::environment(target.uid, &target.user)
+        && let Some(dir) = session_config_home(&target.home, &env)
+    {
+        return ConfigHome {
+            dir,
+            source: format!("session: {}", env.source),
+        };
+    }
+    ConfigHome {
+        dir: target.home.join(".config"),
+        source: "default".to_string(),
+    }
+}
+
+/// XDG_CONFIG_HOME from a session environment. Relative values are invalid
+/// per the XDG spec, and a session with another HOME is not this target's.
+fn session_config_home(home: &Path, env: &SessionEnv) -> Option<PathBuf> {
+    if env.get("HOME").is_some_and(|h| Path::new(h) != home) {
+        return None;
+    }
+    let dir = PathBuf::from(env.get("XDG_CONFIG_HOME")?);
+    dir.is_absolute().then_some(dir)
+}
+
+/// The target's config directory (kwinrc and focusctl's own files).
+pub fn config_dir_for(target: &Target) -> PathBuf {
+    config_home_for(target).dir
+}
+
+pub fn config_path_for(target: &Target) -> PathBuf {
+    config_dir_for(target).join("kwinrc")
+}
+
+fn read_kwinrc(target: &Target) -> io::Result<String> {
+    fileops::as_target(target, || fileops::read(&config_path_for(target)))
+}
+
+/// The target's kwinrc; a missing file reads as empty.
+pub fn read_document(target: &Target) -> io::Result<Document> {
+    match read_kwinrc(target) {
+        Ok(text) => Ok(Document::parse(&text)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Document::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Locked read-modify-write of kwinrc (see `fileops::update`). `edit`
+/// changes the document and returns whether it did; it runs again on the
+/// new contents if KWin saves the file in between. The helper settings it
+/// replaces are saved to the undo history (best-effort: a history that cannot
+/// be written does not block the edit). Returns whether the file changed.
+pub fn update_document(
+    target: &Target,
+    edit: impl FnMut(&mut Document) -> io::Result<bool>,
+) -> io::Result<bool> {
+    update_kwinrc(target, true, edit)
+}
+
+/// `update_document` without a history entry (for `history::undo` and
+/// ephemeral wrap grants).
+pub(crate) fn update_document_untracked(
+    target: &Target,
+    edit: impl FnMut(&mut Document) -> io::Result<bool>,
+) -> io::Result<bool> {
+    update_kwinrc(target, false, edit)
+}
+
+fn update_kwinrc(
+    target: &Target,
+    track: bool,
+    mut edit: impl FnMut(&mut Document) -> io::Result<bool>,
+) -> io::Result<bool> {
+    fileops::as_target(target, || {
+        let mut edited = None;
+        let changed = fileops::update(&config_path_for(target), |text| {
+            let before = Document::parse(text.unwrap_or(""));
+            let mut doc = before.clone();
+            if !edit(&mut doc)? {
+                return Ok(None);
+            }
+            let out = doc.to_string();
+            edited = Some((before, doc));
+            Ok(Some(out))
+        })?;
+        // A dry run leaves nothing to undo.
+        if changed
+            && track
+            && !dryrun::enabled()
+            && let Some((before, after)) = &edited
+        {
+            let _ = history::record(target, before, after);
+        }
+        Ok(changed)
+    })
+}
+
+/// Replace kwinrc with `doc` (locked, recorded in the history).
+pub fn write_document(target: &Target, doc: &Document) -> io::Result<()> {
+    update_document(target, |d| {
+        *d = doc.clone();
+        Ok(true)
+    })?;
+    Ok(())
+}
+
+// -------------------------------
+// Parsing + normalization
+// -------------------------------
+
+/// Window properties an entry can be limited to with a `field:` prefix:
+/// (short name used in match keys, accepted prefixes, script property).
+pub const MATCH_FIELDS: &[(&str, &[&str], &str)] = &[
+    (
+        "desktop",
+        &["desktop", "desktopfile", "desktopfilename"],
+        "desktopFileName",
+    ),
+    ("class", &["class", "resourceclass"], "resourceClass"),
+    ("name", &["name", "resourcename"], "resourceName"),
+];
+
+/// Same normalization as `normClass()` in the script.
+fn norm_class(s: &str) -> String {
+    let lower = s.trim().to_lowercase();
+    let lower = lower.strip_suffix(".desktop").unwrap_or(&lower);
+    lower.to_string()
+}
+
+/// Short field name for a `field:` prefix (case-insensitive).
+fn field_for_prefix(prefix: &str) -> Option<&'static str> {
+    let p = prefix.trim().to_lowercase();
+    MATCH_FIELDS
+        .iter()
+        .find(|(_, aliases, _)| aliases.contains(&p.as_str()))
+        .map(|(short, _, _)| *short)
+}
+
+/// Short field name for a window property (`resourceClass` -> `class`).
+pub fn field_for_property(property: &str) -> Option<&'static str> {
+    MATCH_FIELDS
+        .iter()
+        .find(|(_, _, prop)| *prop == property)
+        .map(|(short, _, _)| *short)
+}
+
+/// Match key of a class value (no field prefix): the normalized class, or
+/// for a glob / `/regex/` the compiled pattern, `/^...$/`.
+fn value_key(entry: &str, value: &str) -> Result<String, String> {
+    let v = value.trim();
+    if pattern::is_regex(v) {
+        let body = v
+            .strip_prefix('/')
+            .and_then(|b| b.strip_suffix('/'))
+            .filter(|b| !b.is_empty())
+            .ok_or_else(|| format!("'{}': a regex is written /pattern/", entry))?;
+        Regex::parse(body)?;
+        return Ok(format!(
+            "/^{}$/",
+            pattern::lowercase_source(pattern::strip_anchors(body))
+        ));
+    }
+    let v = norm_class(v);
+    if pattern::is_glob(&v) {
+        let re = pattern::glob_to_regex(&v)?;
+        Regex::parse(&re)?;
+        return Ok(format!("/^{}$/", re));
+    }
+    Ok(v)
+}
+
+/// Validate one class-list entry and return its match key.
+pub fn check_class(entry: &str) -> Result<String, String> {
+    let s = entry.trim();
+    // A `/regex/` may itself contain `:` (`(?:...)`).
+    let split = if pattern::is_regex(s) {
+        None
+    } else {
+        s.split_once(':')
+    };
+    let key = match split {
+        None => value_key(s, s)?,
+        Some((prefix, value)) => {
+            let Some(field) = field_for_prefix(prefix) else {
+                return Err(format!(
+                    "'{}': unknown field '{}' (expected: {})",
+                    s,
+                    prefix,
+                    MATCH_FIELDS
+                        .iter()
+                        .map(|(short, _, prop)| format!("{}|{}", short, prop))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            };
+            if value.contains(':') && !pattern::is_regex(value.trim()) {
+                return Err(format!("'{}': more than one field prefix", s));
+            }
+            let v = value_key(s, value)?;
+            if v.is_empty() {
+                return Err(format!("'{}': empty class after '{}:'", s, prefix));
+            }
+            format!("{}:{}", field, v)
+        }
+    };
+    if key.is_empty() {
+        return Err("empty class".to_string());
+    }
+    Ok(key)
+}
+
+/// Match key of a class: trimmed, lowercased, trailing `.desktop` removed
+/// (same as `normClass()` in the script). A field-limited entry keeps its
+/// field in short form (`resourceClass:Foo` -> `class:foo`), globs and
+/// regexes are shown compiled (`proclet-*` -> `/^proclet-.*$/`), and an
+/// entry the script cannot read has an empty key and never matches.
+pub fn class_key(s: &str) -> String {
+    check_class(s).unwrap_or_default()
+}
+
+/// Split a class list on `;`, `,` or whitespace.
+pub fn parse_classes(value: &str) -> Vec<String> {
+    value
+        .split(|c: char| c == ';' || c == ',' || c.is_whitespace())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Like `parse_classes`, but every entry must be readable by the script.
+pub fn check_classes(value: &str) -> Result<Vec<String>, String> {
+    let classes = parse_classes(value);
+    for c in &classes {
+        check_class(c)?;
+    }
+    Ok(classes)
+}
+
+pub fn join_classes(classes: &[String]) -> String {
+    classes.join(";")
+}
+
+/// Split a match key into its field (if limited) and value part.
+fn split_key(key: &str) -> (Option<&str>, &str) {
+    if pattern::is_regex(key) {
+        return (None, key);
+    }
+    match key.split_once(':') {
+        Some((field, v)) => (Some(field), v),
+        None => (None, key),
+    }
+}
+
+fn is_pattern_key(key: &str) -> bool {
+    split_key(key).1.starts_with('/')
+}
+
+/// Whether match key `key` accepts a window whose `property` has the
+/// normalized value `value`.
+pub fn key_matches(key: &str, property: &str, value: &str) -> bool {
+    let (field, v) = split_key(key);
+    if field.is_some() && field != field_for_property(property) {
+        return false;
+    }
+    match v.strip_prefix('/').and_then(|b| b.strip_suffix('/')) {
+        Some(body) => Regex::parse(body).is_ok_and(|re| re.is_match(value)),
+        None => v == value,
+    }
+}
+
+/// The configured key that accepts `w`, with the property it matched on.
+/// Same precedence as `matchForced()` in the script: candidates best-first
+/// (`windowCandidates()`), and for each one an exact field-limited key, an
+/// exact plain key, then the first matching pattern in configured order.
+pub fn match_key<'a>(w: &WindowInfo, keys: &'a [String]) -> Option<(&'static str, &'a str)> {
+    for (property, v) in w.candidates() {
+        let value = norm_class(v);
+        if value.is_empty() {
+            continue;
+        }
+        let limited = field_for_property(property).map(|f| format!("{}:{}", f, value));
+        let found = keys
+            .iter()
+            .find(|k| Some(k.as_str()) == limited.as_deref())
+            .or_else(|| keys.iter().find(|k| **k == value))
+            .or_else(|| {
+                keys.iter()
+                    .find(|k| is_pattern_key(k) && key_matches(k, property, &value))
+            });
+        if let Some(k) = found {
+            return Some((property, k.as_str()));
+        }
+    }
+    None
+}
+
+/// First candidate field of `w` that a configured key accepts.
+pub fn match_window(w: &WindowInfo, keys: &[String]) -> Option<&'static str> {
+    match_key(w, keys).map(|(property, _)| property)
+}
+
+fn parse_bool(v: &str) -> bool {
+    matches!(
+        v.trim().to_lowercase().as_str(),
+        "true" | "1" | "yes" | "on"
+    )
+}
+
+/// `kwin-focus-helperEnabled`
+pub fn enabled_key() -> String {
+    format!("{}Enabled", SCRIPT_ID)
+}
+
+// -------------------------------
+// Class list
+// -------------------------------
+
+/// Configured classes, stored spelling preserved.
+pub fn get_classes(target: &Target) -> io::Result<Vec<String>> {
+    Ok(doc_classes(&read_document(target)?))
+}
+
+pub(crate) fn doc_classes(doc: &Document) -> Vec<String> {
+    doc.get(GROUP_NAME, KEY_NAME)
+        .map(|v| parse_classes(&v))
+        .unwrap_or_default()
+}
+
+/// Replace the class list. Returns whether the file changed.
+pub fn set_classes(target: &Target, new_classes: &[String]) -> io::Result<bool> {
+    set_script_value(target, KEY_NAME, &join_classes(new_classes))
+}
+
+/// Append `class` unless its match key is already configured (as a class
+/// or a rule). Returns `false` when it was already present; an entry that
+/// `check_class` rejects is an `InvalidInput` error.
+pub fn add_class(target: &Target, class: &str) -> io::Result<bool> {
+    let input = class.trim().to_string();
+    let ikey = check_class(&input).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    update_document(target, |doc| {
+        if doc_effective_rules(doc).iter().any(|r| r.key() == ikey) {
+            return Ok(false);
+        }
+        let mut classes = doc_classes(doc);
+        classes.push(input.clone());
+        doc.set(GROUP_NAME, KEY_NAME, &join_classes(&classes))
+    })
+}
+
+/// Remove every class and rule with the same match key as `class`.
+/// Returns `false` when nothing matched.
+pub fn remove_class(target: &Target, class: &str) -> io::Result<bool> {
+    let tkey = class_key(class);
+    if tkey.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty class"));
+    }
+
+    update_document(target, |doc| {
+        let mut changed = false;
+
+        let mut classes = doc_classes(doc);
+        let before = classes.len();
+        classes.retain(|c| class_key(c) != tkey);
+        if classes.len() != before {
+            changed |= doc.set(GROUP_NAME, KEY_NAME, &join_classes(&classes))?;
+        }
+
+        let mut rules = doc_rules(doc);
+        let before = rules.len();
+        rules.retain(|r| r.key() != tkey);
+        if rules.len() != before {
+            changed |= doc.set(GROUP_NAME, RULES_KEY, &join_rules(&rules))?;
+        }
+        Ok(changed)
+    })
+}
+
+/// Empty the class list and drop every rule in one write. Returns whether
+/// the file changed.
+pub fn clear_classes(target: &Target) -> io::Result<bool> {
+    update_document(target, |doc| {
+        let changed = doc.set(GROUP_NAME, KEY_NAME, "")?;
+        Ok(doc.remove(GROUP_NAME, RULES_KEY)? | changed)
+    })
+}
+
+// -------------------------------
+// Per-class rules
+// -------------------------------
+
+/// Configured rules; entries the script would skip are left out.
+pub fn get_rules(target: &Target) -> io::Result<Vec<Rule>> {
+    Ok(doc_rules(&read_document(target)?))
+}
+
+pub(crate) fn doc_rules(doc: &Document) -> Vec<Rule> {
+    doc.get(GROUP_NAME, RULES_KEY)
+        .map(|v| parse_rules(&v))
+        .unwrap_or_default()
+}
+
+/// Rules plus plain classes, the way the script combines them.
+pub fn effective_rules(target: &Target) -> io::Result<Vec<Rule>> {
+    Ok(doc_effective_rules(&read_document(target)?))
+}
+
+pub(crate) fn doc_effective_rules(doc: &Document) -> Vec<Rule> {
+    rules::effective(&doc_rules(doc), &doc_classes(doc))
+}
+
+/// Store `rule`, replacing any rule for the same match key and dropping that
+/// key from the plain class list. Returns whether the file changed.
+pub fn add_rule(target: &Target, rule: &Rule) -> io::Result<bool> {
+    let rkey =
+        check_class(&rule.class).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    update_document(target, |doc| {
+        let mut rules = doc_rules(doc);
+        match rules.iter_mut().find(|r| r.key() == rkey) {
+            Some(r) => *r = rule.clone(),
+            None => rules.push(rule.clone()),
+        }
+        let mut changed = doc.set(GROUP_NAME, RULES_KEY, &join_rules(&rules))?;
+
+        let mut classes = doc_classes(doc);
+        let before = classes.len();
+        classes.retain(|c| class_key(c) != rkey);
+        if classes.len() != before {
+            changed |= doc.set(GROUP_NAME, KEY_NAME, &join_classes(&classes))?;
+        }
+        Ok(changed)
+    })
+}
+
+// -------------------------------
+// Script settings + enabled flag
+// -------------------------------
+
+/// Raw stored value of a key in `[Script-kwin-focus-helper]`.
+pub fn get_script_value(target: &Target, key: &str) -> io::Result<Option<String>> {
+    let doc = read_document(target)?;
+    Ok(doc.get(GROUP_NAME, key))
+}
+
+/// Returns whether the file changed.
+pub fn set_script_value(target: &Target, key: &str, value: &str) -> io::Result<bool> {
+    update_document(target, |doc| doc.set(GROUP_NAME, key, value))
+}
+
+/// Returns whether the key was present.
+pub fn unset_script_value(target: &Target, key: &str) -> io::Result<bool> {
+    update_document(target, |doc| doc.remove(GROUP_NAME, key))
+}
+
+/// `[Plugins] kwin-focus-helperEnabled`, or `None` when unset.
+pub fn get_enabled(target: &Target) -> io::Result<Option<bool>> {
+    let doc = read_document(target)?;
+    Ok(doc
+        .get(PLUGINS_GROUP, &enabled_key())
+        .map(|v| parse_bool(&v)))
+}
+
+/// Returns whether the file changed.
+pub fn set_enabled(target: &Target, enabled: bool) -> io::Result<bool> {
+    let value = if enabled { "true" } else { "false" };
+    update_document(target, |doc| doc.set(PLUGINS_GROUP, &enabled_key(), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_target(tag: &str) -> Target {
+        let home =
+            std::env::temp_dir().join(format!("focusctl-config-{}-{}", tag, std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        fs::create_dir_all(home.join(".config")).unwrap();
+        Target {
+            uid: 0,
+            user: "test".into(),
+            home,
+        }
+    }
+
+    #[test]
+    fn session_config_home_must_be_absolute_and_ours() {
+        let env = |vars: &[(&str, &str)]| SessionEnv {
+            source: "plasmashell pid 1".to_string(),
+            vars: vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        };
+        let home = Path::new("/home/darko");
+        assert_eq!(
+            session_config_home(
+                home,
+                &env(&[
+                    ("HOME", "/home/darko"),
+                    ("XDG_CONFIG_HOME", "/home/darko/.cfg")
+                ])
+            ),
+            Some(PathBuf::from("/home/darko/.cfg"))
+        );
+        assert_eq!(
+            session_config_home(home, &env(&[("XDG_CONFIG_HOME", "/srv/cfg")])),
+            Some(PathBuf::from("/srv/cfg"))
+        );
+        assert_eq!(
+            session_config_home(home, &env(&[("XDG_CONFIG_HOME", "cfg")])),
+            None
+        );
+        assert_eq!(
+            session_config_home(home, &env(&[("HOME", "/home/darko")])),
+            None
+        );
+        assert_eq!(
+            session_config_home(
+                home,
+                &env(&[("HOME", "/root"), ("XDG_CONFIG_HOME", "/root/c")])
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn class_keys_follow_norm_class() {
+        assert_eq!(class_key("  Google-Chrome.desktop "), "google-chrome");
+        assert_eq!(class_key("ProcletChrome"), "procletchrome");
+        assert_eq!(class_key("   "), "");
+        assert_eq!(parse_classes("a;b, c\td;;"), vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn field_prefixes_limit_the_match() {
+        assert_eq!(
+            class_key("resourceClass:ProcletChrome"),
+            "class:procletchrome"
+        );
+        assert_eq!(
+            class_key("Desktop:org.mozilla.firefox.desktop"),
+            "desktop:org.mozilla.firefox"
+        );
+        assert_eq!(class_key("bogus:x"), "");
+        assert!(check_class("name:").is_err());
+        assert!(check_class("class:a:b").is_err());
+        assert!(check_classes("chrome;foo:bar").is_err());
+
+        let w = WindowInfo {
+            resource_class: "ProcletChrome".into(),
+            resource_name: "chromium".into(),
+            ..Default::default()
+        };
+        let keys = |v: &[&str]| v.iter().map(|s| class_key(s)).collect::<Vec<_>>();
+        assert_eq!(match_window(&w, &keys(&["class:chromium"])), None);
+        assert_eq!(
+            match_window(&w, &keys(&["name:chromium"])),
+            Some("resourceName")
+        );
+        assert_eq!(
+            match_window(
+                &w,
+                &keys(&["chromium", "resourceClass:proclet-x", "class:proclETchrome"])
+            ),
+            Some("resourceClass")
+        );
+    }
+
+    #[test]
+    fn globs_and_regexes_compile_and_match() {
+        assert_eq!(class_key("Proclet-*"), "/^proclet-.*$/");
+        assert_eq!(
+            class_key("class:/^Proclet-(Work|Home)$/"),
+            "class:/^proclet-(work|home)$/"
+        );
+        assert_eq!(class_key("org.kde.*.desktop"), "/^org\\.kde\\..*$/");
+        assert!(check_class("/a{2}/").is_err());
+        assert!(check_class("/unclosed").is_err());
+        assert!(check_class("a[b").is_err());
+
+        let w = WindowInfo {
+            desktop_file: "proclet-chrome-work".into(),
+            resource_class: "Proclet-Chrome-Work".into(),
+            ..Default::default()
+        };
+        let keys: Vec<String> = [
+            "class:/proclet-.*-work/",
+            "proclet-*",
+            "proclet-chrome-work",
+        ]
+        .iter()
+        .map(|s| class_key(s))
+        .collect();
+        // Exact beats pattern; earlier candidates beat later ones.
+        assert_eq!(
+            match_key(&w, &keys),
+            Some(("desktopFileName", "proclet-chrome-work"))
+        );
+        assert_eq!(
+            match_key(&w, &keys[..2]),
+            Some(("desktopFileName", "/^proclet-.*$/"))
+        );
+        assert_eq!(
+            match_key(&w, &keys[..1]),
+            Some(("resourceClass", "class:/^proclet-.*-work$/"))
+        );
+    }
+
+    #[test]
+    fn add_and_remove_preserve_spelling_and_dedupe() {
+        let t = temp_target("classes");
+        assert!(add_class(&t, "ProcletChrome").unwrap());
+        assert!(!add_class(&t, "procletchrome").unwrap());
+        assert!(add_class(&t, "firefox.desktop").unwrap());
+        assert_eq!(
+            get_classes(&t).unwrap(),
+            vec!["ProcletChrome", "firefox.desktop"]
+        );
+        assert_eq!(
+            add_class(&t, " ").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        assert!(remove_class(&t, "FIREFOX").unwrap());
+        assert!(!remove_class(&t, "firefox").unwrap());
+        assert_eq!(get_classes(&t).unwrap(), vec!["ProcletChrome"]);
+
+        assert_eq!(get_enabled(&t).unwrap(), None);
+        assert!(set_enabled(&t, true).unwrap());
+        assert!(!set_enabled(&t, true).unwrap());
+        assert_eq!(get_enabled(&t).unwrap(), Some(true));
+
+        let _ = fs::remove_dir_all(&t.home);
+    }
+
+    #[test]
+    fn rules_replace_plain_classes_with_the_same_key() {
+        let t = temp_target("rules");
+        assert!(add_class(&t, "Chrome").unwrap());
+        assert!(add_class(&t, "firefox").unwrap());
+
+        let rule = rules::parse_rule("chrome.desktop:raise").unwrap();
+        assert!(add_rule(&t, &rule).unwrap());
+        assert!(!add_rule(&t, &rule).unwrap());
+        assert_eq!(get_classes(&t).unwrap(), vec!["firefox"]);
+        assert!(!add_class(&t, "chrome").unwrap());
+
+        let dialogs = rules::parse_rule("Chrome:dialogs").unwrap();
+        assert!(add_rule(&t, &dialogs).unwrap());
+        assert_eq!(
+            join_rules(&effective_rules(&t).unwrap()),
+            "Chrome:dialogs;firefox"
+        );
+
+        assert!(remove_class(&t, "chrome").unwrap());
+        assert!(get_rules(&t).unwrap().is_empty());
+        assert_eq!(get_classes(&t).unwrap(), vec!["firefox"]);
+
+        let _ = fs::remove_dir_all(&t.home);
+    }
+
+    #[test]
+    fn parallel_add_class_calls_are_not_lost() {
+        let t = temp_target("parallel");
+        let n = 32;
+        let handles: Vec<_> = (0..n)
+            .map(|i| {
+                let t = t.clone();
+                std::thread::spawn(move || {
+                    assert!(add_class(&t, &format!("App{}", i)).unwrap());
+                    set_enabled(&t, i % 2 == 0).unwrap();
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        let mut classes = get_classes(&t).unwrap();
+        classes.sort_by_key(|c| c[3..].parse::<u32>().unwrap());
+        let want: Vec<String> = (0..n).map(|i| format!("App{}", i)).collect();
+        assert_eq!(classes, want);
+        assert!(get_enabled(&t).unwrap().is_some());
+
+        let _ = fs::remove_dir_all(&t.home);
+    }
+}
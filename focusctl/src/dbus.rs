@@ -0,0 +1,970 @@
+// -------------------------------
+// Minimal D-Bus client (no deps)
+// -------------------------------
+// Just enough of the D-Bus wire protocol to talk to KWin without qdbus:
+//
+// - unix socket transport (`unix:path=`, `unix:abstract=`)
+// - SASL EXTERNAL authentication
+// - little-endian marshalling of the basic/container types we need
+// - method calls, replies, errors (signals are read and dropped)
+//
+// Everything is synchronous; each call blocks until its reply arrives or the
+// read timeout expires.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+pub const BUS_NAME: &str = "org.freedesktop.DBus";
+pub const BUS_PATH: &str = "/org/freedesktop/DBus";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(25);
+
+// -------------------------------
+// Values
+// -------------------------------
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Bool(bool),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    Double(f64),
+    Str(String),
+    ObjectPath(String),
+    Signature(String),
+    Variant(Box<Value>),
+    /// Element signature + elements (needed for empty arrays).
+    Array(String, Vec<Value>),
+    Struct(Vec<Value>),
+    DictEntry(Box<Value>, Box<Value>),
+}
+
+impl Value {
+    pub fn signature(&self) -> String {
+        match self {
+            Value::Byte(_) => "y".into(),
+            Value::Bool(_) => "b".into(),
+            Value::I16(_) => "n".into(),
+            Value::U16(_) => "q".into(),
+            Value::I32(_) => "i".into(),
+            Value::U32(_) => "u".into(),
+            Value::I64(_) => "x".into(),
+            Value::U64(_) => "t".into(),
+            Value::Double(_) => "d".into(),
+            Value::Str(_) => "s".into(),
+            Value::ObjectPath(_) => "o".into(),
+            Value::Signature(_) => "g".into(),
+            Value::Variant(_) => "v".into(),
+            Value::Array(elem, _) => format!("a{}", elem),
+            Value::Struct(fields) => {
+                let inner: String = fields.iter().map(|f| f.signature()).collect();
+                format!("({})", inner)
+            }
+            Value::DictEntry(k, v) => format!("{{{}{}}}", k.signature(), v.signature()),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) | Value::ObjectPath(s) | Value::Signature(s) => Some(s),
+            Value::Variant(v) => v.as_str(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            Value::Variant(v) => v.as_bool(),
+            _ => None,
+        }
+    }
+
+    pub fn as_i32(&self) -> Option<i32> {
+        match self {
+            Value::I32(x) => Some(*x),
+            Value::Variant(v) => v.as_i32(),
+            _ => None,
+        }
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            Value::U32(x) => Some(*x),
+            Value::Variant(v) => v.as_u32(),
+            _ => None,
+        }
+    }
+}
+
+/// Longest message the spec allows (128 MiB); anything bigger is refused
+/// before its buffer is allocated.
+const MAX_MESSAGE_LEN: usize = 128 * 1024 * 1024;
+
+/// Total length of the message starting with `head`, from the header and
+/// body lengths the peer sent.
+fn message_len(head: &[u8; 16]) -> io::Result<usize> {
+    if head[0] != b'l' {
+        return Err(proto_err("only little-endian messages are supported"));
+    }
+    let body_len = u32::from_le_bytes(head[4..8].try_into().unwrap()) as usize;
+    let fields_len = u32::from_le_bytes(head[12..16].try_into().unwrap()) as usize;
+    let len = (16 + fields_len).div_ceil(8) * 8 + body_len;
+    if len > MAX_MESSAGE_LEN {
+        return Err(proto_err("message too large"));
+    }
+    Ok(len)
+}
+
+/// Natural alignment of the first complete type in `sig`.
+fn align_of(sig: &str) -> usize {
+    match sig.as_bytes().first() {
+        Some(b'y') | Some(b'g') | Some(b'v') => 1,
+        Some(b'n') | Some(b'q') => 2,
+        Some(b'b') | Some(b'i') | Some(b'u') | Some(b's') | Some(b'o') | Some(b'a')
+        | Some(b'h') => 4,
+        Some(b'x') | Some(b't') | Some(b'd') | Some(b'(') | Some(b'{') => 8,
+        _ => 1,
+    }
+}
+
+/// Split off the first complete type: "a{sv}s" -> ("a{sv}", "s").
+fn split_type(sig: &str) -> io::Result<(&str, &str)> {
+    let b = sig.as_bytes();
+    let mut i = 0usize;
+    // Leading array markers.
+    while i < b.len() && b[i] == b'a' {
+        i += 1;
+    }
+    match b.get(i) {
+        Some(b'(') | Some(b'{') => {
+            let open = i;
+            let mut depth = 0i32;
+            while i < b.len() {
+                match b[i] {
+                    b'(' | b'{' => depth += 1,
+                    b')' | b'}' => {
+                        depth -= 1;
+                        if depth == 0 && i == open + 1 {
+                            // Empty containers read nothing: `a()` would never end.
+                            return Err(proto_err(&format!("empty container: {}", sig)));
+                        }
+                        if depth == 0 {
+                            return Ok((&sig[..=i], &sig[i + 1..]));
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+            Err(proto_err(&format!("unbalanced signature: {}", sig)))
+        }
+        Some(_) => Ok((&sig[..=i], &sig[i + 1..])),
+        None => Err(proto_err(&format!("incomplete signature: {}", sig))),
+    }
+}
+
+fn split_all(mut sig: &str) -> io::Result<Vec<&str>> {
+    let mut out = Vec::new();
+    while !sig.is_empty() {
+        let (t, rest) = split_type(sig)?;
+        out.push(t);
+        sig = rest;
+    }
+    Ok(out)
+}
+
+fn proto_err(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("dbus: {}", msg))
+}
+
+// -------------------------------
+// Marshalling
+// -------------------------------
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn pad(&mut self, align: usize) {
+        while !self.buf.len().is_multiple_of(align) {
+            self.buf.push(0);
+        }
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.pad(4);
+        self.buf.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, s: &str) {
+        self.buf.push(s.len() as u8);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn value(&mut self, v: &Value) {
+        match v {
+            Value::Byte(x) => self.buf.push(*x),
+            Value::Bool(x) => self.u32(*x as u32),
+            Value::I16(x) => {
+                self.pad(2);
+                self.buf.extend_from_slice(&x.to_le_bytes());
+            }
+            Value::U16(x) => {
+                self.pad(2);
+                self.buf.extend_from_slice(&x.to_le_bytes());
+            }
+            Value::I32(x) => {
+                self.pad(4);
+                self.buf.extend_from_slice(&x.to_le_bytes());
+            }
+            Value::U32(x) => self.u32(*x),
+            Value::I64(x) => {
+                self.pad(8);
+                self.buf.extend_from_slice(&x.to_le_bytes());
+            }
+            Value::U64(x) => {
+                self.pad(8);
+                self.buf.extend_from_slice(&x.to_le_bytes());
+            }
+            Value::Double(x) => {
+                self.pad(8);
+                self.buf.extend_from_slice(&x.to_le_bytes());
+            }
+            Value::Str(s) | Value::ObjectPath(s) => self.string(s),
+            Value::Signature(s) => self.signature(s),
+            Value::Variant(inner) => {
+                self.signature(&inner.signature());
+                self.value(inner);
+            }
+            Value::Array(elem, items) => {
+                self.pad(4);
+                let len_at = self.buf.len();
+                self.buf.extend_from_slice(&[0; 4]);
+                self.pad(align_of(elem));
+                let start = self.buf.len();
+                for it in items {
+                    self.value(it);
+                }
+                let len = (self.buf.len() - start) as u32;
+                self.buf[len_at..len_at + 4].copy_from_slice(&len.to_le_bytes());
+            }
+            Value::Struct(fields) => {
+                self.pad(8);
+                for f in fields {
+                    self.value(f);
+                }
+            }
+            Value::DictEntry(k, v) => {
+                self.pad(8);
+                self.value(k);
+                self.value(v);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn align(&mut self, a: usize) -> io::Result<()> {
+        let p = self.pos.div_ceil(a) * a;
+        if p > self.buf.len() {
+            return Err(proto_err("short message"));
+        }
+        self.pos = p;
+        Ok(())
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.pos + n > self.buf.len() {
+            return Err(proto_err("short message"));
+        }
+        let s = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(s)
+    }
+
+    fn fixed<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        self.align(N)?;
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.fixed::<4>()?))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let n = self.u32()? as usize;
+        let s = self.take(n)?;
+        self.take(1)?; // NUL
+        String::from_utf8(s.to_vec()).map_err(|_| proto_err("invalid utf-8"))
+    }
+
+    fn signature(&mut self) -> io::Result<String> {
+        let n = self.take(1)?[0] as usize;
+        let s = self.take(n)?;
+        self.take(1)?;
+        String::from_utf8(s.to_vec()).map_err(|_| proto_err("invalid signature"))
+    }
+
+    /// Read one value of type `sig`, which comes off the wire and must be
+    /// exactly one complete type.
+    fn value(&mut self, sig: &str) -> io::Result<Value> {
+        if !split_type(sig)?.1.is_empty() {
+            return Err(proto_err(&format!("not a single type: {}", sig)));
+        }
+        let b = sig.as_bytes();
+        Ok(match b.first() {
+            Some(b'y') => Value::Byte(self.take(1)?[0]),
+            Some(b'b') => Value::Bool(self.u32()? != 0),
+            Some(b'n') => Value::I16(i16::from_le_bytes(self.fixed::<2>()?)),
+            Some(b'q') => Value::U16(u16::from_le_bytes(self.fixed::<2>()?)),
+            Some(b'i') => Value::I32(i32::from_le_bytes(self.fixed::<4>()?)),
+            Some(b'u') | Some(b'h') => Value::U32(self.u32()?),
+            Some(b'x') => Value::I64(i64::from_le_bytes(self.fixed::<8>()?)),
+            Some(b't') => Value::U64(u64::from_le_bytes(self.fixed::<8>()?)),
+            Some(b'd') => Value::Double(f64::from_le_bytes(self.fixed::<8>()?)),
+            Some(b's') => Value::Str(self.string()?),
+            Some(b'o') => Value::ObjectPath(self.string()?),
+            Some(b'g') => Value::Signature(self.signature()?),
+            Some(b'v') => {
+                let inner = self.signature()?;
+                Value::Variant(Box::new(self.value(&inner)?))
+            }
+            Some(b'a') => {
+                let elem = &sig[1..];
+                let len = self.u32()? as usize;
+                self.align(align_of(elem))?;
+                let end = self.pos + len;
+                if end > self.buf.len() {
+                    return Err(proto_err("array overruns message"));
+                }
+                let mut items = Vec::new();
+                while self.pos < end {
+                    items.push(self.value(elem)?);
+                }
+                Value::Array(elem.to_string(), items)
+            }
+            Some(b'(') => {
+                self.align(8)?;
+                let mut fields = Vec::new();
+                for t in split_all(&sig[1..sig.len() - 1])? {
+                    fields.push(self.value(t)?);
+                }
+                Value::Struct(fields)
+            }
+            Some(b'{') => {
+                self.align(8)?;
+                let inner = split_all(&sig[1..sig.len() - 1])?;
+                if inner.len() != 2 {
+                    return Err(proto_err("bad dict entry"));
+                }
+                let k = self.value(inner[0])?;
+                let v = self.value(inner[1])?;
+                Value::DictEntry(Box::new(k), Box::new(v))
+            }
+            _ => return Err(proto_err(&format!("unsupported type: {}", sig))),
+        })
+    }
+}
+
+// -------------------------------
+// Messages
+// -------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    MethodCall = 1,
+    MethodReturn = 2,
+    Error = 3,
+    Signal = 4,
+}
+
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub kind: Kind,
+    pub serial: u32,
+    pub flags: u8,
+    pub path: Option<String>,
+    pub interface: Option<String>,
+    pub member: Option<String>,
+    pub error_name: Option<String>,
+    pub reply_serial: Option<u32>,
+    pub destination: Option<String>,
+    pub sender: Option<String>,
+    pub body: Vec<Value>,
+}
+
+impl Message {
+    pub fn method_call(dest: &str, path: &str, iface: &str, member: &str, args: Vec<Value>) -> Self {
+        Message {
+            kind: Kind::MethodCall,
+            serial: 0,
+            flags: 0,
+            path: Some(path.to_string()),
+            interface: Some(iface.to_string()),
+            member: Some(member.to_string()),
+            error_name: None,
+            reply_serial: None,
+            destination: Some(dest.to_string()),
+            sender: None,
+            body: args,
+        }
+    }
+
+    /// Reply to an incoming method call.
+    pub fn method_return(call: &Message, body: Vec<Value>) -> Self {
+        Message {
+            kind: Kind::MethodReturn,
+            serial: 0,
+            flags: 0,
+            path: None,
+            interface: None,
+            member: None,
+            error_name: None,
+            reply_serial: Some(call.serial),
+            destination: call.sender.clone(),
+            sender: None,
+            body,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Writer { buf: Vec::new() };
+        for v in &self.body {
+            body.value(v);
+        }
+        let sig: String = self.body.iter().map(|v| v.signature()).collect();
+
+        let mut fields = Vec::new();
+        let mut field = |code: u8, v: Value| {
+            fields.push(Value::Struct(vec![Value::Byte(code), Value::Variant(Box::new(v))]));
+        };
+        if let Some(p) = &self.path {
+            field(1, Value::ObjectPath(p.clone()));
+        }
+        if let Some(i) = &self.interface {
+            field(2, Value::Str(i.clone()));
+        }
+        if let Some(m) = &self.member {
+            field(3, Value::Str(m.clone()));
+        }
+        if let Some(e) = &self.error_name {
+            field(4, Value::Str(e.clone()));
+        }
+        if let Some(r) = self.reply_serial {
+            field(5, Value::U32(r));
+        }
+        if let Some(d) = &self.destination {
+            field(6, Value::Str(d.clone()));
+        }
+        if !sig.is_empty() {
+            field(8, Value::Signature(sig));
+        }
+
+        let mut w = Writer { buf: Vec::new() };
+        w.buf.extend_from_slice(&[b'l', self.kind as u8, self.flags, 1]);
+        w.u32(body.buf.len() as u32);
+        w.u32(self.serial);
+        w.value(&Value::Array("(yv)".to_string(), fields));
+        w.pad(8);
+        w.buf.extend_from_slice(&body.buf);
+        w.buf
+    }
+
+    fn decode(buf: &[u8]) -> io::Result<Message> {
+        if buf.len() < 16 || buf[0] != b'l' {
+            return Err(proto_err("only little-endian messages are supported"));
+        }
+        let kind = match buf[1] {
+            1 => Kind::MethodCall,
+            2 => Kind::MethodReturn,
+            3 => Kind::Error,
+            4 => Kind::Signal,
+            x => return Err(proto_err(&format!("unknown message type {}", x))),
+        };
+
+        let mut r = Reader { buf, pos: 8 };
+        let serial = r.u32()?;
+        let fields = r.value("a(yv)")?;
+
+        let mut m = Message {
+            kind,
+            serial,
+            flags: buf[2],
+            path: None,
+            interface: None,
+            member: None,
+            error_name: None,
+            reply_serial: None,
+            destination: None,
+            sender: None,
+            body: Vec::new(),
+        };
+        let mut sig = String::new();
+
+        if let Value::Array(_, items) = fields {
+            for f in items {
+                let Value::Struct(pair) = f else { continue };
+                let (Some(Value::Byte(code)), Some(v)) = (pair.first(), pair.get(1)) else {
+                    continue;
+                };
+                let s = v.as_str().map(|s| s.to_string());
+                match code {
+                    1 => m.path = s,
+                    2 => m.interface = s,
+                    3 => m.member = s,
+                    4 => m.error_name = s,
+                    5 => m.reply_serial = v.as_u32(),
+                    6 => m.destination = s,
+                    7 => m.sender = s,
+                    8 => sig = s.unwrap_or_default(),
+                    _ => {}
+                }
+            }
+        }
+
+        r.align(8)?;
+        let mut body = Reader {
+            buf: &buf[r.pos..],
+            pos: 0,
+        };
+        for t in split_all(&sig)? {
+            m.body.push(body.value(t)?);
+        }
+        Ok(m)
+    }
+}
+
+// -------------------------------
+// Addresses
+// -------------------------------
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Address {
+    Path(String),
+    Abstract(String),
+}
+
+/// Parse a D-Bus address list (`unix:path=/run/user/1000/bus;...`).
+/// Returns the supported unix transports in order.
+pub fn parse_address(s: &str) -> Vec<Address> {
+    let mut out = Vec::new();
+    for entry in s.split(';') {
+        let Some(params) = entry.trim().strip_prefix("unix:") else {
+            continue;
+        };
+        for kv in params.split(',') {
+            let Some((k, v)) = kv.split_once('=') else {
+                continue;
+            };
+            match k {
+                "path" => out.push(Address::Path(unescape_address(v))),
+                "abstract" => out.push(Address::Abstract(unescape_address(v))),
+                _ => {}
+            }
+        }
+    }
+    out
+}
+
+fn unescape_address(s: &str) -> String {
+    let b = s.as_bytes();
+    let mut out = Vec::with_capacity(b.len());
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'%'
+            && i + 2 < b.len()
+            && let Ok(x) = u8::from_str_radix(&s[i + 1..i + 3], 16)
+        {
+            out.push(x);
+            i += 3;
+            continue;
+        }
+        out.push(b[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// -------------------------------
+// Connection
+// -------------------------------
+
+pub struct Connection {
+    stream: UnixStream,
+    next_serial: u32,
+    unique_name: String,
+    /// Incoming method calls that arrived while we waited for a reply.
+    pending: VecDeque<Message>,
+}
+
+impl Connection {
+    /// Connect, authenticate (EXTERNAL as `uid`) and say Hello.
+    pub fn open(address: &Address, uid: u32) -> io::Result<Connection> {
+        let stream = match address {
+            Address::Path(p) => UnixStream::connect(Path::new(p))?,
+            Address::Abstract(name) => connect_abstract(name)?,
+        };
+        stream.set_read_timeout(Some(DEFAULT_TIMEOUT))?;
+
+        let mut c = Connection {
+            stream,
+            next_serial: 1,
+            unique_name: String::new(),
+            pending: VecDeque::new(),
+        };
+        c.authenticate(uid)?;
+
+        let reply = c.call(BUS_NAME, BUS_PATH, BUS_NAME, "Hello", vec![])?;
+        c.unique_name = reply
+            .first()
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string();
+        Ok(c)
+    }
+
+    /// Try each address of a D-Bus address string in turn.
+    pub fn open_address(address: &str, uid: u32) -> io::Result<Connection> {
+        let mut last = io::Error::new(io::ErrorKind::NotFound, "no usable unix transport");
+        for a in parse_address(address) {
+            match Connection::open(&a, uid) {
+                Ok(c) => return Ok(c),
+                Err(e) => last = e,
+            }
+        }
+        Err(last)
+    }
+
+    pub fn unique_name(&self) -> &str {
+        &self.unique_name
+    }
+
+    pub fn set_timeout(&self, t: Duration) -> io::Result<()> {
+        self.stream.set_read_timeout(Some(t))
+    }
+
+    fn authenticate(&mut self, uid: u32) -> io::Result<()> {
+        // The identity is the uid as a decimal string, hex-encoded.
+        let hex: String = uid.to_string().bytes().map(|b| format!("{:02x}", b)).collect();
+        self.stream.write_all(b"\0")?;
+        self.stream
+            .write_all(format!("AUTH EXTERNAL {}\r\n", hex).as_bytes())?;
+
+        let line = self.read_line()?;
+        if !line.starts_with("OK ") {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("dbus: authentication rejected ({})", line.trim()),
+            ));
+        }
+        self.stream.write_all(b"BEGIN\r\n")?;
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut out = Vec::new();
+        let mut b = [0u8; 1];
+        while !out.ends_with(b"\r\n") {
+            if self.stream.read(&mut b)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "dbus: connection closed during auth",
+                ));
+            }
+            out.push(b[0]);
+        }
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    /// Send a message, assigning it a serial. Returns the serial.
+    pub fn send(&mut self, mut m: Message) -> io::Result<u32> {
+        m.serial = self.next_serial;
+        self.next_serial += 1;
+        self.stream.write_all(&m.encode())?;
+        Ok(m.serial)
+    }
+
+    /// Block until the next message arrives.
+    pub fn read_message(&mut self) -> io::Result<Message> {
+        let mut head = [0u8; 16];
+        self.stream.read_exact(&mut head)?;
+        let mut buf = head.to_vec();
+        buf.resize(message_len(&head)?, 0);
+        self.stream.read_exact(&mut buf[16..])?;
+        Message::decode(&buf)
+    }
+
+    /// Next incoming method call (queued ones first). Honors the read timeout.
+    pub fn next_call(&mut self) -> io::Result<Message> {
+        if let Some(m) = self.pending.pop_front() {
+            return Ok(m);
+        }
+        loop {
+            let m = self.read_message()?;
+            if m.kind == Kind::MethodCall {
+                return Ok(m);
+            }
+        }
+    }
+
+    /// Call a method and wait for its reply body.
+    /// Incoming method calls received meanwhile are queued for `next_call`;
+    /// signals are dropped.
+    pub fn call(
+        &mut self,
+        dest: &str,
+        path: &str,
+        iface: &str,
+        member: &str,
+        args: Vec<Value>,
+    ) -> io::Result<Vec<Value>> {
+        let serial = self.send(Message::method_call(dest, path, iface, member, args))?;
+        loop {
+            let m = self.read_message()?;
+            if m.kind == Kind::MethodCall {
+                self.pending.push_back(m);
+                continue;
+            }
+            if m.reply_serial != Some(serial) {
+                continue;
+            }
+            return match m.kind {
+                Kind::MethodReturn => Ok(m.body),
+                Kind::Error => {
+                    let name = m.error_name.unwrap_or_default();
+                    let text = m.body.first().and_then(|v| v.as_str()).unwrap_or_default();
+                    Err(io::Error::other(format!("{}: {}", name, text)))
+                }
+                _ => continue,
+            };
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn connect_abstract(name: &str) -> io::Result<UnixStream> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+    let addr = SocketAddr::from_abstract_name(name.as_bytes())?;
+    UnixStream::connect_addr(&addr)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_abstract(_name: &str) -> io::Result<UnixStream> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract sockets are Linux-only",
+    ))
+}
+
+/// Private bus daemon for tests (also used by other modules' tests).
+#[cfg(test)]
+pub(crate) mod test_support {
+    use std::process::{Child, Command, Stdio};
+    use std::thread;
+    use std::time::Duration;
+
+    pub struct Daemon {
+        child: Child,
+        pub address: String,
+        dir: std::path::PathBuf,
+    }
+
+    impl Drop for Daemon {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Private `dbus-daemon --session`; None if it isn't installed.
+    pub fn spawn_daemon(tag: &str) -> Option<Daemon> {
+        let dir = std::env::temp_dir().join(format!(
+            "focusctl-dbus-{}-{}",
+            std::process::id(),
+            tag
+        ));
+        std::fs::create_dir_all(&dir).ok()?;
+        let address = format!("unix:path={}/bus", dir.display());
+        let child = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--nopidfile"])
+            .arg(format!("--address={}", address))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let daemon = Daemon {
+            child,
+            address,
+            dir: dir.clone(),
+        };
+        for _ in 0..100 {
+            if dir.join("bus").exists() {
+                return Some(daemon);
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        None
+    }
+
+    pub fn own_uid() -> u32 {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::metadata("/proc/self").map(|m| m.uid()).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_support::{own_uid, spawn_daemon};
+    use std::thread;
+
+    #[test]
+    fn values_round_trip() {
+        let body = vec![
+            Value::Str("org.kde.KWin".into()),
+            Value::U32(7),
+            Value::Array(
+                "{sv}".into(),
+                vec![
+                    Value::DictEntry(
+                        Box::new(Value::Str("resourceClass".into())),
+                        Box::new(Value::Variant(Box::new(Value::Str("firefox".into())))),
+                    ),
+                    Value::DictEntry(
+                        Box::new(Value::Str("x".into())),
+                        Box::new(Value::Variant(Box::new(Value::Double(1.5)))),
+                    ),
+                ],
+            ),
+            Value::Array("s".into(), vec![]),
+            Value::Bool(true),
+            Value::I64(-3),
+        ];
+        let mut m = Message::method_call("d", "/p", "i.f", "m", body.clone());
+        m.serial = 42;
+        let back = Message::decode(&m.encode()).unwrap();
+        assert_eq!(back.serial, 42);
+        assert_eq!(back.path.as_deref(), Some("/p"));
+        assert_eq!(back.member.as_deref(), Some("m"));
+        assert_eq!(back.body, body);
+    }
+
+    #[test]
+    fn signatures_split() {
+        assert_eq!(split_all("a{sv}s(ii)ay").unwrap(), vec!["a{sv}", "s", "(ii)", "ay"]);
+        assert!(split_all("(ii").is_err());
+        assert!(split_all("a()").is_err());
+        assert!(split_all("{}").is_err());
+    }
+
+    #[test]
+    fn hostile_variants_are_rejected() {
+        // A variant whose signature is just an opening bracket, a struct
+        // with no fields in an array, or more than one type.
+        for sig in ["(", "{", "a()", "ss"] {
+            let mut body = vec![sig.len() as u8];
+            body.extend_from_slice(sig.as_bytes());
+            body.extend_from_slice(&[0; 16]);
+            let mut r = Reader { buf: &body, pos: 0 };
+            assert!(r.value("v").is_err(), "{}", sig);
+        }
+
+        let mut head = [0u8; 16];
+        head[0] = b'l';
+        head[4..8].copy_from_slice(&8u32.to_le_bytes());
+        head[12..16].copy_from_slice(&3u32.to_le_bytes());
+        assert_eq!(message_len(&head).unwrap(), 32);
+        head[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(message_len(&head).is_err());
+    }
+
+    #[test]
+    fn addresses_parse() {
+        assert_eq!(
+            parse_address("unix:path=/run/user/1000/bus,guid=abc"),
+            vec![Address::Path("/run/user/1000/bus".into())]
+        );
+        assert_eq!(
+            parse_address("tcp:host=x;unix:abstract=/tmp/dbus-%41x"),
+            vec![Address::Abstract("/tmp/dbus-Ax".into())]
+        );
+    }
+
+    #[test]
+    fn reconfigure_reaches_stub_kwin() {
+        let Some(daemon) = spawn_daemon("stub") else {
+            eprintln!("dbus-daemon not available; skipping");
+            return;
+        };
+        let uid = own_uid();
+
+        // Stub org.kde.KWin answering a single reconfigure call.
+        let mut stub = Connection::open_address(&daemon.address, uid).unwrap();
+        let granted = stub
+            .call(
+                BUS_NAME,
+                BUS_PATH,
+                BUS_NAME,
+                "RequestName",
+                vec![Value::Str("org.kde.KWin".into()), Value::U32(4)],
+            )
+            .unwrap();
+        assert_eq!(granted.first().and_then(|v| v.as_u32()), Some(1));
+
+        let server = thread::spawn(move || {
+            let m = stub.next_call().unwrap();
+            stub.send(Message::method_return(&m, vec![])).unwrap();
+            (m.path, m.interface, m.member)
+        });
+
+        let mut client = Connection::open_address(&daemon.address, uid).unwrap();
+        assert!(client.unique_name().starts_with(':'));
+        client
+            .call("org.kde.KWin", "/KWin", "org.kde.KWin", "reconfigure", vec![])
+            .unwrap();
+
+        let (path, iface, member) = server.join().unwrap();
+        assert_eq!(path.as_deref(), Some("/KWin"));
+        assert_eq!(iface.as_deref(), Some("org.kde.KWin"));
+        assert_eq!(member.as_deref(), Some("reconfigure"));
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let Some(daemon) = spawn_daemon("errors") else {
+            eprintln!("dbus-daemon not available; skipping");
+            return;
+        };
+        let mut client = Connection::open_address(&daemon.address, own_uid()).unwrap();
+        let e = client
+            .call("org.kde.KWin", "/KWin", "org.kde.KWin", "reconfigure", vec![])
+            .unwrap_err();
+        assert!(e.to_string().contains("ServiceUnknown"), "{}", e);
+    }
+}
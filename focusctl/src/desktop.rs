@@ -0,0 +1,488 @@
+// -------------------------------
+// Desktop entries
+// -------------------------------
+// Most apps name their windows after their .desktop file (Wayland's app id,
+// KWin's `desktopFileName`) or after the entry's `StartupWMClass`. So the
+// best name for a program is found by looking for the desktop entry that
+// runs it, in the XDG data directories, user's first:
+//
+//   $XDG_DATA_HOME/applications          (~/.local/share/applications)
+//   $XDG_DATA_DIRS/applications          (/usr/local/share:/usr/share)
+//
+// An entry runs a program when the first word of its `Exec` (after `env` and
+// its variable assignments, and past launchers such as `firejail`) or its
+// `TryExec` is that program: the same path, or the same file name when one
+// side is a bare command name. The class is the entry's `StartupWMClass` if
+// it has one, else its desktop id (the path under `applications/` with `/`
+// turned into `-`, minus `.desktop`). Without an entry, a Flatpak app id is
+// used as it is, and only a program falls back to
+// `wrap::auto_class_from_argv0`.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::fileops;
+use crate::kconfig::Document;
+use crate::launcher::{self, Launcher, Unwrapped};
+use crate::session;
+use crate::target::{Target, current_target};
+use crate::wrap::auto_class_from_argv0;
+
+const ENTRY_GROUP: &str = "Desktop Entry";
+const DEFAULT_DATA_DIRS: &str = "/usr/local/share:/usr/share";
+
+/// One application entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DesktopEntry {
+    /// Desktop id without `.desktop` (`org.mozilla.firefox`).
+    pub id: String,
+    pub path: PathBuf,
+    pub exec: Option<String>,
+    pub try_exec: Option<String>,
+    pub startup_wm_class: Option<String>,
+    pub no_display: bool,
+}
+
+/// Where a class name came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// The entry's `StartupWMClass`.
+    StartupWmClass(DesktopEntry),
+    /// The entry's desktop id.
+    DesktopId(DesktopEntry),
+    /// No entry; the Flatpak-style app id itself.
+    AppId,
+    /// No entry; `auto_class_from_argv0`.
+    Heuristic,
+}
+
+impl Source {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Source::StartupWmClass(_) => "StartupWMClass",
+            Source::DesktopId(_) => "desktop id",
+            Source::AppId => "app id",
+            Source::Heuristic => "heuristic",
+        }
+    }
+
+    pub fn entry(&self) -> Option<&DesktopEntry> {
+        match self {
+            Source::StartupWmClass(e) | Source::DesktopId(e) => Some(e),
+            Source::AppId | Source::Heuristic => None,
+        }
+    }
+}
+
+/// A class name for a program or desktop id, and how it was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resolution {
+    pub class: String,
+    pub source: Source,
+}
+
+/// The target's data directories, most important first. Their own
+/// environment when they are the current user, else their session's.
+fn data_dirs_for(target: &Target) -> Vec<PathBuf> {
+    let me = current_target();
+    let own = me.uid == target.uid && me.home == target.home;
+    let session = (!own)
+        .then(|| session::environment(target.uid, &target.user))
+        .flatten();
+    let var = |key: &str| -> Option<String> {
+        let value = if own {
+            env::var(key).ok()
+        } else {
+            session.as_ref()?.get(key).map(str::to_string)
+        };
+        value.filter(|v| !v.is_empty())
+    };
+
+    let data_home = var("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|d| d.is_absolute())
+        .unwrap_or_else(|| target.home.join(".local").join("share"));
+    let others = var("XDG_DATA_DIRS").unwrap_or_else(|| DEFAULT_DATA_DIRS.to_string());
+    let mut dirs = vec![data_home];
+    for dir in others.split(':').map(PathBuf::from) {
+        if dir.is_absolute() && !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+/// Every application entry under `data_dirs`; an id found in an earlier
+/// directory hides later ones, and `Hidden=true` entries hide it too.
+fn scan(data_dirs: &[PathBuf]) -> Vec<DesktopEntry> {
+    let mut seen: Vec<String> = Vec::new();
+    let mut out = Vec::new();
+    for dir in data_dirs {
+        let apps = dir.join("applications");
+        let mut files = Vec::new();
+        collect(&apps, &mut files);
+        files.sort();
+        for path in files {
+            let Ok(rel) = path.strip_prefix(&apps) else {
+                continue;
+            };
+            let rel = rel.to_string_lossy();
+            let id = rel
+                .strip_suffix(".desktop")
+                .unwrap_or(&rel)
+                .replace('/', "-");
+            if seen.contains(&id) {
+                continue;
+            }
+            seen.push(id.clone());
+            if let Ok(text) = fs::read_to_string(&path)
+                && let Some(entry) = parse_entry(id, &path, &text)
+            {
+                out.push(entry);
+            }
+        }
+    }
+    out
+}
+
+fn collect(dir: &Path, out: &mut Vec<PathBuf>) {
+    let Ok(rd) = fs::read_dir(dir) else {
+        return;
+    };
+    for e in rd.flatten() {
+        let path = e.path();
+        match e.file_type() {
+            Ok(t) if t.is_dir() => collect(&path, out),
+            _ if path.extension().is_some_and(|x| x == "desktop") => out.push(path),
+            _ => {}
+        }
+    }
+}
+
+fn parse_entry(id: String, path: &Path, text: &str) -> Option<DesktopEntry> {
+    let doc = Document::parse(text);
+    let get = |key: &str| doc.get(ENTRY_GROUP, key).filter(|v| !v.trim().is_empty());
+    let yes = |key: &str| get(key).is_some_and(|v| v.trim() == "true");
+    if get("Type").as_deref() != Some("Application") || yes("Hidden") {
+        return None;
+    }
+    Some(DesktopEntry {
+        id,
+        path: path.to_path_buf(),
+        exec: get("Exec"),
+        try_exec: get("TryExec"),
+        startup_wm_class: get("StartupWMClass").map(|v| v.trim().to_string()),
+        no_display: yes("NoDisplay"),
+    })
+}
+
+/// Split an `Exec` value into words: double quotes group, and a backslash
+/// inside them escapes the next character.
+fn exec_words(exec: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut cur = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            '\\' if quoted => cur.extend(chars.next()),
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut cur));
+                    in_word = false;
+                }
+            }
+            c => {
+                cur.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(cur);
+    }
+    words
+}
+
+/// The command an `Exec` line runs, past `env [-u NAME] VAR=value ...`.
+fn exec_argv(exec: &str) -> Vec<String> {
+    let words = exec_words(exec);
+    let mut rest = words.into_iter().peekable();
+    if rest
+        .peek()
+        .is_some_and(|w| w == "env" || w.ends_with("/env"))
+    {
+        rest.next();
+        while let Some(w) = rest.next_if(|w| w.contains('=') || w.starts_with('-')) {
+            if w == "-u" || w == "--unset" {
+                rest.next();
+            }
+        }
+    }
+    rest.collect()
+}
+
+/// The program an `Exec` line starts.
+pub fn exec_program(exec: &str) -> Option<String> {
+    exec_argv(exec).into_iter().next()
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// How well `program` (from an entry) names `argv0`: 2 for the same
+/// string, 1 for the same file name (one side bare, or both paths to the
+/// same file), 0 otherwise.
+fn program_match(program: &str, argv0: &str) -> u8 {
+    if program == argv0 {
+        return 2;
+    }
+    if basename(program) != basename(argv0) {
+        return 0;
+    }
+    if !program.contains('/') || !argv0.contains('/') {
+        return 1;
+    }
+    match (fs::canonicalize(program), fs::canonicalize(argv0)) {
+        (Ok(a), Ok(b)) if a == b => 1,
+        _ => 0,
+    }
+}
+
+/// The entry whose `Exec` (past any launchers) or `TryExec` runs `argv0`,
+/// best match first, then entries shown in menus, then search order.
+fn find_by_program<'e>(
+    entries: &'e [DesktopEntry],
+    launchers: &[Launcher],
+    argv0: &str,
+) -> Option<&'e DesktopEntry> {
+    entries
+        .iter()
+        .map(|e| {
+            let exec = e
+                .exec
+                .as_deref()
+                .map(|x| launcher::unwrap(launchers, &exec_argv(x)))
+                .filter(|u| !u.app_id && !u.program.is_empty())
+                .map(|u| u.program);
+            let score = [exec.as_deref(), e.try_exec.as_deref()]
+                .into_iter()
+                .flatten()
+                .map(|p| program_match(p, argv0))
+                .max()
+                .unwrap_or(0);
+            (score, e)
+        })
+        .filter(|(score, _)| *score > 0)
+        // max_by_key keeps the last of equals; reverse so search order wins.
+        .rev()
+        .max_by_key(|(score, e)| (*score, !e.no_display))
+        .map(|(_, e)| e)
+}
+
+fn find_by_id<'e>(entries: &'e [DesktopEntry], query: &str) -> Option<&'e DesktopEntry> {
+    if query.contains('/') {
+        return None;
+    }
+    let id = query.strip_suffix(".desktop").unwrap_or(query);
+    entries.iter().find(|e| e.id == id)
+}
+
+fn class_of(e: &DesktopEntry) -> Resolution {
+    match &e.startup_wm_class {
+        Some(class) => Resolution {
+            class: class.clone(),
+            source: Source::StartupWmClass(e.clone()),
+        },
+        None => Resolution {
+            class: e.id.clone(),
+            source: Source::DesktopId(e.clone()),
+        },
+    }
+}
+
+fn resolve_in(entries: &[DesktopEntry], launchers: &[Launcher], inner: &Unwrapped) -> Resolution {
+    let query = inner.program.as_str();
+    if inner.app_id {
+        return find_by_id(entries, query).map_or_else(
+            || Resolution {
+                class: query.to_string(),
+                source: Source::AppId,
+            },
+            class_of,
+        );
+    }
+    match find_by_id(entries, query).or_else(|| find_by_program(entries, launchers, query)) {
+        Some(e) => class_of(e),
+        None => Resolution {
+            class: auto_class_from_argv0(query),
+            source: Source::Heuristic,
+        },
+    }
+}
+
+/// The target's application entries (read as them when root acts for
+/// someone else).
+pub fn entries_for(target: &Target) -> Vec<DesktopEntry> {
+    let dirs = data_dirs_for(target);
+    fileops::as_target(target, || Ok(scan(&dirs))).unwrap_or_default()
+}
+
+/// Class name for a command line or a desktop id (`firefox`,
+/// `org.kde.dolphin.desktop`). Known launchers in front are skipped (see
+/// `launcher`); the class comes from the desktop entry that runs what is
+/// left or has that id, else from a Flatpak app id itself, else
+/// `auto_class_from_argv0`. Fails only on a bad focusctl config.
+pub fn resolve_command(target: &Target, argv: &[String]) -> io::Result<(Unwrapped, Resolution)> {
+    let launchers = launcher::launchers_for(target)?;
+    let inner = launcher::unwrap(&launchers, argv);
+    let found = resolve_in(&entries_for(target), &launchers, &inner);
+    Ok((inner, found))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dirs(tag: &str) -> (PathBuf, Vec<PathBuf>) {
+        let root =
+            std::env::temp_dir().join(format!("focusctl-desktop-{}-{}", tag, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let dirs = vec![root.join("home"), root.join("usr")];
+        for d in &dirs {
+            fs::create_dir_all(d.join("applications").join("kde")).unwrap();
+        }
+        (root, dirs)
+    }
+
+    fn write(dir: &Path, rel: &str, body: &str) {
+        fs::write(
+            dir.join("applications").join(rel),
+            format!("[Desktop Entry]\nType=Application\nName=x\n{}", body),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn exec_program_skips_env_and_quotes() {
+        assert_eq!(
+            exec_program("/usr/bin/google-chrome-stable %U").as_deref(),
+            Some("/usr/bin/google-chrome-stable")
+        );
+        assert_eq!(
+            exec_program("env GDK_BACKEND=x11 -u FOO app --x").as_deref(),
+            Some("app")
+        );
+        assert_eq!(
+            exec_program("\"/opt/My App/run\" %f").as_deref(),
+            Some("/opt/My App/run")
+        );
+        assert_eq!(exec_program("\"a\\\"b\"").as_deref(), Some("a\"b"));
+        assert_eq!(exec_program("  "), None);
+    }
+
+    #[test]
+    fn entries_are_found_by_program_or_id() {
+        let (root, dirs) = temp_dirs("find");
+        write(
+            &dirs[1],
+            "google-chrome.desktop",
+            "Exec=/usr/bin/google-chrome-stable %U\nStartupWMClass=Google-chrome\n",
+        );
+        write(&dirs[1], "kde/dolphin.desktop", "Exec=dolphin %u\n");
+        write(
+            &dirs[1],
+            "dolphin-helper.desktop",
+            "Exec=dolphin --select\nNoDisplay=true\n",
+        );
+        write(
+            &dirs[1],
+            "org.gnome.Terminal.desktop",
+            "Exec=gnome-terminal\nTryExec=/usr/bin/gnome-terminal\n",
+        );
+        // The user's copy hides the system one; Hidden hides it entirely.
+        write(&dirs[1], "firefox.desktop", "Exec=firefox\n");
+        write(&dirs[0], "firefox.desktop", "Exec=firefox\nHidden=true\n");
+        // Launchers are looked through, in Exec lines too.
+        write(
+            &dirs[0],
+            "chromium.desktop",
+            "Exec=firejail --x11 chromium %U\n",
+        );
+        write(
+            &dirs[1],
+            "org.mozilla.Thunderbird.desktop",
+            "Exec=/usr/bin/flatpak run --command=thunderbird org.mozilla.Thunderbird\n\
+             StartupWMClass=thunderbird\n",
+        );
+        let entries = scan(&dirs);
+        let ids: Vec<&str> = entries.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            [
+                "chromium",
+                "dolphin-helper",
+                "google-chrome",
+                "kde-dolphin",
+                "org.gnome.Terminal",
+                "org.mozilla.Thunderbird"
+            ]
+        );
+
+        let launchers = launcher::builtin_launchers();
+        let class = |q: &str| {
+            let argv: Vec<String> = q.split(' ').map(str::to_string).collect();
+            let r = resolve_in(&entries, &launchers, &launcher::unwrap(&launchers, &argv));
+            (r.class, r.source.as_str())
+        };
+        assert_eq!(
+            class("google-chrome-stable"),
+            ("Google-chrome".into(), "StartupWMClass")
+        );
+        assert_eq!(
+            class("/usr/bin/google-chrome-stable"),
+            ("Google-chrome".into(), "StartupWMClass")
+        );
+        assert_eq!(
+            class("/opt/other/google-chrome-stable"),
+            ("GoogleApp".into(), "heuristic")
+        );
+        assert_eq!(class("dolphin"), ("kde-dolphin".into(), "desktop id"));
+        assert_eq!(
+            class("/usr/bin/gnome-terminal"),
+            ("org.gnome.Terminal".into(), "desktop id")
+        );
+        assert_eq!(
+            class("google-chrome.desktop"),
+            ("Google-chrome".into(), "StartupWMClass")
+        );
+        assert_eq!(class("firefox"), ("FirefoxApp".into(), "heuristic"));
+        assert_eq!(
+            class("firejail --profile=x chromium"),
+            ("chromium".into(), "desktop id")
+        );
+        assert_eq!(
+            class("flatpak run org.mozilla.Thunderbird"),
+            ("thunderbird".into(), "StartupWMClass")
+        );
+        assert_eq!(
+            class("flatpak run org.example.Editor"),
+            ("org.example.Editor".into(), "app id")
+        );
+        assert_eq!(
+            class("bwrap --bind / / -- /usr/bin/dolphin"),
+            ("kde-dolphin".into(), "desktop id")
+        );
+        // A thunderbird binary is not the Flatpak.
+        assert_eq!(class("thunderbird"), ("ThunderbirdApp".into(), "heuristic"));
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,158 @@
+// -------------------------------
+// Unified line diffs
+// -------------------------------
+// `diff -u` output for the small text files focusctl edits, so `--dry-run`
+// can show exactly what a write would change. Common leading and trailing
+// lines are set aside first; the rest is a plain LCS table, which is cheap
+// for the handful of lines one command touches.
+
+/// Lines of context around each change, as in `diff -u`.
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Same,
+    Del,
+    Ins,
+}
+
+/// Line-by-line edit script from `a` to `b`.
+fn edit_script<'t>(a: &[&'t str], b: &[&'t str]) -> Vec<(Op, &'t str)> {
+    let head = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let tail = a[head..]
+        .iter()
+        .rev()
+        .zip(b[head..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (ma, mb) = (&a[head..a.len() - tail], &b[head..b.len() - tail]);
+
+    // lcs[i][j]: longest common subsequence of ma[i..] and mb[j..].
+    let mut lcs = vec![vec![0u32; mb.len() + 1]; ma.len() + 1];
+    for i in (0..ma.len()).rev() {
+        for j in (0..mb.len()).rev() {
+            lcs[i][j] = if ma[i] == mb[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops: Vec<(Op, &str)> = a[..head].iter().map(|l| (Op::Same, *l)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < ma.len() || j < mb.len() {
+        if i < ma.len() && j < mb.len() && ma[i] == mb[j] {
+            ops.push((Op::Same, ma[i]));
+            i += 1;
+            j += 1;
+        } else if i < ma.len() && (j == mb.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            // Deletions first, as diff prints them.
+            ops.push((Op::Del, ma[i]));
+            i += 1;
+        } else {
+            ops.push((Op::Ins, mb[j]));
+            j += 1;
+        }
+    }
+    ops.extend(a[a.len() - tail..].iter().map(|l| (Op::Same, *l)));
+    ops
+}
+
+/// `diff -u` of `old` and `new` with the given file labels; empty when they
+/// have the same lines.
+pub fn unified(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let ops = edit_script(&a, &b);
+
+    // Index ranges of `ops` to print: each change plus its context, merged
+    // when they overlap or touch.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (n, (op, _)) in ops.iter().enumerate() {
+        if *op == Op::Same {
+            continue;
+        }
+        let lo = n.saturating_sub(CONTEXT);
+        let hi = (n + 1 + CONTEXT).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if lo <= last.1 => last.1 = hi,
+            _ => hunks.push((lo, hi)),
+        }
+    }
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_label, new_label);
+    for (lo, hi) in hunks {
+        let count = |ops: &[(Op, &str)], skip: Op| ops.iter().filter(|(o, _)| *o != skip).count();
+        let (a_before, b_before) = (count(&ops[..lo], Op::Ins), count(&ops[..lo], Op::Del));
+        let (a_len, b_len) = (count(&ops[lo..hi], Op::Ins), count(&ops[lo..hi], Op::Del));
+        // An empty side is numbered by the line before it and a one-line
+        // side by its line alone, as diff does.
+        let range = |before: usize, len: usize| match len {
+            0 => format!("{},0", before),
+            1 => (before + 1).to_string(),
+            _ => format!("{},{}", before + 1, len),
+        };
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(a_before, a_len),
+            range(b_before, b_len)
+        ));
+        for (op, line) in &ops[lo..hi] {
+            let mark = match op {
+                Op::Same => ' ',
+                Op::Del => '-',
+                Op::Ins => '+',
+            };
+            out.push(mark);
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unchanged_text_has_no_diff() {
+        assert_eq!(unified("a\nb\n", "a\nb\n", "x", "y"), "");
+    }
+
+    #[test]
+    fn changes_get_context_and_hunk_headers() {
+        let old = "[General]\na=1\n\n[Script-kwin-focus-helper]\nforceFocusClasses=a\nmode=raise\n\n\
+                   [Plugins]\nw=0\nx=1\ny=2\nz=3\nkwin-focus-helperEnabled=false\n";
+        let new = "[General]\na=1\n\n[Script-kwin-focus-helper]\nforceFocusClasses=a;b\nmode=raise\n\n\
+                   [Plugins]\nw=0\nx=1\ny=2\nz=3\nkwin-focus-helperEnabled=true\n";
+        assert_eq!(
+            unified(old, new, "a/kwinrc", "b/kwinrc"),
+            "--- a/kwinrc\n+++ b/kwinrc\n\
+             @@ -2,7 +2,7 @@\n a=1\n \n [Script-kwin-focus-helper]\n-forceFocusClasses=a\n\
+             +forceFocusClasses=a;b\n mode=raise\n \n [Plugins]\n\
+             @@ -10,4 +10,4 @@\n x=1\n y=2\n z=3\n-kwin-focus-helperEnabled=false\n\
+             +kwin-focus-helperEnabled=true\n"
+        );
+    }
+
+    #[test]
+    fn one_line_ranges_have_no_length() {
+        assert_eq!(
+            unified("a\n", "b\n", "x", "y"),
+            "--- x\n+++ y\n@@ -1 +1 @@\n-a\n+b\n"
+        );
+    }
+
+    #[test]
+    fn a_new_file_is_all_additions() {
+        assert_eq!(
+            unified("", "[Plugins]\nx=true\n", "/dev/null", "kwinrc"),
+            "--- /dev/null\n+++ kwinrc\n@@ -0,0 +1,2 @@\n+[Plugins]\n+x=true\n"
+        );
+    }
+}
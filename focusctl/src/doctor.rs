@@ -0,0 +1,412 @@
+// -------------------------------
+// Doctor
+// -------------------------------
+// End-to-end diagnostics for "focus forcing doesn't work": install location,
+// enabled flag, config permissions, session bus, whether KWin loaded the
+// script, and a sanity pass over the stored settings.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::bus::{self, QDBUS_PROGRAMS};
+use crate::config::{
+    PLUGINS_GROUP, SCRIPT_ID, class_key, config_path_for, enabled_key, get_classes, get_enabled,
+    get_rules, get_script_value,
+};
+use crate::fileops;
+use crate::kwin;
+use crate::schema;
+use crate::target::Target;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Check {
+    pub status: Status,
+    pub name: &'static str,
+    pub detail: String,
+}
+
+fn check(status: Status, name: &'static str, detail: impl Into<String>) -> Check {
+    Check {
+        status,
+        name,
+        detail: detail.into(),
+    }
+}
+
+/// Exit status for a doctor run: 1 when any check failed, else 0.
+pub fn exit_code(checks: &[Check]) -> i32 {
+    i32::from(checks.iter().any(|c| c.status == Status::Fail))
+}
+
+/// Run every check, in a stable order.
+pub fn run(target: &Target) -> Vec<Check> {
+    let mut checks = vec![
+        check_installed(target),
+        check_enabled(target),
+        check_config_writable(target),
+    ];
+    checks.extend(check_session(target));
+    checks.push(check_qdbus());
+    checks.extend(check_classes(target));
+    checks
+}
+
+/// Where KWin looks for the script package: the user's data dir first
+/// (it shadows the system copy), then `$XDG_DATA_DIRS`.
+fn script_install_dirs(target: &Target) -> Vec<(&'static str, PathBuf)> {
+    let mut out = vec![(
+        "user",
+        target
+            .home
+            .join(".local/share/kwin/scripts")
+            .join(SCRIPT_ID),
+    )];
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    for d in data_dirs.split(':').filter(|d| !d.is_empty()) {
+        let dir = Path::new(d).join("kwin/scripts").join(SCRIPT_ID);
+        if !out.iter().any(|(_, p)| *p == dir) {
+            out.push(("system", dir));
+        }
+    }
+    out
+}
+
+fn check_installed(target: &Target) -> Check {
+    check_installed_in(script_install_dirs(target))
+}
+
+fn check_installed_in(dirs: Vec<(&'static str, PathBuf)>) -> Check {
+    let found: Vec<(&str, PathBuf)> = dirs
+        .into_iter()
+        .filter(|(_, d)| d.join("metadata.json").is_file())
+        .collect();
+    match found.as_slice() {
+        [] => check(
+            Status::Fail,
+            "script installed",
+            format!(
+                "no {}/metadata.json in user or system script dirs",
+                SCRIPT_ID
+            ),
+        ),
+        [(kind, dir)] => check(
+            Status::Pass,
+            "script installed",
+            format!("{} ({})", dir.display(), kind),
+        ),
+        [(kind, dir), ..] => check(
+            Status::Warn,
+            "script installed",
+            format!(
+                "{} copies; KWin uses {} ({})",
+                found.len(),
+                dir.display(),
+                kind
+            ),
+        ),
+    }
+}
+
+fn check_enabled(target: &Target) -> Check {
+    let name = "plugin enabled";
+    match get_enabled(target) {
+        Ok(Some(true)) => check(
+            Status::Pass,
+            name,
+            format!("[{}] {}=true", PLUGINS_GROUP, enabled_key()),
+        ),
+        Ok(Some(false)) => check(
+            Status::Fail,
+            name,
+            format!(
+                "[{}] {}=false (run: focusctl enable)",
+                PLUGINS_GROUP,
+                enabled_key()
+            ),
+        ),
+        Ok(None) => check(
+            Status::Warn,
+            name,
+            format!("{} is unset; KWin uses the script's default", enabled_key()),
+        ),
+        Err(e) => check(Status::Fail, name, format!("cannot read config: {}", e)),
+    }
+}
+
+/// Same checks the atomic config write depends on: the file (if present)
+/// opens for writing and its directory accepts a new file.
+fn check_config_writable(target: &Target) -> Check {
+    let name = "config writable";
+    let path = config_path_for(target);
+    let dir = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+
+    let probe = || -> io::Result<()> {
+        if path.exists() {
+            fs::OpenOptions::new().append(true).open(&path)?;
+        }
+        let tmp = dir.join(format!(".{}.doctor-probe", SCRIPT_ID));
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp)?;
+        fs::remove_file(&tmp)
+    };
+    match fileops::as_target(target, probe) {
+        Ok(()) if path.exists() => check(Status::Pass, name, path.display().to_string()),
+        Ok(()) => check(
+            Status::Warn,
+            name,
+            format!("{} does not exist yet (will be created)", path.display()),
+        ),
+        Err(e) => check(Status::Fail, name, format!("{}: {}", path.display(), e)),
+    }
+}
+
+fn check_classes(target: &Target) -> Vec<Check> {
+    let name = "class list";
+    let classes = match get_classes(target) {
+        Ok(c) => c,
+        Err(e) => {
+            return vec![check(
+                Status::Fail,
+                name,
+                format!("cannot read config: {}", e),
+            )];
+        }
+    };
+    let rules = get_rules(target).unwrap_or_default();
+    if classes.is_empty() && rules.is_empty() {
+        return vec![check(
+            Status::Warn,
+            name,
+            "no classes configured (run: focusctl add-class)",
+        )];
+    }
+
+    let mut out = Vec::new();
+    let mut seen: Vec<String> = Vec::new();
+    for c in &classes {
+        let k = class_key(c);
+        if seen.contains(&k) {
+            out.push(check(
+                Status::Warn,
+                name,
+                format!("duplicate entry {} (match key {})", c, k),
+            ));
+        } else {
+            seen.push(k);
+        }
+    }
+    if out.is_empty() {
+        out.push(check(
+            Status::Pass,
+            name,
+            format!("{} class(es), {} rule(s)", classes.len(), rules.len()),
+        ));
+    }
+
+    for spec in schema::SCRIPT_KEYS {
+        if let Ok(Some(raw)) = get_script_value(target, spec.name)
+            && let Err(e) = spec.normalize(&raw)
+        {
+            out.push(check(
+                Status::Fail,
+                "script settings",
+                format!("{}: {}", spec.name, e),
+            ));
+        }
+    }
+    out
+}
+
+fn check_session(target: &Target) -> Vec<Check> {
+    let mut out = Vec::new();
+
+    let session_env = match bus::detect_session_env_for_uid(target.uid) {
+        Ok(Some(env)) => {
+            out.push(check(
+                Status::Pass,
+                "graphical session",
+                format!("runtime dir {}", env.0),
+            ));
+            Some(env)
+        }
+        Ok(None) => {
+            out.push(check(
+                Status::Warn,
+                "graphical session",
+                format!("no active graphical session for uid {}", target.uid),
+            ));
+            None
+        }
+        Err(e) => {
+            out.push(check(
+                Status::Warn,
+                "graphical session",
+                format!("logind: {}", e),
+            ));
+            None
+        }
+    };
+
+    let address = bus::session_bus_address(target, session_env.as_ref());
+    let mut conn = match bus::open_session_bus(target, session_env.as_ref()) {
+        Ok(b) => {
+            out.push(check(
+                Status::Pass,
+                "session bus",
+                format!("{} as {}", address, b.unique_name()),
+            ));
+            b
+        }
+        Err(e) => {
+            out.push(check(
+                Status::Fail,
+                "session bus",
+                format!("{}: {}", address, e),
+            ));
+            out.push(check(
+                Status::Fail,
+                "script loaded",
+                "skipped: no session bus",
+            ));
+            return out;
+        }
+    };
+
+    out.push(match kwin::is_script_loaded(&mut conn, SCRIPT_ID) {
+        Ok(true) => check(Status::Pass, "script loaded", "isScriptLoaded = true"),
+        Ok(false) => check(
+            Status::Fail,
+            "script loaded",
+            "KWin has not loaded it (enable it, then: focusctl reconfigure)",
+        ),
+        Err(e) => check(
+            Status::Fail,
+            "script loaded",
+            format!("KWin unreachable: {}", e),
+        ),
+    });
+    out
+}
+
+fn check_qdbus() -> Check {
+    let found = QDBUS_PROGRAMS.iter().find(|p| bus::have_cmd(p));
+    match found {
+        Some(p) => check(Status::Pass, "qdbus", format!("{} (fallback only)", p)),
+        None => check(
+            Status::Warn,
+            "qdbus",
+            "not found; only native D-Bus is available",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{set_classes, set_enabled, set_script_value};
+
+    fn temp_target(tag: &str) -> Target {
+        let home =
+            std::env::temp_dir().join(format!("focusctl-doctor-{}-{}", tag, std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        fs::create_dir_all(home.join(".config")).unwrap();
+        Target {
+            uid: 0,
+            user: "test".into(),
+            home,
+        }
+    }
+
+    fn statuses(checks: &[Check]) -> Vec<Status> {
+        checks.iter().map(|c| c.status).collect()
+    }
+
+    #[test]
+    fn the_enabled_flag_is_checked() {
+        let t = temp_target("enabled");
+        assert_eq!(check_enabled(&t).status, Status::Warn);
+        set_enabled(&t, true).unwrap();
+        assert_eq!(check_enabled(&t).status, Status::Pass);
+        set_enabled(&t, false).unwrap();
+        assert_eq!(check_enabled(&t).status, Status::Fail);
+        let _ = fs::remove_dir_all(&t.home);
+    }
+
+    #[test]
+    fn the_class_list_and_settings_are_checked() {
+        let t = temp_target("classes");
+        assert_eq!(statuses(&check_classes(&t)), [Status::Warn]);
+        set_classes(&t, &["firefox".into(), "Chromium".into()]).unwrap();
+        assert_eq!(statuses(&check_classes(&t)), [Status::Pass]);
+        set_classes(&t, &["firefox".into(), "Firefox".into()]).unwrap();
+        let checks = check_classes(&t);
+        assert_eq!(statuses(&checks), [Status::Warn]);
+        assert!(checks[0].detail.contains("duplicate"));
+        set_script_value(&t, "mode", "sideways").unwrap();
+        assert_eq!(statuses(&check_classes(&t)), [Status::Warn, Status::Fail]);
+        let _ = fs::remove_dir_all(&t.home);
+    }
+
+    #[test]
+    fn the_config_must_be_writable() {
+        let t = temp_target("writable");
+        assert_eq!(check_config_writable(&t).status, Status::Warn);
+        set_enabled(&t, true).unwrap();
+        assert_eq!(check_config_writable(&t).status, Status::Pass);
+        // A config directory that is not a directory.
+        fs::remove_dir_all(t.home.join(".config")).unwrap();
+        fs::write(t.home.join(".config"), "").unwrap();
+        assert_eq!(check_config_writable(&t).status, Status::Fail);
+        let _ = fs::remove_dir_all(&t.home);
+    }
+
+    #[test]
+    fn installed_copies_are_counted() {
+        let t = temp_target("installed");
+        let user = t.home.join("user");
+        let system = t.home.join("system");
+        let dirs = || vec![("user", user.clone()), ("system", system.clone())];
+        assert_eq!(check_installed_in(dirs()).status, Status::Fail);
+        fs::create_dir_all(&system).unwrap();
+        fs::write(system.join("metadata.json"), "{}").unwrap();
+        assert_eq!(check_installed_in(dirs()).status, Status::Pass);
+        fs::create_dir_all(&user).unwrap();
+        fs::write(user.join("metadata.json"), "{}").unwrap();
+        let both = check_installed_in(dirs());
+        assert_eq!(both.status, Status::Warn);
+        assert!(both.detail.contains(&user.display().to_string()));
+        let _ = fs::remove_dir_all(&t.home);
+    }
+
+    #[test]
+    fn any_failed_check_fails_the_run() {
+        let c = |status| check(status, "x", "");
+        assert_eq!(exit_code(&[c(Status::Pass), c(Status::Warn)]), 0);
+        assert_eq!(exit_code(&[c(Status::Pass), c(Status::Fail)]), 1);
+        assert_eq!(exit_code(&[]), 0);
+    }
+}
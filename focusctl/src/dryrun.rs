@@ -0,0 +1,75 @@
+// -------------------------------
+// Dry runs
+// -------------------------------
+// With `--dry-run`, `fileops::update` does not write: it keeps the new
+// contents in an overlay here, and `fileops::read` returns them, so a command
+// that reads back what it wrote (wrap's ensure steps, `use` then `status`)
+// behaves as if the write had happened. Afterwards the CLI turns the overlay
+// into unified diffs. The state is per thread, so tests stay independent.
+
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+
+/// A file a dry run would have replaced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub path: PathBuf,
+    /// Contents on disk, `None` when the file does not exist yet.
+    pub before: Option<String>,
+    /// Contents after every edit so far.
+    pub after: String,
+}
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+    static CHANGES: RefCell<Vec<Change>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Turn dry-run mode on or off for this thread; either way the recorded
+/// changes are dropped.
+pub fn set_enabled(on: bool) {
+    ENABLED.with(|e| e.set(on));
+    CHANGES.with(|c| c.borrow_mut().clear());
+}
+
+pub fn enabled() -> bool {
+    ENABLED.with(|e| e.get())
+}
+
+/// Contents a dry-run edit left for `path`, `None` when none touched it.
+pub(crate) fn pending(path: &Path) -> Option<String> {
+    CHANGES.with(|c| {
+        c.borrow()
+            .iter()
+            .find(|ch| ch.path == path)
+            .map(|ch| ch.after.clone())
+    })
+}
+
+/// Record that `path` would now hold `after`; `before` only counts for the
+/// first edit of a file.
+pub(crate) fn record(path: &Path, before: Option<String>, after: String) {
+    CHANGES.with(|c| {
+        let mut changes = c.borrow_mut();
+        match changes.iter_mut().find(|ch| ch.path == path) {
+            Some(ch) => ch.after = after,
+            None => changes.push(Change {
+                path: path.to_path_buf(),
+                before,
+                after,
+            }),
+        }
+    })
+}
+
+/// Files that would change, in the order they were first edited. A file
+/// edited back to its original contents is left out.
+pub fn changes() -> Vec<Change> {
+    CHANGES.with(|c| {
+        c.borrow()
+            .iter()
+            .filter(|ch| ch.before.as_deref() != Some(ch.after.as_str()))
+            .cloned()
+            .collect()
+    })
+}
@@ -0,0 +1,384 @@
+// -------------------------------
+// Locked read-modify-write of config files
+// -------------------------------
+// Every focusctl edit of kwinrc (and of its own files) goes through `update`:
+//
+// 1. take an exclusive flock(2) on `<file>.lock.kwin-focus-helper`, so
+//    concurrent focusctl runs (two `wrap` launches, a script looping over
+//    `add-class`) queue up instead of overwriting each other;
+// 2. read the file and remember its stamp (inode, mtime, size, content hash);
+// 3. let the caller edit the text, write it to a temp file next to it;
+// 4. just before the rename, check the stamp again. KWin and System Settings
+//    don't take our lock, so if the file changed underneath us the edit is
+//    thrown away and redone on the new contents (up to `ATTEMPTS` times).
+//
+// The lock file is left in place: removing it would let a waiting process
+// lock a file nobody else can see.
+//
+// The new file gets the old one's mode, owner and POSIX ACL. When root edits
+// another user's files, callers wrap the whole operation in `as_target`, so
+// it runs with that user's uid/gid: new files and directories belong to them,
+// and a symlink or a swapped directory inside their home can only lead where
+// they could write themselves. A symlinked file (dotfile managers) is
+// followed and the file it points to is updated; the temp file is created
+// with O_EXCL, so a planted link there is never followed.
+//
+// Under `--dry-run` nothing is locked, created or written; see `dryrun`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::creds;
+use crate::dryrun;
+use crate::target::{self, Target};
+
+unsafe extern "C" {
+    fn flock(fd: i32, operation: i32) -> i32;
+    fn getxattr(path: *const c_char, name: *const c_char, value: *mut c_void, size: usize)
+    -> isize;
+    fn fsetxattr(
+        fd: i32,
+        name: *const c_char,
+        value: *const c_void,
+        size: usize,
+        flags: i32,
+    ) -> i32;
+}
+
+const LOCK_EX: i32 = 2;
+const ENODATA: i32 = 61;
+const EOPNOTSUPP: i32 = 95;
+const ACL_XATTR: &CStr = c"system.posix_acl_access";
+
+/// How often an edit is redone when someone else keeps writing the file.
+const ATTEMPTS: usize = 8;
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    path.with_extension(suffix)
+}
+
+/// Run `f` with the target's uid, primary gid and no other groups when root
+/// acts for someone else; otherwise just run it.
+pub(crate) fn as_target<T>(target: &Target, f: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+    if creds::euid() != 0 || target.uid == 0 {
+        return f();
+    }
+    let gid = target::primary_gid(target.uid)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("uid {} is not in /etc/passwd", target.uid),
+        )
+    })?;
+    creds::with_ids(target.uid, gid, f).and_then(|r| r)
+}
+
+/// The file a symlinked `path` points to, or `path` itself.
+fn real_path(path: &Path) -> io::Result<PathBuf> {
+    match fs::symlink_metadata(path) {
+        Ok(m) if m.file_type().is_symlink() => fs::canonicalize(path),
+        _ => Ok(path.to_path_buf()),
+    }
+}
+
+/// Held for the whole read-modify-write; closing the file unlocks.
+struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    fn acquire(path: &Path) -> io::Result<Lock> {
+        let f = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(sibling(path, "lock.kwin-focus-helper"))?;
+        loop {
+            // SAFETY: `f` owns a valid descriptor for the duration of the call.
+            if unsafe { flock(f.as_raw_fd(), LOCK_EX) } == 0 {
+                return Ok(Lock { _file: f });
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// What we saw when we read the file; `None` fields for a missing file.
+#[derive(Debug, PartialEq, Eq)]
+struct Stamp {
+    ino: Option<u64>,
+    mtime: Option<SystemTime>,
+    len: Option<u64>,
+    hash: u64,
+}
+
+fn hash(text: &str) -> u64 {
+    let mut h = DefaultHasher::new();
+    text.hash(&mut h);
+    h.finish()
+}
+
+fn read_missing_ok(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(t) => Ok(Some(t)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Read `path`, or what a dry-run `update` left for it.
+pub(crate) fn read(path: &Path) -> io::Result<String> {
+    if dryrun::enabled()
+        && let Some(text) = dryrun::pending(&real_path(path)?)
+    {
+        return Ok(text);
+    }
+    fs::read_to_string(path)
+}
+
+/// The file's contents (`None` when missing), its stamp and metadata.
+fn read_stamped(path: &Path) -> io::Result<(Option<String>, Stamp, Option<Metadata>)> {
+    let text = read_missing_ok(path)?;
+    let meta = match fs::metadata(path) {
+        Ok(m) => Some(m),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let stamp = Stamp {
+        ino: meta.as_ref().map(|m| m.ino()),
+        mtime: meta.as_ref().and_then(|m| m.modified().ok()),
+        len: meta.as_ref().map(|m| m.len()),
+        hash: text.as_deref().map(hash).unwrap_or(0),
+    };
+    Ok((text, stamp, meta))
+}
+
+/// Copy `from`'s POSIX access ACL, if it has one, to `to`.
+fn copy_acl(from: &Path, to: &File) -> io::Result<()> {
+    let path = CString::new(from.as_os_str().as_bytes())?;
+    let ignorable = |e: &io::Error| matches!(e.raw_os_error(), Some(ENODATA | EOPNOTSUPP));
+
+    // SAFETY: NUL-terminated strings and a buffer of the length we pass.
+    let len = unsafe { getxattr(path.as_ptr(), ACL_XATTR.as_ptr(), std::ptr::null_mut(), 0) };
+    if len < 0 {
+        let e = io::Error::last_os_error();
+        return if ignorable(&e) { Ok(()) } else { Err(e) };
+    }
+    let mut acl = vec![0u8; len as usize];
+    let len = unsafe {
+        getxattr(
+            path.as_ptr(),
+            ACL_XATTR.as_ptr(),
+            acl.as_mut_ptr().cast(),
+            acl.len(),
+        )
+    };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let rc = unsafe {
+        fsetxattr(
+            to.as_raw_fd(),
+            ACL_XATTR.as_ptr(),
+            acl.as_ptr().cast(),
+            len as usize,
+            0,
+        )
+    };
+    if rc != 0 {
+        let e = io::Error::last_os_error();
+        if !ignorable(&e) {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Write `contents` next to `path`, with `original`'s mode, owner (when we
+/// are root) and ACL.
+fn write_tmp(path: &Path, contents: &str, original: Option<&Metadata>) -> io::Result<PathBuf> {
+    let tmp = sibling(path, "tmp.kwin-focus-helper");
+    // Left over from a crashed run; nobody else uses it while we hold the lock.
+    match fs::remove_file(&tmp) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut f = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(if original.is_some() { 0o600 } else { 0o666 })
+        .open(&tmp)?;
+    f.write_all(contents.as_bytes())?;
+    if let Some(meta) = original {
+        f.set_permissions(fs::Permissions::from_mode(meta.mode() & 0o7777))?;
+        if creds::euid() == 0 {
+            std::os::unix::fs::fchown(&f, Some(meta.uid()), Some(meta.gid()))?;
+        }
+        copy_acl(path, &f)?;
+    }
+    f.sync_all()?;
+    Ok(tmp)
+}
+
+/// Locked, conflict-checked read-modify-write of `path`.
+///
+/// `edit` gets the current contents (`None` if the file does not exist) and
+/// returns the new contents, or `None` to leave the file alone. It may run
+/// more than once when another writer changes the file in between, so it
+/// must compute the result from its argument. Returns whether the file was
+/// replaced. In a dry run the new contents only go to `dryrun`'s overlay,
+/// which also stands in for the file on the next `update` or `read`.
+pub(crate) fn update(
+    path: &Path,
+    mut edit: impl FnMut(Option<&str>) -> io::Result<Option<String>>,
+) -> io::Result<bool> {
+    let path = &real_path(path)?;
+    if dryrun::enabled() {
+        let on_disk = read_missing_ok(path)?;
+        let current = dryrun::pending(path).or_else(|| on_disk.clone());
+        let Some(new) = edit(current.as_deref())? else {
+            return Ok(false);
+        };
+        dryrun::record(path, on_disk, new);
+        return Ok(true);
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let _lock = Lock::acquire(path)?;
+
+    for _ in 0..ATTEMPTS {
+        let (text, stamp, meta) = read_stamped(path)?;
+        let Some(new) = edit(text.as_deref())? else {
+            return Ok(false);
+        };
+        let tmp = write_tmp(path, &new, meta.as_ref())?;
+        if read_stamped(path)?.1 != stamp {
+            let _ = fs::remove_file(&tmp);
+            continue;
+        }
+        fs::rename(tmp, path)?;
+        return Ok(true);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::WouldBlock,
+        format!(
+            "{} kept changing while it was being updated",
+            path.display()
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(tag: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("focusctl-fileops-{}-{}", tag, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("sub").join("rc")
+    }
+
+    #[test]
+    fn update_creates_edits_and_skips() {
+        let path = temp_file("basic");
+        assert!(update(&path, |t| Ok(Some(format!("{}a\n", t.unwrap_or("")))),).unwrap());
+        assert!(update(&path, |t| Ok(Some(format!("{}b\n", t.unwrap_or("")))),).unwrap());
+        assert!(!update(&path, |_| Ok(None)).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nb\n");
+        let _ = fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+    }
+
+    #[test]
+    fn a_concurrent_writer_is_merged_not_overwritten() {
+        let path = temp_file("conflict");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "base\n").unwrap();
+
+        // Someone without our lock (KWin saving) writes between our read and
+        // our rename on the first attempt.
+        let mut calls = 0;
+        update(&path, |t| {
+            calls += 1;
+            if calls == 1 {
+                fs::write(&path, "base\nkwin\n").unwrap();
+            }
+            Ok(Some(format!("{}ours\n", t.unwrap())))
+        })
+        .unwrap();
+        assert_eq!(calls, 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "base\nkwin\nours\n");
+
+        // A writer that never stops is reported, not raced forever.
+        let e = update(&path, |t| {
+            fs::write(&path, format!("{}x", t.unwrap())).unwrap();
+            Ok(Some(String::new()))
+        })
+        .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+        let _ = fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+    }
+
+    #[test]
+    fn a_dry_run_only_fills_the_overlay() {
+        let path = temp_file("dry");
+        dryrun::set_enabled(true);
+        assert!(update(&path, |t| Ok(Some(format!("{}a\n", t.unwrap_or(""))))).unwrap());
+        assert!(update(&path, |t| Ok(Some(format!("{}b\n", t.unwrap_or(""))))).unwrap());
+        assert_eq!(read(&path).unwrap(), "a\nb\n");
+        let changes = dryrun::changes();
+        dryrun::set_enabled(false);
+
+        assert!(!path.parent().unwrap().exists());
+        assert_eq!(
+            changes,
+            [dryrun::Change {
+                path: path.clone(),
+                before: None,
+                after: "a\nb\n".into(),
+            }]
+        );
+        assert_eq!(read(&path).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn replacing_keeps_mode_owner_and_symlinks() {
+        let path = temp_file("meta");
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir.join("dotfiles")).unwrap();
+        let real = dir.join("dotfiles").join("rc");
+        fs::write(&real, "a\n").unwrap();
+        fs::set_permissions(&real, fs::Permissions::from_mode(0o640)).unwrap();
+        std::os::unix::fs::symlink("dotfiles/rc", &path).unwrap();
+        let root = creds::euid() == 0;
+        if root {
+            std::os::unix::fs::chown(&real, Some(65534), Some(65534)).unwrap();
+        }
+
+        assert!(update(&path, |t| Ok(Some(format!("{}b\n", t.unwrap())))).unwrap());
+        assert!(
+            fs::symlink_metadata(&path)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert_eq!(fs::read_to_string(&real).unwrap(), "a\nb\n");
+        let meta = fs::metadata(&real).unwrap();
+        assert_eq!(meta.mode() & 0o7777, 0o640);
+        if root {
+            assert_eq!((meta.uid(), meta.gid()), (65534, 65534));
+        }
+        let _ = fs::remove_dir_all(dir.parent().unwrap());
+    }
+}
@@ -0,0 +1,396 @@
+// -------------------------------
+// Ephemeral wrap grants
+// -------------------------------
+// `wrap --ephemeral` adds its class only for as long as the wrapped program
+// runs. Every running wrapper holds a line in a state file in the target's
+// runtime directory, which the session tears down at logout:
+//
+//   $XDG_RUNTIME_DIR/kwin-focus-helper/grants
+//
+//   hold 41235 9917342 ProcletChrome
+//   hold 41260 9917501 procletchrome
+//   added ProcletChrome
+//
+// A `hold` line is one wrapper: its pid, its start time (so a recycled pid
+// does not count) and its class. An `added` line says an ephemeral wrapper
+// put that class into kwinrc; when the last holder of its match key is gone,
+// it is removed again. A class that was already configured gets no `added`
+// line and is never removed. Wrappers that died without releasing are
+// noticed on the next acquire or release, and their grants cleaned up.
+//
+// The file is edited with `fileops::update`, and kwinrc only inside that
+// locked edit, so concurrent wrappers agree on the count. These kwinrc edits
+// are not recorded in the undo history.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::{
+    GROUP_NAME, KEY_NAME, check_class, class_key, doc_classes, doc_effective_rules, join_classes,
+    update_document_untracked,
+};
+use crate::fileops;
+use crate::session;
+use crate::target::{Target, current_target};
+
+/// One running wrapper.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Holder {
+    pid: u32,
+    /// Field 22 of `/proc/<pid>/stat`.
+    start: u64,
+    class: String,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct State {
+    holders: Vec<Holder>,
+    added: Vec<String>,
+}
+
+impl State {
+    fn parse(text: &str) -> State {
+        let mut state = State::default();
+        for line in text.lines() {
+            let mut parts = line.trim().splitn(4, ' ');
+            match parts.next() {
+                Some("hold") => {
+                    let (Some(pid), Some(start), Some(class)) =
+                        (parts.next(), parts.next(), parts.next())
+                    else {
+                        continue;
+                    };
+                    if let (Ok(pid), Ok(start)) = (pid.parse(), start.parse()) {
+                        state.holders.push(Holder {
+                            pid,
+                            start,
+                            class: class.to_string(),
+                        });
+                    }
+                }
+                Some("added") => {
+                    let rest: Vec<&str> = parts.collect();
+                    if !rest.is_empty() {
+                        state.added.push(rest.join(" "));
+                    }
+                }
+                _ => {}
+            }
+        }
+        state
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for h in &self.holders {
+            out.push_str(&format!("hold {} {} {}\n", h.pid, h.start, h.class));
+        }
+        for class in &self.added {
+            out.push_str(&format!("added {}\n", class));
+        }
+        out
+    }
+
+    fn held(&self, key: &str) -> bool {
+        self.holders.iter().any(|h| class_key(&h.class) == key)
+    }
+
+    /// Drop holders `alive` rejects, then forget added classes nobody holds
+    /// any more; those are returned for removal from kwinrc.
+    fn sweep(&mut self, alive: &impl Fn(u32, u64) -> bool) -> Vec<String> {
+        self.holders.retain(|h| alive(h.pid, h.start));
+        let (kept, orphans) = std::mem::take(&mut self.added)
+            .into_iter()
+            .partition(|c| self.held(&class_key(c)));
+        self.added = kept;
+        orphans
+    }
+}
+
+/// A wrapper's hold on its class, from `acquire` until `release`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Grant {
+    pub class: String,
+    /// This wrapper put the class into kwinrc.
+    pub added: bool,
+    /// Classes removed because the wrappers holding them had died.
+    pub swept: Vec<String>,
+    pid: u32,
+    start: u64,
+}
+
+/// The target's runtime directory: `$XDG_RUNTIME_DIR` for ourselves, else
+/// the one logind set up for them.
+fn runtime_dir_for(target: &Target) -> PathBuf {
+    let me = current_target();
+    if me.uid == target.uid
+        && let Some(dir) = env::var_os("XDG_RUNTIME_DIR").filter(|d| !d.is_empty())
+    {
+        return PathBuf::from(dir);
+    }
+    session::runtime_dir_for(target.uid)
+}
+
+/// Where the grant state lives; a `NotFound` error when the target has no
+/// runtime directory (not logged in).
+pub fn grants_path_for(target: &Target) -> io::Result<PathBuf> {
+    let runtime = runtime_dir_for(target);
+    if !runtime.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "no runtime directory for {} ({} is missing)",
+                target.user,
+                runtime.display()
+            ),
+        ));
+    }
+    Ok(runtime.join("kwin-focus-helper").join("grants"))
+}
+
+/// Start time of `pid` in clock ticks since boot, `None` if it is not
+/// running.
+fn start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name in field 2 may contain spaces; count from after it.
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(19)?.parse().ok()
+}
+
+fn is_alive(pid: u32, start: u64) -> bool {
+    start_time(pid) == Some(start)
+}
+
+/// Remove `orphans` from the plain class list (by match key) and add `add`
+/// unless its key is configured already. Returns whether `add` was added.
+fn edit_kwinrc(target: &Target, orphans: &[String], add: Option<&str>) -> io::Result<bool> {
+    if orphans.is_empty() && add.is_none() {
+        return Ok(false);
+    }
+    let keys: Vec<String> = orphans.iter().map(|c| class_key(c)).collect();
+    let mut added = false;
+    update_document_untracked(target, |doc| {
+        added = false;
+        let mut classes = doc_classes(doc);
+        let before = classes.len();
+        classes.retain(|c| !keys.contains(&class_key(c)));
+        let mut changed = classes.len() != before;
+        if let Some(class) = add
+            && !doc_effective_rules(doc)
+                .iter()
+                .any(|r| r.key() == class_key(class))
+        {
+            classes.push(class.to_string());
+            added = true;
+            changed = true;
+        }
+        if !changed {
+            return Ok(false);
+        }
+        doc.set(GROUP_NAME, KEY_NAME, &join_classes(&classes))
+    })?;
+    Ok(added)
+}
+
+fn acquire_in(
+    path: &Path,
+    target: &Target,
+    class: &str,
+    (pid, start): (u32, u64),
+    alive: impl Fn(u32, u64) -> bool,
+) -> io::Result<Grant> {
+    let key = check_class(class).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut grant = None;
+    // The edit may be retried after it already put the class in kwinrc;
+    // the retry then finds it configured, but it is still ours.
+    let mut added_earlier = false;
+    fileops::update(path, |text| {
+        let mut state = State::parse(text.unwrap_or(""));
+        let mut orphans = state.sweep(&alive);
+        // An orphaned grant for our own class simply passes to us.
+        let ours = orphans.iter().position(|c| class_key(c) == key);
+        if let Some(n) = ours {
+            state.added.push(orphans.remove(n));
+        }
+        let already_ours = state.added.iter().any(|c| class_key(c) == key);
+        let added = edit_kwinrc(target, &orphans, (!already_ours).then_some(class))?
+            || (added_earlier && !already_ours);
+        added_earlier = added;
+        if added {
+            state.added.push(class.to_string());
+        }
+        state.holders.push(Holder {
+            pid,
+            start,
+            class: class.to_string(),
+        });
+        grant = Some(Grant {
+            class: class.to_string(),
+            added,
+            swept: orphans,
+            pid,
+            start,
+        });
+        Ok(Some(state.render()))
+    })?;
+    Ok(grant.expect("update runs the edit at least once"))
+}
+
+fn release_in(
+    path: &Path,
+    target: &Target,
+    grant: &Grant,
+    alive: impl Fn(u32, u64) -> bool,
+) -> io::Result<Vec<String>> {
+    let mut removed = Vec::new();
+    fileops::update(path, |text| {
+        let mut state = State::parse(text.unwrap_or(""));
+        state
+            .holders
+            .retain(|h| (h.pid, h.start) != (grant.pid, grant.start));
+        removed = state.sweep(&alive);
+        edit_kwinrc(target, &removed, None)?;
+        Ok(Some(state.render()))
+    })?;
+    Ok(removed)
+}
+
+/// Hold `class` for this process, adding it to kwinrc unless it is
+/// configured already. Grants left by wrappers that died are cleaned up
+/// first.
+pub fn acquire(target: &Target, class: &str) -> io::Result<Grant> {
+    let pid = std::process::id();
+    let start = start_time(pid)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "cannot read /proc/self/stat"))?;
+    fileops::as_target(target, || {
+        acquire_in(
+            &grants_path_for(target)?,
+            target,
+            class,
+            (pid, start),
+            is_alive,
+        )
+    })
+}
+
+/// Give up `grant`. Returns the classes removed from kwinrc: its own once no
+/// other wrapper holds it, plus any left behind by dead wrappers.
+pub fn release(target: &Target, grant: &Grant) -> io::Result<Vec<String>> {
+    fileops::as_target(target, || {
+        release_in(&grants_path_for(target)?, target, grant, is_alive)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, get_classes};
+    use std::cell::RefCell;
+
+    fn temp_target(tag: &str) -> Target {
+        let home =
+            std::env::temp_dir().join(format!("focusctl-grants-{}-{}", tag, std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        fs::create_dir_all(home.join(".config")).unwrap();
+        Target {
+            uid: 0,
+            user: "test".into(),
+            home,
+        }
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let text = "hold 41235 9917342 ProcletChrome\nhold 7 8 /a b/\nadded ProcletChrome\n";
+        let state = State::parse(&format!("{}junk\nhold x 1 y\n", text));
+        assert_eq!(state.holders.len(), 2);
+        assert_eq!(state.holders[1].class, "/a b/");
+        assert_eq!(state.render(), text);
+    }
+
+    #[test]
+    fn last_holder_removes_what_was_added() {
+        let t = temp_target("count");
+        let path = t.home.join("run").join("grants");
+        config::set_classes(&t, &["Firefox".to_string()]).unwrap();
+        let always = |_: u32, _: u64| true;
+
+        let a = acquire_in(&path, &t, "ProcletChrome", (10, 1), always).unwrap();
+        let b = acquire_in(&path, &t, "procletchrome", (11, 1), always).unwrap();
+        let c = acquire_in(&path, &t, "firefox", (12, 1), always).unwrap();
+        assert_eq!((a.added, b.added, c.added), (true, false, false));
+        assert_eq!(get_classes(&t).unwrap(), ["Firefox", "ProcletChrome"]);
+
+        assert!(release_in(&path, &t, &a, always).unwrap().is_empty());
+        assert_eq!(get_classes(&t).unwrap(), ["Firefox", "ProcletChrome"]);
+        assert_eq!(
+            release_in(&path, &t, &b, always).unwrap(),
+            ["ProcletChrome"]
+        );
+        // Firefox was configured before any wrapper; it stays.
+        assert!(release_in(&path, &t, &c, always).unwrap().is_empty());
+        assert_eq!(get_classes(&t).unwrap(), ["Firefox"]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        let _ = fs::remove_dir_all(&t.home);
+    }
+
+    #[test]
+    fn dead_wrappers_are_swept() {
+        let t = temp_target("sweep");
+        let path = t.home.join("run").join("grants");
+        let dead = RefCell::new(Vec::new());
+        let alive = |pid: u32, _: u64| !dead.borrow().contains(&pid);
+
+        acquire_in(&path, &t, "Crashy", (20, 1), alive).unwrap();
+        acquire_in(&path, &t, "Kept", (21, 1), alive).unwrap();
+        dead.borrow_mut().extend([20, 21]);
+
+        // The crashed "Kept" holder's grant passes to the new one.
+        let g = acquire_in(&path, &t, "kept", (22, 1), alive).unwrap();
+        assert_eq!(
+            (g.added, g.swept.as_slice()),
+            (false, &["Crashy".to_string()][..])
+        );
+        assert_eq!(get_classes(&t).unwrap(), ["Kept"]);
+        assert_eq!(release_in(&path, &t, &g, alive).unwrap(), ["Kept"]);
+        assert!(get_classes(&t).unwrap().is_empty());
+        let _ = fs::remove_dir_all(&t.home);
+    }
+
+    #[test]
+    fn a_retried_acquire_keeps_what_it_added() {
+        let t = temp_target("retry");
+        let path = t.home.join("run").join("grants");
+        let always = |_: u32, _: u64| true;
+        let other = acquire_in(&path, &t, "Other", (30, 1), always).unwrap();
+
+        // Change the grants file under the first attempt so it is redone.
+        let touched = RefCell::new(false);
+        let alive = |_: u32, _: u64| {
+            if !touched.replace(true) {
+                let text = fs::read_to_string(&path).unwrap();
+                fs::write(&path, format!("{}junk\n", text)).unwrap();
+            }
+            true
+        };
+        let g = acquire_in(&path, &t, "Retried", (31, 1), alive).unwrap();
+        assert!(*touched.borrow());
+        assert!(g.added);
+        assert_eq!(get_classes(&t).unwrap(), ["Other", "Retried"]);
+
+        assert_eq!(release_in(&path, &t, &g, always).unwrap(), ["Retried"]);
+        assert_eq!(release_in(&path, &t, &other, always).unwrap(), ["Other"]);
+        assert!(get_classes(&t).unwrap().is_empty());
+        let _ = fs::remove_dir_all(&t.home);
+    }
+
+    #[test]
+    fn our_own_process_is_alive() {
+        let pid = std::process::id();
+        let start = start_time(pid).unwrap();
+        assert!(is_alive(pid, start));
+        assert!(!is_alive(pid, start + 1));
+    }
+}
@@ -0,0 +1,366 @@
+// -------------------------------
+// Undo history for kwinrc edits
+// -------------------------------
+// Every kwinrc write that changes the helper's settings first saves what they
+// were: all of `[Script-kwin-focus-helper]` plus the `[Plugins]` enabled
+// flag, with the time, the focusctl command line and the invoking user. The
+// last `HISTORY_LIMIT` snapshots are kept, oldest first, in
+//
+//   ~/.config/focusctl/history.json
+//
+// Restoring one writes it back through `config::update_document`, so a restore
+// is itself recorded and can be undone. `undo` instead consumes the newest
+// snapshot, so repeated undos keep walking back.
+
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{
+    GROUP_NAME, KEY_NAME, PLUGINS_GROUP, RULES_KEY, config_dir_for, enabled_key, parse_classes,
+    update_document, update_document_untracked,
+};
+use crate::fileops;
+use crate::json::{self, Json};
+use crate::kconfig::Document;
+use crate::rules::parse_rules;
+use crate::target::{self, Target};
+
+/// How many snapshots are kept.
+pub const HISTORY_LIMIT: usize = 20;
+
+/// The helper's settings as they were before one change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    /// The focusctl arguments that made the change.
+    pub command: String,
+    pub user: String,
+    pub uid: u32,
+    /// Every key in `[Script-kwin-focus-helper]`, raw.
+    pub settings: Vec<(String, String)>,
+    /// Raw `[Plugins] kwin-focus-helperEnabled`.
+    pub enabled: Option<String>,
+}
+
+impl Snapshot {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.settings
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// "3 class(es), 1 rule(s), enabled=true"
+    pub fn summary(&self) -> String {
+        let classes = self.get(KEY_NAME).map(parse_classes).unwrap_or_default();
+        let rules = self.get(RULES_KEY).map(parse_rules).unwrap_or_default();
+        format!(
+            "{} class(es), {} rule(s), enabled={}",
+            classes.len(),
+            rules.len(),
+            self.enabled.as_deref().unwrap_or("(unset)")
+        )
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::obj(vec![
+            ("time", Json::Num(self.time as f64)),
+            ("command", Json::from(self.command.as_str())),
+            ("user", Json::from(self.user.as_str())),
+            ("uid", Json::from(self.uid)),
+            (
+                "settings",
+                Json::Obj(
+                    self.settings
+                        .iter()
+                        .map(|(k, v)| (k.clone(), Json::from(v.as_str())))
+                        .collect(),
+                ),
+            ),
+            ("enabled", Json::from(self.enabled.as_deref())),
+        ])
+    }
+
+    fn from_json(v: &Json) -> Option<Snapshot> {
+        let settings = match v.get("settings")? {
+            Json::Obj(kv) => kv
+                .iter()
+                .map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                .collect::<Option<Vec<_>>>()?,
+            _ => return None,
+        };
+        Some(Snapshot {
+            time: v.get("time")?.as_f64()? as u64,
+            command: v.str_or_empty("command"),
+            user: v.str_or_empty("user"),
+            uid: v.get("uid").and_then(Json::as_f64).unwrap_or(0.0) as u32,
+            settings,
+            enabled: v.get("enabled").and_then(Json::as_str).map(str::to_string),
+        })
+    }
+}
+
+pub fn history_path_for(target: &Target) -> PathBuf {
+    config_dir_for(target).join("focusctl").join("history.json")
+}
+
+/// The helper's part of a kwinrc document.
+fn helper_state(doc: &Document) -> (Vec<(String, String)>, Option<String>) {
+    let settings = doc
+        .keys(GROUP_NAME)
+        .into_iter()
+        .filter_map(|k| doc.get(GROUP_NAME, &k).map(|v| (k, v)))
+        .collect();
+    (settings, doc.get(PLUGINS_GROUP, &enabled_key()))
+}
+
+/// Snapshots, oldest first. A missing or unreadable file is an empty history.
+fn parse_ring(text: &str) -> Vec<Snapshot> {
+    json::parse(text)
+        .ok()
+        .and_then(|v| {
+            v.get("snapshots")?
+                .as_array()
+                .map(|a| a.iter().filter_map(Snapshot::from_json).collect())
+        })
+        .unwrap_or_default()
+}
+
+fn read_ring(target: &Target) -> Vec<Snapshot> {
+    fileops::as_target(target, || fileops::read(&history_path_for(target)))
+        .map(|text| parse_ring(&text))
+        .unwrap_or_default()
+}
+
+/// Locked read-modify-write of the history file; `edit` returns whether it
+/// changed the ring.
+fn update_ring(
+    target: &Target,
+    mut edit: impl FnMut(&mut Vec<Snapshot>) -> bool,
+) -> io::Result<bool> {
+    fileops::as_target(target, || {
+        fileops::update(&history_path_for(target), |text| {
+            let mut ring = parse_ring(text.unwrap_or(""));
+            if !edit(&mut ring) {
+                return Ok(None);
+            }
+            let doc = Json::obj(vec![(
+                "snapshots",
+                Json::Arr(ring.iter().map(Snapshot::to_json).collect()),
+            )]);
+            Ok(Some(format!("{}\n", doc)))
+        })
+    })
+}
+
+/// `focusctl` arguments as typed, quoted where a shell would need it.
+fn command_line() -> String {
+    env::args()
+        .skip(1)
+        .map(|a| {
+            if !a.is_empty() && !a.contains(|c: char| c.is_whitespace() || "'\";|&$*?".contains(c))
+            {
+                a
+            } else {
+                format!("'{}'", a.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Save `before`'s helper settings if `after` changes them. Called by
+/// `config::update_document` once it has replaced kwinrc.
+pub(crate) fn record(target: &Target, before: &Document, after: &Document) -> io::Result<()> {
+    let state = helper_state(before);
+    if state == helper_state(after) {
+        return Ok(());
+    }
+    let (settings, enabled) = state;
+
+    let me = target::current_target();
+    let snap = Snapshot {
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        command: command_line(),
+        user: me.user,
+        uid: me.uid,
+        settings,
+        enabled,
+    };
+    update_ring(target, |ring| {
+        ring.push(snap.clone());
+        let excess = ring.len().saturating_sub(HISTORY_LIMIT);
+        ring.drain(..excess);
+        true
+    })?;
+    Ok(())
+}
+
+/// Snapshots, newest first: `restore(target, 1)` is the most recent.
+pub fn list_snapshots(target: &Target) -> Vec<Snapshot> {
+    let mut ring = read_ring(target);
+    ring.reverse();
+    ring
+}
+
+/// Replace the helper's settings in `doc` with the snapshot's (other groups
+/// and keys untouched). Returns whether that changed anything.
+fn apply(doc: &mut Document, snap: &Snapshot) -> io::Result<bool> {
+    let before = helper_state(doc);
+    for k in doc.keys(GROUP_NAME) {
+        if snap.get(&k).is_none() {
+            doc.remove(GROUP_NAME, &k)?;
+        }
+    }
+    for (k, v) in &snap.settings {
+        doc.set(GROUP_NAME, k, v)?;
+    }
+    match &snap.enabled {
+        Some(v) => doc.set(PLUGINS_GROUP, &enabled_key(), v)?,
+        None => doc.remove(PLUGINS_GROUP, &enabled_key())?,
+    };
+    Ok(helper_state(doc) != before)
+}
+
+fn nth(target: &Target, n: usize) -> io::Result<Snapshot> {
+    list_snapshots(target)
+        .into_iter()
+        .nth(n.wrapping_sub(1))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no snapshot {}", n)))
+}
+
+/// Write snapshot `n` (1 = newest) back to kwinrc. The overwritten state is
+/// recorded like any other change. Returns the snapshot and whether kwinrc
+/// changed.
+pub fn restore(target: &Target, n: usize) -> io::Result<(Snapshot, bool)> {
+    let snap = nth(target, n)?;
+    let changed = update_document(target, |doc| apply(doc, &snap))?;
+    Ok((snap, changed))
+}
+
+/// Restore the newest snapshot and drop it from the history, so the next
+/// undo goes one step further back. Returns the snapshot and whether kwinrc
+/// changed.
+pub fn undo(target: &Target) -> io::Result<(Snapshot, bool)> {
+    let snap = nth(target, 1)?;
+    let changed = update_document_untracked(target, |doc| apply(doc, &snap))?;
+    update_ring(target, |ring| match ring.iter().rposition(|s| *s == snap) {
+        Some(i) => {
+            ring.remove(i);
+            true
+        }
+        None => false,
+    })?;
+    Ok((snap, changed))
+}
+
+/// `2026-10-16 09:30:00 UTC`
+pub fn format_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Howard Hinnant's days_from_civil, inverted.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        y,
+        m,
+        d,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::config::{add_class, get_classes, get_enabled, set_classes, set_enabled};
+
+    fn temp_target(tag: &str) -> Target {
+        let home =
+            std::env::temp_dir().join(format!("focusctl-history-{}-{}", tag, std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        fs::create_dir_all(home.join(".config")).unwrap();
+        Target {
+            uid: 0,
+            user: "test".into(),
+            home,
+        }
+    }
+
+    fn list(s: &str) -> Vec<String> {
+        parse_classes(s)
+    }
+
+    #[test]
+    fn times_are_formatted_in_utc() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_time(951_825_600), "2000-02-29 12:00:00 UTC");
+        assert_eq!(format_time(1_791_624_600), "2026-10-10 09:30:00 UTC");
+    }
+
+    #[test]
+    fn changes_are_recorded_and_undone_in_order() {
+        let t = temp_target("undo");
+        set_classes(&t, &list("a;b")).unwrap();
+        set_enabled(&t, true).unwrap();
+        add_class(&t, "c").unwrap();
+        // Unchanged writes are not recorded.
+        set_enabled(&t, true).unwrap();
+
+        let snaps = list_snapshots(&t);
+        assert_eq!(snaps.len(), 3);
+        assert_eq!(snaps[0].get(KEY_NAME), Some("a;b"));
+        assert_eq!(snaps[0].enabled.as_deref(), Some("true"));
+        assert_eq!(snaps[2].settings, []);
+
+        assert!(undo(&t).unwrap().1);
+        assert_eq!(get_classes(&t).unwrap(), ["a", "b"]);
+        assert!(undo(&t).unwrap().1);
+        assert_eq!(get_enabled(&t).unwrap(), None);
+        assert!(undo(&t).unwrap().1);
+        assert_eq!(get_classes(&t).unwrap(), Vec::<String>::new());
+        assert_eq!(undo(&t).unwrap_err().kind(), io::ErrorKind::NotFound);
+        let _ = fs::remove_dir_all(&t.home);
+    }
+
+    #[test]
+    fn restore_is_recorded_and_the_ring_is_bounded() {
+        let t = temp_target("restore");
+        for i in 0..HISTORY_LIMIT + 5 {
+            set_classes(&t, &[format!("c{}", i)]).unwrap();
+        }
+        let snaps = list_snapshots(&t);
+        assert_eq!(snaps.len(), HISTORY_LIMIT);
+        assert_eq!(
+            snaps[0].get(KEY_NAME),
+            Some(format!("c{}", HISTORY_LIMIT + 3).as_str())
+        );
+
+        let (snap, changed) = restore(&t, 3).unwrap();
+        assert!(changed);
+        assert_eq!(get_classes(&t).unwrap(), list(snap.get(KEY_NAME).unwrap()));
+        // The state restore replaced is now the newest snapshot.
+        assert_eq!(
+            list_snapshots(&t)[0].get(KEY_NAME),
+            Some(format!("c{}", HISTORY_LIMIT + 4).as_str())
+        );
+        assert_eq!(restore(&t, 0).unwrap_err().kind(), io::ErrorKind::NotFound);
+        let _ = fs::remove_dir_all(&t.home);
+    }
+}
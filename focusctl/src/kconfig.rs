@@ -0,0 +1,680 @@
+// -------------------------------
+// KConfig-compatible INI document
+// -------------------------------
+// A small document model for kwinrc and friends. It understands the parts of
+// the KConfig INI dialect that matter for safe editing:
+//
+// - `[Group]` and nested `[Group][Sub]` headers (plus `[$i]` immutability)
+// - `key=value` with whitespace around `=`
+// - `key[locale]=...`, `key[$e]=...`, `key[$i]=...` option markers
+// - value escapes (`\s`, `\t`, `\n`, `\r`, `\\`, `\xNN`; `\;`/`\,` kept as-is)
+// - duplicate groups (KConfig merges them; the last entry wins)
+//
+// Every line keeps its original bytes. Serializing an untouched document gives
+// back the exact input; edits only rewrite (or insert/remove) the lines they
+// touch.
+
+use std::fmt;
+use std::io;
+
+/// Separator KConfig uses internally for nested group names.
+const NESTED_SEP: char = '\x1d';
+
+#[derive(Clone, Debug)]
+enum Line {
+    /// `[Group]`, `[Group][Sub]`, optionally followed by `[$i]`.
+    Group {
+        raw: String,
+        name: String,
+        immutable: bool,
+    },
+    /// `key[locale][$flags]=value`
+    Entry {
+        raw: String,
+        key: String,
+        locale: Option<String>,
+        immutable: bool,
+        expand: bool,
+        value: String,
+    },
+    /// Blank lines, comments, and lines KConfig would ignore.
+    Other { raw: String },
+}
+
+impl Line {
+    fn raw(&self) -> &str {
+        match self {
+            Line::Group { raw, .. } | Line::Entry { raw, .. } | Line::Other { raw } => raw,
+        }
+    }
+}
+
+/// Parsed KConfig file. Round-trips byte-for-byte.
+#[derive(Clone, Debug, Default)]
+pub struct Document {
+    lines: Vec<Line>,
+    /// `[$i]` before the first group: the whole file is immutable.
+    file_immutable: bool,
+}
+
+impl Document {
+    pub fn parse(text: &str) -> Document {
+        let mut doc = Document::default();
+        let mut cur_group = String::new();
+        let mut cur_group_immutable = false;
+
+        for raw in text.split_inclusive('\n') {
+            let trimmed = raw.trim();
+
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                doc.lines.push(Line::Other {
+                    raw: raw.to_string(),
+                });
+                continue;
+            }
+
+            if trimmed.starts_with('[') {
+                match parse_group_header(trimmed) {
+                    Some((names, immutable)) if names.is_empty() => {
+                        // A bare `[$i]` marker.
+                        if immutable && cur_group.is_empty() {
+                            doc.file_immutable = true;
+                        }
+                        doc.lines.push(Line::Other {
+                            raw: raw.to_string(),
+                        });
+                    }
+                    Some((names, immutable)) => {
+                        cur_group = names.join(&NESTED_SEP.to_string());
+                        cur_group_immutable = immutable;
+                        doc.lines.push(Line::Group {
+                            raw: raw.to_string(),
+                            name: cur_group.clone(),
+                            immutable,
+                        });
+                    }
+                    None => doc.lines.push(Line::Other {
+                        raw: raw.to_string(),
+                    }),
+                }
+                continue;
+            }
+
+            match parse_entry(trimmed) {
+                Some(mut e) => {
+                    if let Line::Entry {
+                        immutable, raw: r, ..
+                    } = &mut e
+                    {
+                        *immutable |= cur_group_immutable;
+                        *r = raw.to_string();
+                    }
+                    doc.lines.push(e);
+                }
+                None => doc.lines.push(Line::Other {
+                    raw: raw.to_string(),
+                }),
+            }
+        }
+
+        doc
+    }
+
+    /// Effective (last) unlocalized value of `key` in `group`, unescaped.
+    pub fn get(&self, group: &str, key: &str) -> Option<String> {
+        self.find_entry(group, key).map(|i| match &self.lines[i] {
+            Line::Entry { value, .. } => value.clone(),
+            _ => unreachable!(),
+        })
+    }
+
+    /// All unlocalized keys present in `group`, in first-seen order.
+    pub fn keys(&self, group: &str) -> Vec<String> {
+        let mut out: Vec<String> = Vec::new();
+        for (g, l) in self.walk() {
+            if g != group {
+                continue;
+            }
+            if let Line::Entry {
+                key, locale: None, ..
+            } = l
+                && !out.contains(key)
+            {
+                out.push(key.clone());
+            }
+        }
+        out
+    }
+
+    /// Names of the groups nested directly under `parent` (`[parent][name]`),
+    /// in first-seen order. Pass them to `nested` to read their keys.
+    pub fn subgroups(&self, parent: &str) -> Vec<String> {
+        let prefix = nested(parent, "");
+        let mut out: Vec<String> = Vec::new();
+        for l in &self.lines {
+            if let Line::Group { name, .. } = l
+                && let Some(sub) = name.strip_prefix(&prefix)
+                && !sub.contains(NESTED_SEP)
+                && !out.iter().any(|o| o == sub)
+            {
+                out.push(sub.to_string());
+            }
+        }
+        out
+    }
+
+    /// Set `key=value` in `group`.
+    ///
+    /// - Updates the effective entry in place if present (other lines untouched).
+    /// - Otherwise appends the key to the last section of `group`.
+    /// - Otherwise appends a new `[group]` at the end of the file.
+    ///
+    /// Returns `Ok(false)` when the stored value already equals `value`.
+    pub fn set(&mut self, group: &str, key: &str, value: &str) -> io::Result<bool> {
+        self.check_writable(group, key)?;
+
+        if let Some(i) = self.find_entry(group, key) {
+            if let Line::Entry {
+                value: cur, expand, ..
+            } = &self.lines[i]
+                && cur == value
+                && !expand
+            {
+                return Ok(false);
+            }
+            let eol = line_ending(self.lines[i].raw());
+            self.lines[i] = entry_line(key, value, eol);
+            return Ok(true);
+        }
+
+        if let Some(at) = self.insert_point(group) {
+            self.ensure_newline_before(at);
+            self.lines.insert(at, entry_line(key, value, "\n"));
+            return Ok(true);
+        }
+
+        // New group at the end of the file.
+        let end = self.lines.len();
+        self.ensure_newline_before(end);
+        let last_blank = self
+            .lines
+            .last()
+            .map(|l| l.raw().trim().is_empty())
+            .unwrap_or(true);
+        if !last_blank {
+            self.lines.push(Line::Other {
+                raw: "\n".to_string(),
+            });
+        }
+        self.lines.push(Line::Group {
+            raw: format!("[{}]\n", escape_group(group)),
+            name: group.to_string(),
+            immutable: false,
+        });
+        self.lines.push(entry_line(key, value, "\n"));
+        Ok(true)
+    }
+
+    /// Remove every unlocalized `key` in `group` (including shadowed duplicates).
+    /// Returns whether anything was removed.
+    pub fn remove(&mut self, group: &str, key: &str) -> io::Result<bool> {
+        self.check_writable(group, key)?;
+
+        let mut drop = Vec::new();
+        for (i, (g, l)) in self.walk().enumerate() {
+            if g == group
+                && let Line::Entry {
+                    key: k,
+                    locale: None,
+                    ..
+                } = l
+                && k == key
+            {
+                drop.push(i);
+            }
+        }
+        for i in drop.iter().rev() {
+            self.lines.remove(*i);
+        }
+        Ok(!drop.is_empty())
+    }
+
+    /// Iterate lines together with the group each one belongs to.
+    fn walk(&self) -> impl Iterator<Item = (&str, &Line)> {
+        let mut cur: &str = "";
+        self.lines.iter().map(move |l| {
+            if let Line::Group { name, .. } = l {
+                cur = name.as_str();
+            }
+            (cur, l)
+        })
+    }
+
+    fn find_entry(&self, group: &str, key: &str) -> Option<usize> {
+        let mut found = None;
+        for (i, (g, l)) in self.walk().enumerate() {
+            if g == group
+                && let Line::Entry {
+                    key: k,
+                    locale: None,
+                    ..
+                } = l
+                && k == key
+            {
+                found = Some(i);
+            }
+        }
+        found
+    }
+
+    /// Index right after the last entry of the last `[group]` section.
+    fn insert_point(&self, group: &str) -> Option<usize> {
+        let mut at = None;
+        for (i, (g, l)) in self.walk().enumerate() {
+            if g != group {
+                continue;
+            }
+            match l {
+                Line::Group { .. } | Line::Entry { .. } => at = Some(i + 1),
+                Line::Other { .. } => {}
+            }
+        }
+        at
+    }
+
+    fn check_writable(&self, group: &str, key: &str) -> io::Result<()> {
+        if self.file_immutable {
+            return Err(immutable_error("file is marked immutable ([$i])"));
+        }
+        for (g, l) in self.walk() {
+            if g != group {
+                continue;
+            }
+            match l {
+                Line::Group {
+                    immutable: true, ..
+                } => {
+                    return Err(immutable_error(&format!(
+                        "group [{}] is marked immutable ([$i])",
+                        group
+                    )));
+                }
+                Line::Entry {
+                    key: k,
+                    locale: None,
+                    immutable: true,
+                    ..
+                } if k == key => {
+                    return Err(immutable_error(&format!(
+                        "{} in [{}] is marked immutable ([$i])",
+                        key, group
+                    )));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Make sure the line before `at` ends with a newline (last line of a file
+    /// without a trailing newline).
+    fn ensure_newline_before(&mut self, at: usize) {
+        if at == 0 {
+            return;
+        }
+        let prev = &mut self.lines[at - 1];
+        let raw = match prev {
+            Line::Group { raw, .. } | Line::Entry { raw, .. } | Line::Other { raw } => raw,
+        };
+        if !raw.ends_with('\n') {
+            raw.push('\n');
+        }
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for l in &self.lines {
+            f.write_str(l.raw())?;
+        }
+        Ok(())
+    }
+}
+
+fn immutable_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, msg.to_string())
+}
+
+fn line_ending(raw: &str) -> &'static str {
+    if raw.ends_with("\r\n") { "\r\n" } else { "\n" }
+}
+
+fn entry_line(key: &str, value: &str, eol: &str) -> Line {
+    Line::Entry {
+        raw: format!("{}={}{}", key, escape_value(value), eol),
+        key: key.to_string(),
+        locale: None,
+        immutable: false,
+        expand: false,
+        value: value.to_string(),
+    }
+}
+
+/// `[A][B][$i]` -> (["A", "B"], true). `[$i]` alone -> ([], true).
+fn parse_group_header(s: &str) -> Option<(Vec<String>, bool)> {
+    let mut names = Vec::new();
+    let mut immutable = false;
+    let mut rest = s;
+
+    while !rest.is_empty() {
+        let inner = rest.strip_prefix('[')?;
+        let end = inner.find(']')?;
+        let name = &inner[..end];
+        rest = &inner[end + 1..];
+
+        if name == "$i" {
+            immutable = true;
+        } else {
+            names.push(unescape(name));
+        }
+    }
+
+    Some((names, immutable))
+}
+
+/// `key[de_DE][$e] = value` -> Entry.
+fn parse_entry(s: &str) -> Option<Line> {
+    let eq = s.find('=')?;
+    let lhs = s[..eq].trim();
+    let value = unescape(s[eq + 1..].trim());
+
+    let (key, opts) = match lhs.find('[') {
+        Some(b) => (lhs[..b].trim_end(), &lhs[b..]),
+        None => (lhs, ""),
+    };
+    if key.is_empty() {
+        return None;
+    }
+
+    let mut locale = None;
+    let mut immutable = false;
+    let mut expand = false;
+    let mut rest = opts;
+    while let Some(inner) = rest.strip_prefix('[') {
+        let end = inner.find(']')?;
+        let opt = &inner[..end];
+        rest = &inner[end + 1..];
+        if let Some(flags) = opt.strip_prefix('$') {
+            immutable |= flags.contains('i');
+            expand |= flags.contains('e');
+        } else {
+            locale = Some(opt.to_string());
+        }
+    }
+
+    Some(Line::Entry {
+        raw: String::new(),
+        key: key.to_string(),
+        locale,
+        immutable,
+        expand,
+        value,
+    })
+}
+
+/// KConfig `printableToString`: decode `\s \t \n \r \\ \xNN`.
+/// `\;` and `\,` are list escapes and stay in the string. `\xNN` are
+/// bytes, so a multi-byte character may be written as several of them.
+fn unescape(s: &str) -> String {
+    let mut out = Vec::with_capacity(s.len());
+    let push = |out: &mut Vec<u8>, c: char| {
+        out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+    };
+    let mut it = s.chars();
+    while let Some(c) = it.next() {
+        if c != '\\' {
+            push(&mut out, c);
+            continue;
+        }
+        match it.next() {
+            Some('s') => out.push(b' '),
+            Some('t') => out.push(b'\t'),
+            Some('n') => out.push(b'\n'),
+            Some('r') => out.push(b'\r'),
+            Some('\\') => out.push(b'\\'),
+            Some('x') => {
+                let hex: String = it.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(b) => out.push(b),
+                    Err(_) => {
+                        out.extend_from_slice(b"\\x");
+                        out.extend_from_slice(hex.as_bytes());
+                    }
+                }
+            }
+            Some(other) => {
+                out.push(b'\\');
+                push(&mut out, other);
+            }
+            None => out.push(b'\\'),
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// KConfig `stringToPrintable` for values.
+fn escape_value(s: &str) -> String {
+    let chars: Vec<char> = s.chars().collect();
+    let mut out = String::with_capacity(s.len());
+    for (i, &c) in chars.iter().enumerate() {
+        match c {
+            ' ' if i == 0 || i + 1 == chars.len() => out.push_str("\\s"),
+            // Keep list escapes (`\;`, `\,`) as KConfig does.
+            '\\' if matches!(chars.get(i + 1), Some(';') | Some(',')) => out.push('\\'),
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Internal name of the group `[parent][name]`, for `get` and `set`.
+pub fn nested(parent: &str, name: &str) -> String {
+    format!("{}{}{}", parent, NESTED_SEP, name)
+}
+
+fn escape_group(name: &str) -> String {
+    name.split(NESTED_SEP)
+        .map(|part| part.replace('[', "\\x5b").replace(']', "\\x5d"))
+        .collect::<Vec<_>>()
+        .join("][")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLASMA6: &str = include_str!("../tests/fixtures/kwinrc-plasma6");
+    const HAND_EDITED: &str = include_str!("../tests/fixtures/kwinrc-hand-edited");
+
+    #[test]
+    fn round_trip_is_byte_exact() {
+        for sample in [
+            PLASMA6,
+            HAND_EDITED,
+            "",
+            "no newline at eof",
+            "[A]\r\nk=v\r\n",
+        ] {
+            assert_eq!(Document::parse(sample).to_string(), sample);
+        }
+    }
+
+    #[test]
+    fn reads_real_world_values() {
+        let doc = Document::parse(PLASMA6);
+        assert_eq!(
+            doc.get("Plugins", "kwin-focus-helperEnabled").as_deref(),
+            Some("true")
+        );
+        assert_eq!(
+            doc.get("Script-kwin-focus-helper", "forceFocusClasses")
+                .as_deref(),
+            Some("google-chrome;ProcletChrome")
+        );
+        assert_eq!(
+            doc.get("Windows", "FocusStealingPreventionLevel")
+                .as_deref(),
+            Some("2")
+        );
+        assert_eq!(doc.get("Missing", "x"), None);
+    }
+
+    #[test]
+    fn understands_hand_edits() {
+        let doc = Document::parse(HAND_EDITED);
+        // Whitespace around '=' and a later duplicate group: last one wins.
+        assert_eq!(
+            doc.get("Script-kwin-focus-helper", "forceFocusClasses")
+                .as_deref(),
+            Some("firefox; org.kde.dolphin")
+        );
+        // Escapes are decoded.
+        assert_eq!(
+            doc.get("Script-kwin-focus-helper", "note").as_deref(),
+            Some(" a\tb\n")
+        );
+        // Localized keys don't shadow the plain key.
+        assert_eq!(doc.get("Desktops", "Name_1").as_deref(), Some("Main"));
+        assert_eq!(doc.get("Desktops", "Id_1").as_deref(), Some("$HOME/x"));
+    }
+
+    #[test]
+    fn set_touches_only_the_effective_entry() {
+        let mut doc = Document::parse(HAND_EDITED);
+        assert!(
+            doc.set("Script-kwin-focus-helper", "forceFocusClasses", "a;b")
+                .unwrap()
+        );
+        let out = doc.to_string();
+
+        // Exactly one line changed.
+        let before: Vec<&str> = HAND_EDITED.lines().collect();
+        let after: Vec<&str> = out.lines().collect();
+        assert_eq!(before.len(), after.len());
+        let changed: Vec<_> = before.iter().zip(&after).filter(|(a, b)| a != b).collect();
+        assert_eq!(changed.len(), 1);
+        assert_eq!(*changed[0].1, "forceFocusClasses=a;b");
+
+        // Still exactly two sections of the group (no new one appended).
+        assert_eq!(out.matches("[Script-kwin-focus-helper]").count(), 2);
+    }
+
+    #[test]
+    fn set_is_noop_when_unchanged() {
+        let mut doc = Document::parse(PLASMA6);
+        assert!(
+            !doc.set("Plugins", "kwin-focus-helperEnabled", "true")
+                .unwrap()
+        );
+        assert_eq!(doc.to_string(), PLASMA6);
+    }
+
+    #[test]
+    fn set_inserts_into_existing_group() {
+        let mut doc = Document::parse("[A]\nx=1\n\n[B]\ny=2\n");
+        doc.set("A", "z", "3").unwrap();
+        assert_eq!(doc.to_string(), "[A]\nx=1\nz=3\n\n[B]\ny=2\n");
+    }
+
+    #[test]
+    fn set_appends_new_group() {
+        let mut doc = Document::parse("[A]\nx=1");
+        doc.set("Script-kwin-focus-helper", "forceFocusClasses", "chrome")
+            .unwrap();
+        assert_eq!(
+            doc.to_string(),
+            "[A]\nx=1\n\n[Script-kwin-focus-helper]\nforceFocusClasses=chrome\n"
+        );
+
+        let mut empty = Document::parse("");
+        empty.set("Plugins", "k", "true").unwrap();
+        assert_eq!(empty.to_string(), "[Plugins]\nk=true\n");
+    }
+
+    #[test]
+    fn set_escapes_values() {
+        let mut doc = Document::parse("");
+        doc.set("A", "k", " lead\ttab\\back ").unwrap();
+        assert_eq!(doc.to_string(), "[A]\nk=\\slead\\ttab\\\\back\\s\n");
+        let again = Document::parse(&doc.to_string());
+        assert_eq!(again.get("A", "k").as_deref(), Some(" lead\ttab\\back "));
+    }
+
+    #[test]
+    fn hex_escapes_are_utf8_bytes() {
+        let doc = Document::parse("[A]\nk=caf\\xc3\\xa9 \\x01\\xff\\xzz\n");
+        assert_eq!(
+            doc.get("A", "k").as_deref(),
+            Some("café \u{1}\u{fffd}\\xzz")
+        );
+
+        let mut doc = Document::parse("");
+        doc.set("A", "k", "é\u{1}").unwrap();
+        let again = Document::parse(&doc.to_string());
+        assert_eq!(again.get("A", "k").as_deref(), Some("é\u{1}"));
+    }
+
+    #[test]
+    fn remove_drops_all_duplicates() {
+        let mut doc = Document::parse(HAND_EDITED);
+        assert!(
+            doc.remove("Script-kwin-focus-helper", "forceFocusClasses")
+                .unwrap()
+        );
+        assert_eq!(
+            doc.get("Script-kwin-focus-helper", "forceFocusClasses"),
+            None
+        );
+        assert!(
+            !doc.remove("Script-kwin-focus-helper", "forceFocusClasses")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn immutable_groups_refuse_writes() {
+        let mut doc = Document::parse("[Locked][$i]\nk=v\n\n[Free]\nk[$i]=v\n");
+        assert!(doc.set("Locked", "k", "x").is_err());
+        assert!(doc.set("Free", "k", "x").is_err());
+        assert!(doc.set("Free", "other", "x").is_ok());
+
+        let mut file = Document::parse("[$i]\n[A]\nk=v\n");
+        assert!(file.set("A", "k", "x").is_err());
+    }
+
+    #[test]
+    fn nested_groups_are_distinct() {
+        let doc = Document::parse("[A][B]\nk=nested\n[A]\nk=top\n");
+        assert_eq!(doc.get("A", "k").as_deref(), Some("top"));
+        assert_eq!(doc.get("A\x1dB", "k").as_deref(), Some("nested"));
+        assert_eq!(doc.get(&nested("A", "B"), "k").as_deref(), Some("nested"));
+    }
+
+    #[test]
+    fn subgroups_lists_direct_children() {
+        let doc = Document::parse("[A][B]\nk=1\n[A][C][D]\nk=2\n[A]\n[A][B]\n[X][Y]\n");
+        assert_eq!(doc.subgroups("A"), vec!["B".to_string()]);
+        assert_eq!(doc.subgroups("A\x1dC"), vec!["D".to_string()]);
+    }
+
+    #[test]
+    fn keys_lists_unlocalized_keys() {
+        let doc = Document::parse("[G]\na=1\nb[de]=2\nb=3\na=4\n");
+        assert_eq!(doc.keys("G"), vec!["a".to_string(), "b".to_string()]);
+    }
+}
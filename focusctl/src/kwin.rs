@@ -0,0 +1,492 @@
+// -------------------------------
+// KWin scripting over D-Bus
+// -------------------------------
+// Some questions can only be answered from inside KWin (which windows exist,
+// what their classes are). We load a throwaway script through
+// org.kde.kwin.Scripting, let it report back to our own bus connection with
+// callDBus(), and unload it again.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::dbus::{Connection, Message, Value};
+use crate::fileops;
+use crate::json::{self, Json};
+use crate::target::Target;
+
+pub const KWIN_SERVICE: &str = "org.kde.KWin";
+const SCRIPTING_PATH: &str = "/Scripting";
+const SCRIPTING_IFACE: &str = "org.kde.kwin.Scripting";
+const SCRIPT_IFACE: &str = "org.kde.kwin.Script";
+
+/// Interface our temporary scripts call back on.
+const CALLBACK_IFACE: &str = "org.kde.focusctl";
+const CALLBACK_PATH: &str = "/focusctl";
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// `queryWindowInfo` only replies once the user has clicked (or pressed Esc).
+const PICK_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Error KWin returns when the interactive picker is cancelled.
+const USER_CANCEL: &str = "org.kde.KWin.Error.UserCancel";
+
+/// Placeholders in script sources, filled in by `run_script`.
+const SERVICE_PLACEHOLDER: &str = "__FOCUSCTL_SERVICE__";
+
+/// Reports every window as JSON through `report(json)`.
+const WINDOWS_SCRIPT: &str = r#"
+(function () {
+  function s(v) { try { return (v === undefined || v === null) ? "" : String(v); } catch (_) { return ""; } }
+  function b(v) { try { return !!v; } catch (_) { return false; } }
+  var list = [];
+  try {
+    list = (typeof workspace.windowList === "function") ? workspace.windowList() : workspace.clientList();
+  } catch (_) {}
+  var out = [];
+  for (var i = 0; i < list.length; i++) {
+    var w = list[i];
+    out.push({
+      caption: s(w.caption),
+      pid: Number(w.pid) || 0,
+      desktopFileName: s(w.desktopFileName),
+      resourceClass: s(w.resourceClass),
+      resourceName: s(w.resourceName),
+      normalWindow: b(w.normalWindow),
+      dialog: b(w.dialog)
+    });
+  }
+  callDBus(__FOCUSCTL_SERVICE__, "/focusctl", "org.kde.focusctl", "report", JSON.stringify(out));
+})();
+"#;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WindowInfo {
+    pub caption: String,
+    pub pid: u32,
+    pub desktop_file: String,
+    pub resource_class: String,
+    pub resource_name: String,
+    pub normal: bool,
+    pub dialog: bool,
+}
+
+impl WindowInfo {
+    fn from_json(v: &Json) -> WindowInfo {
+        WindowInfo {
+            caption: v.str_or_empty("caption"),
+            pid: v.get("pid").and_then(|p| p.as_f64()).unwrap_or(0.0) as u32,
+            desktop_file: v.str_or_empty("desktopFileName"),
+            resource_class: v.str_or_empty("resourceClass"),
+            resource_name: v.str_or_empty("resourceName"),
+            normal: v
+                .get("normalWindow")
+                .and_then(|b| b.as_bool())
+                .unwrap_or(false),
+            dialog: v.get("dialog").and_then(|b| b.as_bool()).unwrap_or(false),
+        }
+    }
+
+    /// Decode the `a{sv}` map returned by `queryWindowInfo`.
+    fn from_dict(v: &Value) -> WindowInfo {
+        let get = |key: &str| -> Option<&Value> {
+            let Value::Array(_, entries) = v else {
+                return None;
+            };
+            entries.iter().find_map(|e| match e {
+                Value::DictEntry(k, v) if k.as_str() == Some(key) => Some(&**v),
+                _ => None,
+            })
+        };
+        let text = |key: &str| {
+            get(key)
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        // NET::WindowType: 0 = Normal, 5 = Dialog.
+        let ty = get("type").and_then(|v| v.as_i32());
+        WindowInfo {
+            caption: text("caption"),
+            pid: 0,
+            desktop_file: text("desktopFile"),
+            resource_class: text("resourceClass"),
+            resource_name: text("resourceName"),
+            normal: ty == Some(0),
+            dialog: ty == Some(5),
+        }
+    }
+
+    /// The fields `windowCandidates()` in main.js looks at, best-first.
+    pub fn candidates(&self) -> [(&'static str, &str); 3] {
+        [
+            ("desktopFileName", self.desktop_file.as_str()),
+            ("resourceClass", self.resource_class.as_str()),
+            ("resourceName", self.resource_name.as_str()),
+        ]
+    }
+}
+
+/// Snapshot of all windows KWin currently manages; the helper script is
+/// written to `script_dir` as `target`.
+pub fn list_windows(
+    bus: &mut Connection,
+    target: &Target,
+    script_dir: &Path,
+) -> io::Result<Vec<WindowInfo>> {
+    let payload = run_script(bus, target, script_dir, WINDOWS_SCRIPT)?;
+    let v = json::parse(&payload)?;
+    Ok(v.as_array()
+        .unwrap_or_default()
+        .iter()
+        .map(WindowInfo::from_json)
+        .collect())
+}
+
+/// Whether KWin currently has the script `plugin` loaded.
+pub fn is_script_loaded(bus: &mut Connection, plugin: &str) -> io::Result<bool> {
+    let reply = bus.call(
+        KWIN_SERVICE,
+        SCRIPTING_PATH,
+        SCRIPTING_IFACE,
+        "isScriptLoaded",
+        vec![Value::Str(plugin.to_string())],
+    )?;
+    Ok(reply.first().and_then(|v| v.as_bool()).unwrap_or(false))
+}
+
+/// Let the user click a window (the crosshair used by the Window Rules KCM)
+/// and return what KWin knows about it. `None` if the pick was cancelled.
+pub fn query_window_info(bus: &mut Connection) -> io::Result<Option<WindowInfo>> {
+    bus.set_timeout(PICK_TIMEOUT)?;
+    let reply = bus.call(
+        KWIN_SERVICE,
+        "/KWin",
+        "org.kde.KWin",
+        "queryWindowInfo",
+        vec![],
+    );
+    bus.set_timeout(REPLY_TIMEOUT)?;
+    match reply {
+        Ok(body) => Ok(body.first().map(WindowInfo::from_dict)),
+        Err(e) if e.to_string().starts_with(USER_CANCEL) => Ok(None),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no window was picked in time",
+            ))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Load `source` as a temporary KWin script, run it, and return the string
+/// it passes to `callDBus(<us>, "/focusctl", "org.kde.focusctl", "report", s)`.
+/// The file is created fresh as `target` (`script_dir` is usually theirs), so
+/// a link planted at its name is not followed.
+pub fn run_script(
+    bus: &mut Connection,
+    target: &Target,
+    script_dir: &Path,
+    source: &str,
+) -> io::Result<String> {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let plugin = format!("focusctl-{}-{}", process::id(), stamp);
+    let path = script_dir.join(format!("{}.js", plugin));
+
+    let source = source.replace(SERVICE_PLACEHOLDER, &js_string(bus.unique_name()));
+    write_script(target, &path, &source)?;
+
+    let result = load_run_and_wait(bus, &path, &plugin);
+
+    let _ = bus.call(
+        KWIN_SERVICE,
+        SCRIPTING_PATH,
+        SCRIPTING_IFACE,
+        "unloadScript",
+        vec![Value::Str(plugin)],
+    );
+    let _ = fileops::as_target(target, || fs::remove_file(&path));
+    result
+}
+
+fn write_script(target: &Target, path: &Path, source: &str) -> io::Result<()> {
+    fileops::as_target(target, || {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)?
+            .write_all(source.as_bytes())
+    })
+}
+
+fn load_run_and_wait(bus: &mut Connection, path: &Path, plugin: &str) -> io::Result<String> {
+    let reply = bus.call(
+        KWIN_SERVICE,
+        SCRIPTING_PATH,
+        SCRIPTING_IFACE,
+        "loadScript",
+        vec![
+            Value::Str(path.display().to_string()),
+            Value::Str(plugin.to_string()),
+        ],
+    )?;
+    let id = reply.first().and_then(|v| v.as_i32()).unwrap_or(-1);
+    if id < 0 {
+        return Err(io::Error::other("KWin refused to load the helper script"));
+    }
+
+    // Plasma 6 exports scripts as /Scripting/Script<id>, Plasma 5 as /<id>.
+    let run6 = bus.call(
+        KWIN_SERVICE,
+        &format!("{}/Script{}", SCRIPTING_PATH, id),
+        SCRIPT_IFACE,
+        "run",
+        vec![],
+    );
+    if run6.is_err() {
+        bus.call(
+            KWIN_SERVICE,
+            &format!("/{}", id),
+            SCRIPT_IFACE,
+            "run",
+            vec![],
+        )?;
+    }
+
+    bus.set_timeout(REPLY_TIMEOUT)?;
+    loop {
+        let m = match bus.next_call() {
+            Ok(m) => m,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out waiting for the KWin helper script",
+                ));
+            }
+            Err(e) => return Err(e),
+        };
+        let ours = m.interface.as_deref() == Some(CALLBACK_IFACE)
+            && m.path.as_deref() == Some(CALLBACK_PATH);
+        bus.send(Message::method_return(&m, vec![]))?;
+        if ours {
+            return Ok(m
+                .body
+                .first()
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string());
+        }
+    }
+}
+
+/// Quote a string as a JS string literal.
+fn js_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbus::test_support::{own_uid, spawn_daemon};
+    use crate::dbus::{BUS_NAME, BUS_PATH};
+    use crate::target::current_target;
+    use crate::wrap::test_support::in_own_process;
+    use std::thread;
+
+    /// Minimal org.kde.KWin: loads a script, "runs" it by calling back the
+    /// service named in the source with `payload`, then unloads it.
+    fn stub_kwin(address: &str, payload: &'static str) -> thread::JoinHandle<Vec<String>> {
+        let mut kwin = Connection::open_address(address, own_uid()).unwrap();
+        kwin.call(
+            BUS_NAME,
+            BUS_PATH,
+            BUS_NAME,
+            "RequestName",
+            vec![Value::Str(KWIN_SERVICE.into()), Value::U32(4)],
+        )
+        .unwrap();
+
+        thread::spawn(move || {
+            let mut seen = Vec::new();
+            let mut script = String::new();
+            loop {
+                let m = kwin.next_call().unwrap();
+                let member = m.member.clone().unwrap_or_default();
+                seen.push(format!("{} {}", m.path.clone().unwrap_or_default(), member));
+                match member.as_str() {
+                    "loadScript" => {
+                        script = fs::read_to_string(m.body[0].as_str().unwrap()).unwrap();
+                        kwin.send(Message::method_return(&m, vec![Value::I32(3)]))
+                            .unwrap();
+                    }
+                    "run" => {
+                        kwin.send(Message::method_return(&m, vec![])).unwrap();
+                        let service = script.split("callDBus(\"").nth(1).unwrap();
+                        let service = &service[..service.find('"').unwrap()];
+                        kwin.call(
+                            service,
+                            CALLBACK_PATH,
+                            CALLBACK_IFACE,
+                            "report",
+                            vec![Value::Str(payload.into())],
+                        )
+                        .unwrap();
+                    }
+                    "unloadScript" => {
+                        kwin.send(Message::method_return(&m, vec![Value::Bool(true)]))
+                            .unwrap();
+                        return seen;
+                    }
+                    _ => {}
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn scripts_are_created_fresh_as_the_target() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        // Switching to another uid changes every thread's credentials.
+        if in_own_process("kwin::tests::scripts_are_created_fresh_as_the_target") {
+            return;
+        }
+
+        let dir = std::env::temp_dir().join(format!("focusctl-kwin-script-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+        let victim = dir.join("victim");
+        fs::write(&victim, "keep").unwrap();
+        std::os::unix::fs::symlink(&victim, dir.join("planted.js")).unwrap();
+
+        let me = current_target();
+        let err = write_script(&me, &dir.join("planted.js"), "x").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&victim).unwrap(), "keep");
+
+        // Root writing for someone else leaves them owning the file.
+        let nobody = Target {
+            uid: 65534,
+            user: "nobody".into(),
+            home: dir.clone(),
+        };
+        let target = if me.uid == 0 { &nobody } else { &me };
+        write_script(target, &dir.join("ok.js"), "x").unwrap();
+        assert_eq!(fs::metadata(dir.join("ok.js")).unwrap().uid(), target.uid);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn list_windows_through_temporary_script() {
+        let Some(daemon) = spawn_daemon("kwin") else {
+            eprintln!("dbus-daemon not available; skipping");
+            return;
+        };
+        let stub = stub_kwin(
+            &daemon.address,
+            r#"[{"caption":"Inbox","pid":7,"desktopFileName":"org.kde.kmail2",
+                "resourceClass":"kmail","resourceName":"kmail","normalWindow":true}]"#,
+        );
+
+        let dir = std::env::temp_dir();
+        let mut bus = Connection::open_address(&daemon.address, own_uid()).unwrap();
+        let windows = list_windows(&mut bus, &current_target(), &dir).unwrap();
+
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[0].desktop_file, "org.kde.kmail2");
+        assert_eq!(windows[0].pid, 7);
+        assert_eq!(
+            stub.join().unwrap(),
+            vec![
+                "/Scripting loadScript",
+                "/Scripting/Script3 run",
+                "/Scripting unloadScript"
+            ]
+        );
+
+        // The temporary script file is gone again.
+        let leftovers = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| {
+                e.file_name()
+                    .to_string_lossy()
+                    .starts_with(&format!("focusctl-{}-", process::id()))
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn window_json_is_decoded() {
+        let v = json::parse(
+            r#"[{"caption":"New Tab","pid":4242,"desktopFileName":"google-chrome",
+                "resourceClass":"Google-chrome","resourceName":"google-chrome",
+                "normalWindow":true,"dialog":false}]"#,
+        )
+        .unwrap();
+        let w = WindowInfo::from_json(&v.as_array().unwrap()[0]);
+        assert_eq!(w.pid, 4242);
+        assert_eq!(w.resource_class, "Google-chrome");
+        assert!(w.normal && !w.dialog);
+        assert_eq!(w.candidates()[0], ("desktopFileName", "google-chrome"));
+    }
+
+    #[test]
+    fn query_window_info_map_is_decoded() {
+        let entry = |k: &str, v: Value| {
+            Value::DictEntry(
+                Box::new(Value::Str(k.into())),
+                Box::new(Value::Variant(Box::new(v))),
+            )
+        };
+        let dict = Value::Array(
+            "{sv}".into(),
+            vec![
+                entry("caption", Value::Str("Proclet — Chrome".into())),
+                entry("desktopFile", Value::Str("".into())),
+                entry("resourceClass", Value::Str("ProcletChrome".into())),
+                entry("resourceName", Value::Str("google-chrome".into())),
+                entry("type", Value::I32(5)),
+                entry("width", Value::I32(800)),
+            ],
+        );
+        let w = WindowInfo::from_dict(&dict);
+        assert_eq!(w.caption, "Proclet — Chrome");
+        assert_eq!(w.desktop_file, "");
+        assert_eq!(w.candidates()[1], ("resourceClass", "ProcletChrome"));
+        assert!(w.dialog && !w.normal);
+    }
+
+    #[test]
+    fn js_strings_are_quoted() {
+        assert_eq!(js_string(":1.42"), "\":1.42\"");
+        assert_eq!(js_string("a\"b\\\n"), "\"a\\\"b\\\\\\u000a\"");
+    }
+}
@@ -0,0 +1,349 @@
+// -------------------------------
+// Launcher wrappers
+// -------------------------------
+// Sandboxes and launchers put themselves in front of the real program:
+//
+//   firejail --profile=x chromium
+//   bwrap --ro-bind / / --dev /dev -- /usr/bin/foo
+//   flatpak run org.mozilla.firefox
+//   proclet run -- google-chrome-stable
+//
+// `unwrap` skips past each known launcher's subcommand and options to the
+// program it starts, or for Flatpak to the app id (already the window's
+// desktop file name), so `wrap --auto` names the app and not the sandbox.
+// Besides the built-in table, launchers are read from the focusctl config:
+//
+//   ~/.config/focusctl/config
+//
+//   [Launchers][proclet]
+//   subcommand=run
+//   options=--profile;--bind:2
+//   app-id=false
+//
+// `options` lists the options that take separate values (`:N` when there is
+// more than one); any other `-x`, `--xyz` or `--xyz=value` is skipped alone,
+// and `--` ends the options. With a `subcommand`, only that one runs a
+// program (`flatpak install ...` is left alone). An entry for a built-in
+// name replaces it.
+
+use std::io;
+use std::path::PathBuf;
+
+use crate::config::config_dir_for;
+use crate::fileops;
+use crate::kconfig::{Document, nested};
+use crate::target::Target;
+
+const LAUNCHERS_GROUP: &str = "Launchers";
+
+/// Launchers inside launchers (`firejail bwrap ...`) are unwrapped up to
+/// this depth.
+const MAX_DEPTH: usize = 8;
+
+/// bwrap options and how many values follow each.
+const BWRAP_OPTIONS: &[(&str, usize)] = &[
+    ("--args", 1),
+    ("--argv0", 1),
+    ("--userns", 1),
+    ("--userns2", 1),
+    ("--pidns", 1),
+    ("--uid", 1),
+    ("--gid", 1),
+    ("--hostname", 1),
+    ("--chdir", 1),
+    ("--unsetenv", 1),
+    ("--lock-file", 1),
+    ("--sync-fd", 1),
+    ("--remount-ro", 1),
+    ("--exec-label", 1),
+    ("--file-label", 1),
+    ("--proc", 1),
+    ("--dev", 1),
+    ("--tmpfs", 1),
+    ("--mqueue", 1),
+    ("--dir", 1),
+    ("--seccomp", 1),
+    ("--add-seccomp-fd", 1),
+    ("--block-fd", 1),
+    ("--userns-block-fd", 1),
+    ("--info-fd", 1),
+    ("--json-status-fd", 1),
+    ("--cap-add", 1),
+    ("--cap-drop", 1),
+    ("--perms", 1),
+    ("--size", 1),
+    ("--overlay-src", 1),
+    ("--tmp-overlay", 1),
+    ("--ro-overlay", 1),
+    ("--setenv", 2),
+    ("--bind", 2),
+    ("--bind-try", 2),
+    ("--dev-bind", 2),
+    ("--dev-bind-try", 2),
+    ("--ro-bind", 2),
+    ("--ro-bind-try", 2),
+    ("--bind-fd", 2),
+    ("--ro-bind-fd", 2),
+    ("--file", 2),
+    ("--bind-data", 2),
+    ("--ro-bind-data", 2),
+    ("--symlink", 2),
+    ("--chmod", 2),
+    ("--overlay", 3),
+];
+
+/// A program that runs another one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Launcher {
+    /// File name of the launcher (`firejail`).
+    pub name: String,
+    /// Word that must come before the program (`run`).
+    pub subcommand: Option<String>,
+    /// Options followed by separate values, with how many.
+    pub options: Vec<(String, usize)>,
+    /// The launcher takes an app id instead of a program (Flatpak).
+    pub app_id: bool,
+}
+
+impl Launcher {
+    fn new(name: &str, subcommand: Option<&str>, options: &[(&str, usize)], app_id: bool) -> Self {
+        Launcher {
+            name: name.to_string(),
+            subcommand: subcommand.map(str::to_string),
+            options: options.iter().map(|(o, n)| (o.to_string(), *n)).collect(),
+            app_id,
+        }
+    }
+
+    /// Values following option `word`; `None` when it is not an option.
+    fn values_after(&self, word: &str) -> Option<usize> {
+        if !word.starts_with('-') || word == "-" {
+            return None;
+        }
+        if word.starts_with("--") && word.contains('=') {
+            return Some(0);
+        }
+        Some(
+            self.options
+                .iter()
+                .find(|(o, _)| o == word)
+                .map_or(0, |(_, n)| *n),
+        )
+    }
+
+    /// Index of the program (or app id) in `args` (everything after the
+    /// launcher itself), `None` when this invocation runs none.
+    fn program_index(&self, args: &[String]) -> Option<usize> {
+        let mut need_sub = self.subcommand.as_deref();
+        let mut i = 0;
+        while i < args.len() {
+            let word = args[i].as_str();
+            if word == "--" {
+                // Options end, but only the subcommand runs a program.
+                return (need_sub.is_none() && i + 1 < args.len()).then_some(i + 1);
+            }
+            if let Some(n) = self.values_after(word) {
+                i += 1 + n;
+                continue;
+            }
+            match need_sub {
+                Some(sub) if word == sub => need_sub = None,
+                Some(_) => return None,
+                None => return Some(i),
+            }
+            i += 1;
+        }
+        None
+    }
+}
+
+/// The launchers focusctl knows without any configuration.
+pub fn builtin_launchers() -> Vec<Launcher> {
+    vec![
+        Launcher::new("firejail", None, &[], false),
+        Launcher::new("bwrap", None, BWRAP_OPTIONS, false),
+        Launcher::new("flatpak", Some("run"), &[], true),
+        Launcher::new("proclet", Some("run"), &[], false),
+    ]
+}
+
+pub fn focusctl_config_path_for(target: &Target) -> PathBuf {
+    config_dir_for(target).join("focusctl").join("config")
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// `--bind:2` -> (`--bind`, 2); a bare option takes one value.
+fn parse_option(spec: &str) -> Result<(String, usize), String> {
+    let (name, count) = match spec.rsplit_once(':') {
+        Some((name, n)) => match n.parse::<usize>() {
+            Ok(n) => (name, n),
+            Err(_) => return Err(format!("bad value count in {:?}", spec)),
+        },
+        None => (spec, 1),
+    };
+    if !name.starts_with('-') || name.len() < 2 {
+        return Err(format!("{:?} is not an option", spec));
+    }
+    Ok((name.to_string(), count))
+}
+
+/// Built-in launchers overridden and extended by `[Launchers][name]` groups.
+fn launchers_from(doc: &Document) -> io::Result<Vec<Launcher>> {
+    let mut launchers = builtin_launchers();
+    for name in doc.subgroups(LAUNCHERS_GROUP) {
+        let group = nested(LAUNCHERS_GROUP, &name);
+        let get = |key: &str| doc.get(&group, key).map(|v| v.trim().to_string());
+        let options = get("options")
+            .unwrap_or_default()
+            .split(';')
+            .map(str::trim)
+            .filter(|o| !o.is_empty())
+            .map(parse_option)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(format!("[{}][{}]: {}", LAUNCHERS_GROUP, name, e)))?;
+        let launcher = Launcher {
+            name: name.clone(),
+            subcommand: get("subcommand").filter(|s| !s.is_empty()),
+            options,
+            app_id: get("app-id").is_some_and(|v| v == "true"),
+        };
+        match launchers.iter_mut().find(|l| l.name == name) {
+            Some(l) => *l = launcher,
+            None => launchers.push(launcher),
+        }
+    }
+    Ok(launchers)
+}
+
+/// The target's launcher table: built-ins plus their focusctl config.
+pub fn launchers_for(target: &Target) -> io::Result<Vec<Launcher>> {
+    let path = focusctl_config_path_for(target);
+    match fileops::as_target(target, || fileops::read(&path)) {
+        Ok(text) => launchers_from(&Document::parse(&text)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(builtin_launchers()),
+        Err(e) => Err(e),
+    }
+}
+
+/// What a command line really runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unwrapped {
+    /// The program, or the app id when `app_id`.
+    pub program: String,
+    pub app_id: bool,
+    /// Launchers skipped on the way, outermost first.
+    pub via: Vec<String>,
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Skip known launchers at the front of `argv`. A launcher that runs no
+/// program here (`flatpak list`) is itself the program.
+pub fn unwrap(launchers: &[Launcher], argv: &[String]) -> Unwrapped {
+    let mut out = Unwrapped {
+        program: argv.first().cloned().unwrap_or_default(),
+        app_id: false,
+        via: Vec::new(),
+    };
+    let mut rest = argv;
+    while out.via.len() < MAX_DEPTH
+        && !out.app_id
+        && let Some(first) = rest.first()
+        && let Some(l) = launchers.iter().find(|l| l.name == basename(first))
+        && let Some(i) = l.program_index(&rest[1..])
+    {
+        rest = &rest[1 + i..];
+        out = Unwrapped {
+            program: rest[0].clone(),
+            app_id: l.app_id,
+            via: [out.via, vec![l.name.clone()]].concat(),
+        };
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(s: &str) -> Vec<String> {
+        s.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn run(launchers: &[Launcher], s: &str) -> (String, bool, Vec<String>) {
+        let u = unwrap(launchers, &argv(s));
+        (u.program, u.app_id, u.via)
+    }
+
+    #[test]
+    fn builtin_launchers_are_unwrapped() {
+        let l = builtin_launchers();
+        let p = |s: &str| run(&l, s).0;
+        assert_eq!(p("firejail --profile=x chromium --incognito"), "chromium");
+        assert_eq!(
+            p("/usr/bin/firejail --noprofile -- /opt/app/bin/app"),
+            "/opt/app/bin/app"
+        );
+        assert_eq!(
+            p("bwrap --ro-bind / / --dev /dev --setenv A b --unshare-all -- /usr/bin/foo"),
+            "/usr/bin/foo"
+        );
+        assert_eq!(p("bwrap --overlay a b c --die-with-parent foo"), "foo");
+        assert_eq!(
+            p("proclet run -- google-chrome-stable"),
+            "google-chrome-stable"
+        );
+        assert_eq!(
+            p("proclet run --profile=x -- google-chrome-stable"),
+            "google-chrome-stable"
+        );
+        assert_eq!(p("chromium"), "chromium");
+        assert_eq!(
+            run(
+                &l,
+                "flatpak --user run --branch=stable org.mozilla.firefox --new-window"
+            ),
+            (
+                "org.mozilla.firefox".into(),
+                true,
+                vec!["flatpak".to_string()]
+            )
+        );
+        // Nested launchers; an app id is never unwrapped further.
+        assert_eq!(
+            run(&l, "firejail bwrap --bind / / proclet run echo"),
+            ("echo".into(), false, argv("firejail bwrap proclet"))
+        );
+        assert_eq!(run(&l, "flatpak run firejail").0, "firejail");
+        // Launchers that run nothing stay as they are.
+        assert_eq!(run(&l, "flatpak install org.mozilla.firefox").0, "flatpak");
+        assert_eq!(run(&l, "firejail --list").0, "firejail");
+        assert_eq!(run(&l, "bwrap --bind / /").0, "bwrap");
+        assert_eq!(run(&l, "proclet -- google-chrome-stable").0, "proclet");
+        assert_eq!(run(&l, "flatpak -- org.mozilla.firefox").0, "flatpak");
+    }
+
+    #[test]
+    fn the_config_extends_and_overrides_the_table() {
+        let doc = Document::parse(
+            "[Launchers][sandbox-run]\noptions=-p;--mount:2\n\
+             [Launchers][proclet]\nsubcommand=start\napp-id=true\n",
+        );
+        let l = launchers_from(&doc).unwrap();
+        assert_eq!(l.len(), builtin_launchers().len() + 1);
+        assert_eq!(run(&l, "sandbox-run -p x --mount a b -v code").0, "code");
+        assert_eq!(
+            run(&l, "proclet start com.example.App"),
+            ("com.example.App".into(), true, vec!["proclet".to_string()])
+        );
+        assert_eq!(run(&l, "proclet run echo").0, "proclet");
+
+        let bad = Document::parse("[Launchers][x]\noptions=--mount:two\n");
+        assert!(launchers_from(&bad).is_err());
+    }
+}
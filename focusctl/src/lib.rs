@@ -0,0 +1,65 @@
+//! Library behind the `focusctl` CLI for kwin-focus-helper.
+//!
+//! Everything the CLI does is available here without shelling out: pick a
+//! [`Target`] user, edit the forced-focus class list, per-class [`Rule`]s
+//! and the `[Plugins]` flag in their kwinrc, switch between saved class-list
+//! [`Profile`]s, and ask their running KWin to reload.
+//!
+//! Functions return [`std::io::Result`] and never print. Writes only touch
+//! the file; call [`reload_kwin_config`] afterwards so KWin picks them up.
+//!
+//! ```no_run
+//! use focusctl::{Target, add_class, auto_class_from_argv0, reload_kwin_config, set_enabled};
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let target: Target = focusctl::current_target();
+//! let class = auto_class_from_argv0("/usr/bin/google-chrome-stable");
+//! if add_class(&target, &class)? | set_enabled(&target, true)? {
+//!     reload_kwin_config(&target)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Running as root, use [`find_user_by_name`], [`find_user_by_uid`] or
+//! [`find_session_user`] to act on another user's config and session.
+
+mod creds;
+mod fileops;
+
+pub mod bus;
+pub mod config;
+pub mod dbus;
+pub mod desktop;
+pub mod diff;
+pub mod doctor;
+pub mod dryrun;
+pub mod grants;
+pub mod history;
+pub mod json;
+pub mod kconfig;
+pub mod kwin;
+pub mod launcher;
+pub mod pattern;
+pub mod portable;
+pub mod profile;
+pub mod rules;
+pub mod schema;
+pub mod session;
+pub mod target;
+pub mod wrap;
+
+pub use bus::{Reconfigured, list_kwin_windows, pick_kwin_window, reload_kwin_config};
+pub use config::{
+    ConfigHome, GROUP_NAME, KEY_NAME, PLUGINS_GROUP, RULES_KEY, SCRIPT_ID, add_class, add_rule,
+    class_key, config_home_for, config_path_for, effective_rules, get_classes, get_enabled,
+    get_rules, get_script_value, match_window, parse_classes, remove_class, set_classes,
+    set_enabled, set_script_value, unset_script_value,
+};
+pub use desktop::{Resolution, resolve_command};
+pub use profile::{Profile, active_profile, use_profile};
+pub use rules::{Only, Rule};
+pub use target::{
+    Target, current_target, current_uid, find_session_user, find_user_by_name, find_user_by_uid,
+};
+pub use wrap::auto_class_from_argv0;
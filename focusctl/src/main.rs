@@ -1,14 +1,24 @@
 use std::env;
-use std::fs;
 use std::io::{self, Write};
-use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-
-const GROUP_NAME: &str = "Script-kwin-focus-helper";
-const KEY_NAME: &str = "forceFocusClasses";
-
-const SCRIPT_ID: &str = "kwin-focus-helper";
-const PLUGINS_GROUP: &str = "Plugins";
+use std::process::Command;
+use std::time::Duration;
+
+mod output;
+
+use focusctl::bus::{self, Reconfigured};
+use focusctl::config::{
+    self, GROUP_NAME, SCRIPT_ID, add_class, add_rule, check_classes, class_key, config_path_for,
+    effective_rules, get_classes, get_enabled, get_rules, get_script_value, match_window,
+    read_document, set_classes, set_enabled, set_script_value, unset_script_value,
+};
+use focusctl::doctor::{self, Status};
+use focusctl::history::{self, Snapshot};
+use focusctl::json::Json;
+use focusctl::portable::{self, Format, ImportReport, Portable};
+use focusctl::profile::{self, ActiveProfile};
+use focusctl::rules::{self, Only, Rule};
+use focusctl::target::{self, Target};
+use focusctl::{desktop, diff, dryrun, grants, kwin, schema, session, wrap};
 
 // -------------------------------
 // Pretty output (aligned + subtle)
@@ -43,6 +53,12 @@ fn cyan(s: &str) -> String {
 fn soft_red(s: &str) -> String {
     paint(s, "31")
 }
+fn green(s: &str) -> String {
+    paint(s, "32")
+}
+fn yellow(s: &str) -> String {
+    paint(s, "33")
+}
 
 // -------------------------------------
 // Display width (no deps, pragmatic)
@@ -147,11 +163,29 @@ fn section(title: &str) {
 }
 
 fn info(msg: &str) {
+    if output::json() {
+        output::action(msg);
+        return;
+    }
+    if dryrun::enabled() {
+        eprintln!("{} [dry-run] {}", dim("focusctl:"), msg);
+        return;
+    }
     eprintln!("{} {}", dim("focusctl:"), msg);
 }
 fn err(msg: &str) {
     eprintln!("{} {}", soft_red("focusctl:"), msg);
 }
+/// A command error; `code` is what `--json` reports. The process exits 1
+/// unless the command has already chosen another status.
+fn fail(code: &str, msg: &str) {
+    output::set_failed();
+    if output::json() {
+        output::error(code, msg);
+        return;
+    }
+    err(msg);
+}
 
 fn usage() {
     const W: usize = 34;
@@ -169,14 +203,28 @@ fn usage() {
         "Auto-detect active graphical session user (root-friendly)",
         true,
     );
+    line2(
+        W,
+        "--json",
+        Some("36"),
+        "Print one JSON report on stdout (target, classes, actions, errors)",
+        true,
+    );
+    line2(
+        W,
+        "--dry-run",
+        Some("36"),
+        "Change nothing; print the kwinrc diff and the D-Bus calls instead",
+        true,
+    );
     eprintln!();
 
     section("Commands:");
     line2(
         W,
-        "list-classes [--keys|-k]",
+        "list-classes [--keys|-k] [--long|-l]",
         Some("36"),
-        "List stored classes (optional: show match keys)",
+        "List classes and rules (optional: match keys, per-class table)",
         true,
     );
     line2(W, "list-keys", Some("36"), "Show stored value -> normalized match key", true);
@@ -187,11 +235,25 @@ fn usage() {
         "Add class (spelling preserved, matching normalized)",
         true,
     );
+    line2(
+        W,
+        "  [--mode activate|raise]",
+        None,
+        "Store a rule with its own mode instead of the global one",
+        true,
+    );
+    line2(
+        W,
+        "  [--dialogs-only|--normal-only]",
+        None,
+        "Store a rule that only applies to dialogs / normal windows",
+        true,
+    );
     line2(
         W,
         "remove-class <window-class>",
         Some("36"),
-        "Remove by match key (case-insensitive, strips .desktop)",
+        "Remove class and rule by match key (case-insensitive, strips .desktop)",
         true,
     );
     line2(
@@ -201,7 +263,59 @@ fn usage() {
         "Replace entire list (separators: ';' ',' whitespace)",
         true,
     );
-    line2(W, "clear", Some("36"), "Clear all configured classes", true);
+    line2(W, "clear", Some("36"), "Clear all configured classes and rules", true);
+    line2(
+        W,
+        "history",
+        Some("36"),
+        "List saved states from before each change (1 = newest)",
+        true,
+    );
+    line2(W, "undo", Some("36"), "Revert the last change (repeat to go further back)", true);
+    line2(W, "restore <n>", Some("36"), "Go back to the state before change <n>", true);
+    line2(
+        W,
+        "profile list|show <name>",
+        Some("36"),
+        "List saved class-list profiles (* = active) / print one",
+        true,
+    );
+    line2(
+        W,
+        "profile create <name> [list] [-f]",
+        Some("36"),
+        "Save a list (default: the current classes)",
+        true,
+    );
+    line2(
+        W,
+        "profile use|delete <name>",
+        Some("36"),
+        "Write a profile's list to kwinrc / remove a profile",
+        true,
+    );
+    line2(
+        W,
+        "export [--format toml|json|plain]",
+        Some("36"),
+        "Print classes, rules and settings as a shareable file",
+        true,
+    );
+    line2(
+        W,
+        "import <file> [--merge|--replace]",
+        Some("36"),
+        "Add the file's new entries (default) or make kwinrc match it",
+        true,
+    );
+    line2(
+        W,
+        "apply <file>",
+        Some("36"),
+        "Make kwinrc match a desired-state file (exit 0 unchanged, 2 applied, 1 error)",
+        true,
+    );
+    line2(W, "render <file>", Some("36"), "Print the kwinrc groups for a desired-state file", true);
     line2(W, "enable", Some("36"), "Set [Plugins] kwin-focus-helperEnabled=true", true);
     line2(W, "disable", Some("36"), "Set [Plugins] kwin-focus-helperEnabled=false", true);
     line2(W, "enabled", Some("36"), "Print enabled state: true/false/(unset)", true);
@@ -209,9 +323,75 @@ fn usage() {
         W,
         "reconfigure",
         Some("36"),
-        "Request org.kde.KWin /KWin reconfigure (best-effort)",
+        "Request org.kde.KWin /KWin reconfigure (fails if unanswered)",
         true,
     );
+    line2(
+        W,
+        "doctor",
+        Some("36"),
+        "Check install, config, session and KWin (exit 1 on failure)",
+        true,
+    );
+    line2(
+        W,
+        "pick [--desktop|--class|--name]",
+        Some("36"),
+        "Click a window and add one of its names",
+        true,
+    );
+    line2(
+        W,
+        "windows [-k|--keys] [-m|--matching]",
+        Some("36"),
+        "List live windows + match candidates (* = matches)",
+        true,
+    );
+    line2(
+        W,
+        "match --class C [--desktop-file D]",
+        Some("36"),
+        "Would such a window be forced? (exit 1 if not)",
+        true,
+    );
+    line2(
+        W,
+        "  [--name N] [--dialog]",
+        None,
+        "Window properties (a normal window unless --dialog)",
+        true,
+    );
+    line2(
+        W,
+        "  [--minimized] [--active]",
+        None,
+        "Window state checked before forcing",
+        true,
+    );
+    line2(
+        W,
+        "resolve <program|desktop-id|cmd...>",
+        Some("36"),
+        "Print the class wrap --auto would use (past launchers, from .desktop)",
+        true,
+    );
+    eprintln!();
+
+    section("Script settings:");
+    line2(W, "get [key]", Some("36"), "Print one setting (or all with defaults)", true);
+    line2(W, "set <key> <value>", Some("36"), "Validate and store a setting", true);
+    line2(W, "unset <key>", Some("36"), "Remove a setting (script uses its default)", true);
+    line2(W, "mode [activate|raise]", Some("36"), "Show or set the focus mode", true);
+    line2(W, "debug [on|off]", Some("36"), "Show or toggle script debug logging", true);
+    for k in schema::SCRIPT_KEYS {
+        line2(
+            W,
+            &format!("  {}", k.name),
+            None,
+            &format!("{} ({}, default: {})", k.help, k.value_hint(), display_default(k)),
+            true,
+        );
+    }
     eprintln!();
 
     section("Integration wrappers:");
@@ -226,10 +406,31 @@ fn usage() {
         W,
         "wrap --auto -- <cmd...>",
         Some("36"),
-        "Auto class name from argv[0] (example: echo -> EchoApp)",
+        "Class from the desktop entry that runs the command (else echo -> EchoApp)",
+        true,
+    );
+    line2(W, "wrap ... [--dry-run]", Some("36"), "Same as the global --dry-run; does not exec", true);
+    line2(
+        W,
+        "wrap ... [--ephemeral]",
+        Some("36"),
+        "Run the command as a child; remove the class when the last such wrapper exits",
+        true,
+    );
+    line2(
+        W,
+        "wrap ... [--learn]",
+        Some("36"),
+        "Watch the command's first window; add its real class if the name differs",
+        true,
+    );
+    line2(
+        W,
+        "  [--learn-timeout <secs>]",
+        None,
+        "How long to wait for that window (default: 30)",
         true,
     );
-    line2(W, "wrap ... [--dry-run]", Some("36"), "Print actions only (no changes, no exec)", true);
     line2(W, "wrap ... [--no-enable]", Some("36"), "Do not set plugin enabled flag", true);
     line2(
         W,
@@ -242,557 +443,1059 @@ fn usage() {
 
     section("Notes:");
     eprintln!("  {}", dim("• Matching is case-insensitive and ignores trailing '.desktop'."));
+    eprintln!(
+        "  {}",
+        dim("• Prefix desktop:, class: or name: to match one window property only.")
+    );
+    eprintln!(
+        "  {}",
+        dim("• Globs (proclet-*) and /regex/ entries are anchored and case-insensitive.")
+    );
     eprintln!("  {}", dim("• Stored/display names preserve your spelling (e.g. ProcletChrome)."));
     eprintln!("  {}", dim("• Set NO_COLOR=1 to disable colors."));
 }
 
+fn display_default(k: &schema::KeySpec) -> &'static str {
+    if k.default.is_empty() { "empty" } else { k.default }
+}
+
 // -------------------------------
-// Target selection (uid/user/auto)
+// Config writes + reconfigure (reporting)
 // -------------------------------
 
-#[derive(Clone, Debug)]
-struct Target {
-    uid: u32,
-    user: String,
-    home: PathBuf,
-}
-
-fn parse_passwd() -> io::Result<Vec<(String, u32, PathBuf)>> {
-    let s = fs::read_to_string("/etc/passwd")?;
-    let mut out = Vec::new();
-    for line in s.lines() {
-        if line.trim().is_empty() || line.starts_with('#') {
-            continue;
+/// Ask KWin to reload and say how it went; a dry run only lists the calls.
+/// Returns `false` (and fails the command) when KWin could not be reached.
+fn reconfigure(target: &Target) -> bool {
+    if dryrun::enabled() {
+        for call in bus::reload_calls(target) {
+            info(&format!("would call {}", call));
         }
-        // name:pw:uid:gid:gecos:home:shell
-        let parts: Vec<&str> = line.split(':').collect();
-        if parts.len() < 7 {
-            continue;
+        return true;
+    }
+    match bus::reload_kwin_config(target) {
+        Ok(Reconfigured::DBus) => info("requested KWin reconfigure via D-Bus"),
+        Ok(Reconfigured::Qdbus(prog)) => {
+            info(&format!("requested KWin reconfigure via {}", prog))
+        }
+        Err(e) => {
+            fail("kwin", &format!("{}; you may need to run manually:", e));
+            eprintln!("\tqdbus org.kde.KWin /KWin reconfigure");
+            return false;
         }
-        let name = parts[0].to_string();
-        let uid: u32 = match parts[2].parse() {
-            Ok(x) => x,
-            Err(_) => continue,
-        };
-        let home = PathBuf::from(parts[5]);
-        out.push((name, uid, home));
     }
-    Ok(out)
+    true
 }
 
-fn find_user_by_name(name: &str) -> io::Result<Option<Target>> {
-    for (n, uid, home) in parse_passwd()? {
-        if n == name {
-            return Ok(Some(Target { uid, user: n, home }));
+fn report_add_class(target: &Target, class: &str) {
+    match add_class(target, class) {
+        Ok(true) => {
+            info("added class");
+            reconfigure(target);
         }
+        Ok(false) => info("class already present"),
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => fail("usage", &e.to_string()),
+        Err(e) => fail("config-write", &format!("failed to write config: {}", e)),
     }
-    Ok(None)
 }
 
-fn find_user_by_uid(uid: u32) -> io::Result<Option<Target>> {
-    for (n, u, home) in parse_passwd()? {
-        if u == uid {
-            return Ok(Some(Target { uid: u, user: n, home }));
+fn report_set_value(target: &Target, spec: &schema::KeySpec, raw: &str) {
+    let value = match spec.normalize(raw) {
+        Ok(v) => v,
+        Err(e) => return fail("invalid-value", &e),
+    };
+    match set_script_value(target, spec.name, &value) {
+        Ok(true) => {
+            info(&format!("set {}={}", spec.name, value));
+            reconfigure(target);
         }
+        Ok(false) => info(&format!("{} already set to {}", spec.name, value)),
+        Err(e) => fail("config-write", &format!("failed to write config: {}", e)),
     }
-    Ok(None)
 }
 
-// Best-effort "who am I" without libc.
-fn current_uid() -> u32 {
-    if let Ok(u) = env::var("UID") {
-        if let Ok(x) = u.parse::<u32>() {
-            return x;
-        }
-    }
-    if let Ok(out) = Command::new("id").arg("-u").output() {
-        if out.status.success() {
-            if let Ok(s) = String::from_utf8(out.stdout) {
-                if let Ok(x) = s.trim().parse::<u32>() {
-                    return x;
-                }
-            }
+fn report_add_rule(target: &Target, rule: &Rule) {
+    match add_rule(target, rule) {
+        Ok(true) => {
+            info(&format!("stored rule {}", rule));
+            reconfigure(target);
         }
+        Ok(false) => info("rule already present"),
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => fail("usage", &e.to_string()),
+        Err(e) => fail("config-write", &format!("failed to write config: {}", e)),
     }
-    0
-}
-
-fn current_user() -> String {
-    env::var("USER").unwrap_or_else(|_| "unknown".to_string())
-}
-
-fn current_home() -> PathBuf {
-    env::var_os("HOME").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."))
 }
 
 // -------------------------------
-// KWin config path + IO
+// Class/rule table
 // -------------------------------
 
-fn config_path_for(target: &Target) -> PathBuf {
-    // For a different user we can't reliably know XDG_CONFIG_HOME; assume ~/.config.
-    target.home.join(".config").join("kwinrc")
-}
+/// `list-classes --long`: what the script does for each configured entry.
+fn print_rules(effective: &[Rule], stored_rules: &[Rule], global_mode: &str) {
+    let header = ["CLASS", "KEY", "MODE", "WINDOWS", "SOURCE"];
+    let rows: Vec<[String; 5]> = effective
+        .iter()
+        .map(|r| {
+            let key = r.key();
+            let mode = match r.mode {
+                Some(m) => m.to_string(),
+                None => format!("{} (global)", global_mode),
+            };
+            let windows = r.only.map(Only::as_str).unwrap_or("any");
+            let source = if stored_rules.iter().any(|s| s.key() == key) { "rule" } else { "class" };
+            [r.class.clone(), key, mode, windows.to_string(), source.to_string()]
+        })
+        .collect();
+
+    let mut widths: Vec<usize> = header.iter().map(|h| display_width(h)).collect();
+    for r in &rows {
+        for (c, v) in r.iter().enumerate() {
+            widths[c] = widths[c].max(display_width(v));
+        }
+    }
+    let fmt_row = |cells: &[String]| -> String {
+        let last = cells.len() - 1;
+        cells
+            .iter()
+            .enumerate()
+            .map(|(c, v)| if c == last { v.clone() } else { col_left(v, widths[c], None) })
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
 
-fn read_kwinrc(target: &Target) -> io::Result<String> {
-    fs::read_to_string(config_path_for(target))
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    println!("{}", bold(&fmt_row(&header)));
+    for r in &rows {
+        println!("{}", fmt_row(r));
+    }
 }
 
-fn atomic_write(path: &Path, contents: &str) -> io::Result<()> {
-    let tmp = path.with_extension("tmp.kwin-focus-helper");
-    {
-        let mut f = fs::File::create(&tmp)?;
-        f.write_all(contents.as_bytes())?;
-        f.sync_all()?;
-    }
-    fs::rename(tmp, path)?;
-    Ok(())
+fn rule_json(r: &Rule) -> Json {
+    Json::obj(vec![
+        ("rule", Json::from(r.to_string())),
+        ("class", Json::from(r.class.as_str())),
+        ("key", Json::from(r.key())),
+        ("mode", Json::from(r.mode)),
+        ("only", Json::from(r.only.map(Only::as_str))),
+    ])
 }
 
 // -------------------------------
-// Parsing + normalization
+// Window table
 // -------------------------------
 
-fn class_key(s: &str) -> String {
-    let s = s.trim();
-    if s.is_empty() {
-        return String::new();
+fn truncate_cells(s: &str, max: usize) -> String {
+    if display_width(s) <= max {
+        return s.to_string();
     }
-    let lower = s.to_lowercase();
-    let lower = lower.strip_suffix(".desktop").unwrap_or(&lower);
-    lower.to_string()
-}
-
-fn parse_classes(value: &str) -> Vec<String> {
-    value
-        .split(|c: char| c == ';' || c == ',' || c.is_whitespace())
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect()
-}
-
-fn join_classes(classes: &[String]) -> String {
-    classes.join(";")
-}
-
-#[derive(Debug)]
-struct ScriptConfig {
-    group_header_index: Option<usize>,
-    value_line_index: Option<usize>,
-    value: String,
+    let mut out = String::new();
+    for c in s.chars() {
+        if display_width(&out) + display_width(&c.to_string()) >= max {
+            break;
+        }
+        out.push(c);
+    }
+    out.push('…');
+    out
 }
 
-/// Finds `[Script-kwin-focus-helper]` group and `forceFocusClasses=...` within it.
-fn extract_script_config(lines: &[String]) -> ScriptConfig {
-    let target_header = format!("[{}]", GROUP_NAME);
-    let mut in_group = false;
-
-    let mut group_header_index = None;
-    let mut value_line_index = None;
-    let mut value = String::new();
-
-    for (i, line) in lines.iter().enumerate() {
-        let trimmed = line.trim();
+/// One row per window; the field that matches the configured list gets a `*`.
+fn print_windows(windows: &[kwin::WindowInfo], keys: &[String], show_keys: bool, only: bool) {
+    const FIELD_W: usize = 28;
+    const CAPTION_W: usize = 40;
 
-        if trimmed.starts_with('[') && trimmed.ends_with(']') {
-            if trimmed == target_header {
-                in_group = true;
-                group_header_index = Some(i);
-            } else {
-                in_group = false;
-            }
+    let mut rows: Vec<[String; 6]> = Vec::new();
+    for w in windows {
+        let matched = match_window(w, keys);
+        if only && matched.is_none() {
             continue;
         }
+        let cell = |field: &str, v: &str| {
+            let shown = if show_keys { class_key(v) } else { v.to_string() };
+            let shown = if shown.is_empty() { "-".to_string() } else { shown };
+            let mark = if matched == Some(field) { "*" } else { "" };
+            format!("{}{}", truncate_cells(&shown, FIELD_W - 1), mark)
+        };
+        let [(f0, v0), (f1, v1), (f2, v2)] = w.candidates();
+        rows.push([
+            if matched.is_some() { "*" } else { "" }.to_string(),
+            w.pid.to_string(),
+            cell(f0, v0),
+            cell(f1, v1),
+            cell(f2, v2),
+            truncate_cells(&w.caption, CAPTION_W),
+        ]);
+    }
 
-        if in_group {
-            let prefix = format!("{}=", KEY_NAME);
-            if trimmed.starts_with(&prefix) {
-                value_line_index = Some(i);
-                value = trimmed[prefix.len()..].to_string();
-            }
+    if rows.is_empty() {
+        println!("{}", if only { "(no matching windows)" } else { "(no windows)" });
+        return;
+    }
+
+    let header = ["", "PID", "DESKTOP FILE", "RESOURCE CLASS", "RESOURCE NAME", "CAPTION"];
+    let mut widths: Vec<usize> = header.iter().map(|h| display_width(h)).collect();
+    for r in &rows {
+        for (c, v) in r.iter().enumerate() {
+            widths[c] = widths[c].max(display_width(v));
         }
     }
 
-    ScriptConfig {
-        group_header_index,
-        value_line_index,
-        value,
+    let fmt_row = |cells: &[String]| -> String {
+        let last = cells.len() - 1;
+        cells
+            .iter()
+            .enumerate()
+            .map(|(c, v)| if c == last { v.clone() } else { col_left(v, widths[c], None) })
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
+
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    println!("{}", fmt_row(&header));
+    for r in &rows {
+        println!("{}", fmt_row(r));
     }
+    let n = rows.iter().filter(|r| !r[0].is_empty()).count();
+    info(&format!("{} window(s), {} matching the configured classes", rows.len(), n));
 }
 
-/// Finds `[Plugins]` and `kwin-focus-helperEnabled=...` within it.
-fn extract_plugins_enabled(lines: &[String]) -> (Option<usize>, Option<usize>, Option<bool>) {
-    let header = format!("[{}]", PLUGINS_GROUP);
-    let key = format!("{}Enabled", SCRIPT_ID);
+// -------------------------------
+// Interactive pick
+// -------------------------------
 
-    let mut in_group = false;
-    let mut group_header_index = None;
-    let mut value_line_index = None;
-    let mut enabled: Option<bool> = None;
+/// Show the picked window's match candidates and ask which one to store.
+/// Defaults to the first non-empty field (the one the script tries first).
+fn choose_candidate(w: &kwin::WindowInfo) -> Option<String> {
+    let candidates = w.candidates();
+    let default = candidates.iter().position(|(_, v)| !v.trim().is_empty());
+    let Some(default) = default else {
+        fail("kwin", "the picked window has no desktop file, class or name");
+        return None;
+    };
 
-    for (i, line) in lines.iter().enumerate() {
-        let trimmed = line.trim();
+    if !w.caption.is_empty() {
+        eprintln!("{}", bold(&w.caption));
+    }
+    for (n, (field, value)) in candidates.iter().enumerate() {
+        let shown = if value.is_empty() { "-" } else { value };
+        let left = format!("  {}) {}", n + 1, field);
+        line2(22, &left, Some("36"), shown, value.is_empty());
+    }
 
-        if trimmed.starts_with('[') && trimmed.ends_with(']') {
-            if trimmed == header {
-                in_group = true;
-                group_header_index = Some(i);
-            } else {
-                in_group = false;
+    loop {
+        eprint!("store which? [{}] ", default + 1);
+        let _ = io::stderr().flush();
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => {
+                info("cancelled");
+                return None;
             }
-            continue;
+            Ok(_) => {}
         }
-
-        if in_group {
-            let prefix = format!("{}=", key);
-            if trimmed.starts_with(&prefix) {
-                value_line_index = Some(i);
-                let v = trimmed[prefix.len()..].trim().to_lowercase();
-                enabled = Some(v == "true" || v == "1" || v == "yes");
-            }
+        let n = match line.trim() {
+            "" => default,
+            t => match t.parse::<usize>() {
+                Ok(n) if (1..=3).contains(&n) => n - 1,
+                _ => {
+                    err("enter 1, 2 or 3");
+                    continue;
+                }
+            },
+        };
+        if candidates[n].1.trim().is_empty() {
+            err("that field is empty");
+            continue;
         }
+        return Some(candidates[n].1.to_string());
     }
+}
+
+fn window_json(w: &kwin::WindowInfo, matched: Option<&str>) -> Json {
+    Json::obj(vec![
+        ("pid", Json::from(w.pid)),
+        ("caption", Json::from(w.caption.as_str())),
+        ("desktopFileName", Json::from(w.desktop_file.as_str())),
+        ("resourceClass", Json::from(w.resource_class.as_str())),
+        ("resourceName", Json::from(w.resource_name.as_str())),
+        ("normalWindow", Json::from(w.normal)),
+        ("dialog", Json::from(w.dialog)),
+        ("match", Json::from(matched)),
+    ])
+}
 
-    (group_header_index, value_line_index, enabled)
+/// `{key, stored, value, default}` for one script setting; `value` is what
+/// the script will use (only known keys have one).
+fn setting_json(key: &str, stored: Option<String>, known: bool) -> Json {
+    let spec = schema::lookup(key).filter(|_| known);
+    Json::obj(vec![
+        ("key", Json::from(key)),
+        ("stored", Json::from(stored.clone())),
+        ("value", Json::from(spec.map(|k| k.effective(stored.as_deref())))),
+        ("default", Json::from(spec.map(|k| k.default))),
+        ("known", Json::from(spec.is_some())),
+    ])
 }
 
 // -------------------------------
-// KWin DBus reconfigure (root-friendly target)
+// Profiles
 // -------------------------------
 
-fn have_cmd(name: &str) -> bool {
-    Command::new("sh")
-        .arg("-lc")
-        .arg(format!("command -v {} >/dev/null 2>&1", name))
-        .status()
-        .map(|s| s.success())
-        .unwrap_or(false)
-}
-
-/// Find active graphical session env for a target uid:
-/// returns (XDG_RUNTIME_DIR, DBUS_SESSION_BUS_ADDRESS)
-fn detect_session_env_for_uid(uid: u32) -> io::Result<Option<(String, String)>> {
-    if !have_cmd("loginctl") {
-        return Ok(None);
+fn profile_label(p: &ActiveProfile) -> String {
+    if p.modified {
+        format!("profile: {} (modified)", p.name)
+    } else {
+        format!("profile: {}", p.name)
     }
+}
 
-    let out = Command::new("loginctl")
-        .args(["list-sessions", "--no-legend"])
-        .output()?;
-
-    if !out.status.success() {
-        return Ok(None);
+fn profile_error(e: io::Error, code: &str) {
+    match e.kind() {
+        io::ErrorKind::NotFound => fail("unknown-profile", &e.to_string()),
+        io::ErrorKind::InvalidInput => fail("invalid-value", &e.to_string()),
+        io::ErrorKind::AlreadyExists => fail("usage", &format!("{} (use --force)", e)),
+        _ if code == "config-read" => fail(code, &format!("failed to read profiles: {}", e)),
+        _ => fail(code, &format!("failed to write profiles: {}", e)),
     }
+}
 
-    let text = String::from_utf8_lossy(&out.stdout);
-    for line in text.lines() {
-        let sid = match line.split_whitespace().next() {
-            Some(x) => x,
-            None => continue,
-        };
+fn profile_json(p: &profile::Profile, active: bool) -> Json {
+    Json::obj(vec![
+        ("name", Json::from(p.name.as_str())),
+        (
+            "classes",
+            Json::Arr(p.classes.iter().map(|c| Json::from(c.as_str())).collect()),
+        ),
+        ("active", Json::from(active)),
+    ])
+}
 
-        // Active?
-        let active = Command::new("loginctl")
-            .args(["show-session", sid, "-p", "Active", "--value"])
-            .output()
-            .ok()
-            .and_then(|o| String::from_utf8(o.stdout).ok())
-            .map(|s| s.trim().to_string())
-            .unwrap_or_default();
-        if active != "yes" {
-            continue;
+fn run_profile(target: &Target, args: &[String]) {
+    let sub = args.first().map(String::as_str).unwrap_or("list");
+    let name = args.get(1).map(String::as_str);
+    let need_name = || {
+        if name.is_none() {
+            fail("usage", &format!("profile {} requires <name>", sub));
         }
+        name
+    };
 
-        // Class user?
-        let class = Command::new("loginctl")
-            .args(["show-session", sid, "-p", "Class", "--value"])
-            .output()
-            .ok()
-            .and_then(|o| String::from_utf8(o.stdout).ok())
-            .map(|s| s.trim().to_string())
-            .unwrap_or_default();
-        if class != "user" {
-            continue;
+    match sub {
+        "list" => {
+            let profiles = match profile::list_profiles(target) {
+                Ok(p) => p,
+                Err(e) => return profile_error(e, "config-read"),
+            };
+            let active = profile::active_profile(target).ok().flatten();
+            let is_active = |n: &str| active.as_ref().is_some_and(|a| a.name == n);
+            if output::json() {
+                let rows = profiles
+                    .iter()
+                    .map(|p| profile_json(p, is_active(&p.name)))
+                    .collect();
+                output::data("profiles", Json::Arr(rows));
+                return;
+            }
+            if profiles.is_empty() {
+                println!("(no profiles saved)");
+                return;
+            }
+            let w = profiles.iter().map(|p| display_width(&p.name)).max().unwrap_or(0);
+            for p in &profiles {
+                let mark = match &active {
+                    Some(a) if a.name == p.name && a.modified => yellow("*"),
+                    Some(a) if a.name == p.name => green("*"),
+                    _ => " ".to_string(),
+                };
+                let detail = format!("{} class(es)", p.classes.len());
+                println!("{} {}  {}", mark, col_left(&p.name, w, None), dim(&detail));
+            }
+            if let Some(a) = active.filter(|a| a.modified) {
+                info(&format!("kwinrc has changed since profile {} was used", a.name));
+            }
         }
 
-        // Type wayland/x11?
-        let ty = Command::new("loginctl")
-            .args(["show-session", sid, "-p", "Type", "--value"])
-            .output()
-            .ok()
-            .and_then(|o| String::from_utf8(o.stdout).ok())
-            .map(|s| s.trim().to_string())
-            .unwrap_or_default();
-        if ty != "wayland" && ty != "x11" {
-            continue;
+        "show" => {
+            let Some(name) = need_name() else { return };
+            match profile::get_profile(target, name) {
+                Ok(Some(p)) if output::json() => {
+                    let active = profile::active_profile(target).ok().flatten();
+                    let is_active = active.is_some_and(|a| a.name == p.name);
+                    output::data("profiles", Json::Arr(vec![profile_json(&p, is_active)]));
+                }
+                Ok(Some(p)) => {
+                    if p.classes.is_empty() {
+                        println!("(no classes)");
+                    }
+                    for c in p.classes {
+                        println!("{}", c);
+                    }
+                }
+                Ok(None) => fail("unknown-profile", &format!("no such profile: {}", name)),
+                Err(e) => profile_error(e, "config-read"),
+            }
         }
 
-        // State active/online?
-        let state = Command::new("loginctl")
-            .args(["show-session", sid, "-p", "State", "--value"])
-            .output()
-            .ok()
-            .and_then(|o| String::from_utf8(o.stdout).ok())
-            .map(|s| s.trim().to_string())
-            .unwrap_or_default();
-        if state != "active" && state != "online" {
-            continue;
+        "create" => {
+            let Some(name) = need_name() else { return };
+            let mut list: Option<&str> = None;
+            let mut force = false;
+            for a in &args[2..] {
+                match a.as_str() {
+                    "--force" | "-f" => force = true,
+                    _ if list.is_none() && !a.starts_with('-') => list = Some(a),
+                    other => {
+                        fail("usage", &format!("unknown profile create option: {}", other));
+                        return;
+                    }
+                }
+            }
+            let classes = match list {
+                Some(l) => match check_classes(l) {
+                    Ok(c) => c,
+                    Err(e) => return fail("invalid-value", &e),
+                },
+                None => match get_classes(target) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        return fail("config-read", &format!("failed to read config: {}", e));
+                    }
+                },
+            };
+            match profile::save_profile(target, name, &classes, force) {
+                Ok(true) => info(&format!("saved profile {} ({} class(es))", name, classes.len())),
+                Ok(false) => info(&format!("profile {} unchanged", name)),
+                Err(e) => profile_error(e, "config-write"),
+            }
         }
 
-        // User uid?
-        let user_uid = Command::new("loginctl")
-            .args(["show-session", sid, "-p", "User", "--value"])
-            .output()
-            .ok()
-            .and_then(|o| String::from_utf8(o.stdout).ok())
-            .map(|s| s.trim().to_string())
-            .unwrap_or_default();
-        let session_uid: u32 = match user_uid.parse() {
-            Ok(x) => x,
-            Err(_) => continue,
-        };
-
-        if session_uid != uid {
-            continue;
+        "use" => {
+            let Some(name) = need_name() else { return };
+            match profile::use_profile(target, name) {
+                Ok(changed) => {
+                    info(&format!("using profile {}", name));
+                    if changed {
+                        reconfigure(target);
+                    }
+                }
+                Err(e) => profile_error(e, "config-write"),
+            }
         }
 
-        let xdg = Command::new("loginctl")
-            .args(["show-session", sid, "-p", "XDG_RUNTIME_DIR", "--value"])
-            .output()
-            .ok()
-            .and_then(|o| String::from_utf8(o.stdout).ok())
-            .map(|s| s.trim().to_string())
-            .unwrap_or_default();
-
-        let dbus = Command::new("loginctl")
-            .args(["show-session", sid, "-p", "DBUS_SESSION_BUS_ADDRESS", "--value"])
-            .output()
-            .ok()
-            .and_then(|o| String::from_utf8(o.stdout).ok())
-            .map(|s| s.trim().to_string())
-            .unwrap_or_default();
-
-        if !xdg.is_empty() && !dbus.is_empty() {
-            return Ok(Some((xdg, dbus)));
+        "delete" => {
+            let Some(name) = need_name() else { return };
+            match profile::delete_profile(target, name) {
+                Ok(true) => info(&format!("deleted profile {}", name)),
+                Ok(false) => fail("unknown-profile", &format!("no such profile: {}", name)),
+                Err(e) => profile_error(e, "config-write"),
+            }
         }
-    }
 
-    Ok(None)
+        other => fail("usage", &format!("unknown profile command: {}", other)),
+    }
 }
 
-fn run_as_target(target: &Target, mut cmd: Command) -> io::Result<std::process::ExitStatus> {
-    let self_uid = current_uid();
-    if self_uid == 0 && target.uid != 0 && have_cmd("sudo") {
-        let mut sudo = Command::new("sudo");
-        sudo.arg("-u").arg(format!("#{}", target.uid)).arg("-H");
-        let prog = cmd.get_program().to_os_string();
-        let args: Vec<_> = cmd.get_args().map(|a| a.to_os_string()).collect();
-        sudo.arg(prog);
-        for a in args {
-            sudo.arg(a);
+// -------------------------------
+// Export / import
+// -------------------------------
+
+fn run_export(target: &Target, args: &[String]) {
+    let mut format = Format::Toml;
+    let mut it = args.iter();
+    while let Some(a) = it.next() {
+        match a.as_str() {
+            "--format" | "-f" => match it.next().and_then(|f| Format::parse(f)) {
+                Some(f) => format = f,
+                None => return fail("usage", "--format requires toml, json or plain"),
+            },
+            other => return fail("usage", &format!("unknown export option: {}", other)),
         }
-        sudo.stdin(Stdio::inherit()).stdout(Stdio::inherit()).stderr(Stdio::inherit());
-        return sudo.status();
     }
-
-    cmd.stdin(Stdio::inherit()).stdout(Stdio::inherit()).stderr(Stdio::inherit());
-    cmd.status()
+    let file = match portable::export(target) {
+        Ok(p) => p,
+        Err(e) => return fail("config-read", &format!("failed to read config: {}", e)),
+    };
+    if output::json() {
+        output::data("format", Json::from(format.as_str()));
+        output::data("export", Json::from(file.render(format)));
+        return;
+    }
+    print!("{}", file.render(format));
 }
 
-fn reload_kwin_config(target: &Target) {
-    let cmds: &[(&str, [&str; 3])] = &[
-        ("qdbus6", ["org.kde.KWin", "/KWin", "reconfigure"]),
-        ("qdbus-qt6", ["org.kde.KWin", "/KWin", "reconfigure"]),
-        ("qdbus-qt5", ["org.kde.KWin", "/KWin", "reconfigure"]),
-        ("qdbus", ["org.kde.KWin", "/KWin", "reconfigure"]),
-    ];
-
-    let mut session_env: Option<(String, String)> = None;
-    if let Ok(Some((xdg, dbus))) = detect_session_env_for_uid(target.uid) {
-        session_env = Some((xdg, dbus));
+/// `<file> [--format F]` plus the flags in `extra`; `None` after a usage error.
+fn file_args<'a>(
+    cmd: &str,
+    args: &'a [String],
+    extra: &[&str],
+) -> Option<(&'a str, Option<Format>, Vec<&'a str>)> {
+    let mut path: Option<&str> = None;
+    let mut format: Option<Format> = None;
+    let mut flags = Vec::new();
+    let mut it = args.iter();
+    while let Some(a) = it.next() {
+        match a.as_str() {
+            "--format" | "-f" => match it.next().and_then(|f| Format::parse(f)) {
+                Some(f) => format = Some(f),
+                None => {
+                    fail("usage", "--format requires toml, json or plain");
+                    return None;
+                }
+            },
+            f if extra.contains(&f) => flags.push(f),
+            p if path.is_none() && (p == "-" || !p.starts_with('-')) => path = Some(p),
+            other => {
+                fail("usage", &format!("unknown {} option: {}", cmd, other));
+                return None;
+            }
+        }
     }
-
-    for (prog, args) in cmds {
-        if !have_cmd(prog) {
-            continue;
+    match path {
+        Some(p) => Some((p, format, flags)),
+        None => {
+            fail("usage", &format!("{} requires <file> (- for stdin)", cmd));
+            None
         }
+    }
+}
 
-        let mut c = Command::new(prog);
-        c.args(args);
-
-        if let Some((ref xdg, ref dbus)) = session_env {
-            c.env("XDG_RUNTIME_DIR", xdg);
-            c.env("DBUS_SESSION_BUS_ADDRESS", dbus);
+/// Read and parse an export / desired-state file (`-` = stdin).
+fn read_portable(path: &str, format: Option<Format>) -> Option<Portable> {
+    let text = if path == "-" {
+        io::read_to_string(io::stdin())
+    } else {
+        std::fs::read_to_string(path)
+    };
+    let text = match text {
+        Ok(t) => t,
+        Err(e) => {
+            fail("usage", &format!("cannot read {}: {}", path, e));
+            return None;
         }
-
-        match run_as_target(target, c) {
-            Ok(st) if st.success() => {
-                info(&format!("requested KWin reconfigure via {}", prog));
-                return;
-            }
-            _ => {}
+    };
+    let format = format.unwrap_or_else(|| Format::detect(path, &text));
+    match Portable::parse(&text, format) {
+        Ok(f) => Some(f),
+        Err(e) => {
+            fail("invalid-value", &format!("{} ({}): {}", path, format.as_str(), e));
+            None
         }
     }
-
-    err("could not call qdbus/qdbus6; you may need to run manually:");
-    eprintln!("\tqdbus org.kde.KWin /KWin reconfigure");
 }
 
-// -------------------------------
-// Config operations
-// -------------------------------
-
-fn get_classes(target: &Target) -> io::Result<Vec<String>> {
-    let contents = read_kwinrc(target).unwrap_or_default();
-    let lines: Vec<String> = contents.lines().map(|s| s.to_string()).collect();
-    let cfg = extract_script_config(&lines);
-
-    if cfg.value.is_empty() {
-        return Ok(Vec::new());
+fn import_error(path: &str, e: io::Error) {
+    if e.kind() == io::ErrorKind::InvalidData {
+        fail("invalid-value", &format!("{}: {}", path, e));
+    } else {
+        fail("config-write", &format!("failed to write config: {}", e));
     }
-
-    Ok(parse_classes(&cfg.value))
 }
 
-fn set_classes(target: &Target, new_classes: &[String], do_reconfigure: bool) -> io::Result<()> {
-    let path = config_path_for(target);
-    let contents = read_kwinrc(target).unwrap_or_default();
+fn describe_value(v: Option<&str>) -> &str {
+    v.unwrap_or("(unset)")
+}
 
-    let mut lines: Vec<String> = if contents.is_empty() {
-        Vec::new()
-    } else {
-        contents.lines().map(|s| s.to_string()).collect()
+fn run_import(target: &Target, args: &[String]) {
+    let Some((path, format, flags)) = file_args("import", args, &["--merge", "--replace"]) else {
+        return;
+    };
+    let replace = flags.last() == Some(&"--replace");
+    let Some(file) = read_portable(path, format) else {
+        return;
     };
+    let report = match portable::import(target, &file, replace) {
+        Ok(r) => r,
+        Err(e) => return import_error(path, e),
+    };
+    for r in &report.added {
+        info(&format!("added {}", r));
+    }
+    for (r, why) in &report.skipped {
+        info(&format!("skipped {} ({})", r, why.as_str()));
+    }
+    for r in &report.removed {
+        info(&format!("removed {}", r));
+    }
+    for (k, _, v) in &report.settings {
+        match v {
+            Some(v) => info(&format!("set {}={}", k, v)),
+            None => info(&format!("unset {}", k)),
+        }
+    }
+    if let Some((_, on)) = report.enabled {
+        info(&format!("{} {}", if on { "enabled" } else { "disabled" }, SCRIPT_ID));
+    }
+    if output::json() {
+        output::data("import", import_json(&report));
+    } else {
+        info(&format!(
+            "{} added, {} skipped, {} removed",
+            report.added.len(),
+            report.skipped.len(),
+            report.removed.len()
+        ));
+    }
+    if report.changed {
+        reconfigure(target);
+    }
+}
 
-    let cfg = extract_script_config(&lines);
+/// `match` exit codes: 0 when the window would be forced.
+const MATCH_NONE: i32 = 1;
+const MATCH_ERROR: i32 = 2;
 
-    let joined = join_classes(new_classes);
-    let new_line = format!("{}={}", KEY_NAME, joined);
+/// `apply` exit codes.
+const APPLY_UNCHANGED: i32 = 0;
+const APPLY_ERROR: i32 = 1;
+const APPLY_CHANGED: i32 = 2;
 
-    match (cfg.group_header_index, cfg.value_line_index) {
-        (Some(_hdr), Some(val_idx)) => lines[val_idx] = new_line,
-        (Some(hdr_idx), None) => lines.insert(hdr_idx + 1, new_line),
-        (None, _) => {
-            if !lines.is_empty() && !lines.last().unwrap().is_empty() {
-                lines.push(String::new());
-            }
-            lines.push(format!("[{}]", GROUP_NAME));
-            lines.push(new_line);
-        }
+fn print_plan(plan: &ImportReport) {
+    for r in &plan.added {
+        println!("{} {}", green("+"), r);
     }
-
-    let mut out = String::new();
-    for line in lines {
-        out.push_str(&line);
-        out.push('\n');
+    for r in &plan.removed {
+        println!("{} {}", soft_red("-"), r);
     }
+    for (k, before, after) in &plan.settings {
+        println!(
+            "{} {}: {} -> {}",
+            yellow("~"),
+            k,
+            describe_value(before.as_deref()),
+            describe_value(after.as_deref())
+        );
+    }
+    if let Some((before, on)) = &plan.enabled {
+        println!(
+            "{} {}: {} -> {}",
+            yellow("~"),
+            config::enabled_key(),
+            describe_value(before.as_deref()),
+            on
+        );
+    }
+}
 
-    atomic_write(&path, &out)?;
+fn run_apply(target: &Target, args: &[String]) {
+    // Any failure below is reported with fail() and exits APPLY_ERROR.
+    output::set_exit_code(APPLY_ERROR);
+    let Some((path, format, _)) = file_args("apply", args, &[]) else {
+        return;
+    };
+    let Some(file) = read_portable(path, format) else {
+        return;
+    };
+    let plan = match portable::plan(target, &file, true) {
+        Ok(p) => p,
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            return fail("invalid-value", &format!("{}: {}", path, e));
+        }
+        Err(e) => return fail("config-read", &format!("failed to read config: {}", e)),
+    };
+    if output::json() {
+        output::data("plan", import_json(&plan));
+    } else {
+        print_plan(&plan);
+    }
+    if !plan.changed {
+        info("no changes");
+        output::set_exit_code(APPLY_UNCHANGED);
+        return;
+    }
 
-    if do_reconfigure {
-        reload_kwin_config(target);
+    // Re-planned under the kwinrc lock, so this is what was really written.
+    let report = match portable::import(target, &file, true) {
+        Ok(r) => r,
+        Err(e) => return import_error(path, e),
+    };
+    let n = report.added.len()
+        + report.removed.len()
+        + report.settings.len()
+        + usize::from(report.enabled.is_some());
+    info(&format!("applied {} change(s)", n));
+    if report.changed && !reconfigure(target) {
+        // Written, but KWin did not pick it up: exit APPLY_ERROR, as the
+        // report says "ok": false.
+        return;
+    }
+    // A dry run only filled the overlay: nothing was applied.
+    if report.changed && !dryrun::enabled() {
+        output::set_exit_code(APPLY_CHANGED);
+    } else {
+        output::set_exit_code(APPLY_UNCHANGED);
     }
+}
 
-    Ok(())
+fn run_render(args: &[String]) {
+    let Some((path, format, _)) = file_args("render", args, &[]) else {
+        return;
+    };
+    let Some(file) = read_portable(path, format) else {
+        return;
+    };
+    match portable::render_kwinrc(&file) {
+        Ok(text) if output::json() => output::data("kwinrc", Json::from(text)),
+        Ok(text) => print!("{}", text),
+        Err(e) => fail("invalid-value", &format!("{}: {}", path, e)),
+    }
 }
 
-fn get_enabled(target: &Target) -> io::Result<Option<bool>> {
-    let contents = read_kwinrc(target).unwrap_or_default();
-    let lines: Vec<String> = contents.lines().map(|s| s.to_string()).collect();
-    let (_hdr, _val, enabled) = extract_plugins_enabled(&lines);
-    Ok(enabled)
+fn import_json(report: &ImportReport) -> Json {
+    let skipped = report
+        .skipped
+        .iter()
+        .map(|(r, why)| {
+            let mut row = rule_json(r);
+            if let Json::Obj(kv) = &mut row {
+                kv.push(("reason".to_string(), Json::from(why.as_str())));
+            }
+            row
+        })
+        .collect();
+    let settings = report
+        .settings
+        .iter()
+        .map(|(k, before, after)| {
+            Json::obj(vec![
+                ("key", Json::from(k.as_str())),
+                ("before", Json::from(before.clone())),
+                ("after", Json::from(after.clone())),
+            ])
+        })
+        .collect();
+    let enabled = report.enabled.as_ref().map(|(before, on)| {
+        Json::obj(vec![
+            ("before", Json::from(before.clone())),
+            ("after", Json::from(*on)),
+        ])
+    });
+    Json::obj(vec![
+        ("added", Json::Arr(report.added.iter().map(rule_json).collect())),
+        ("skipped", Json::Arr(skipped)),
+        ("removed", Json::Arr(report.removed.iter().map(rule_json).collect())),
+        ("settings", Json::Arr(settings)),
+        ("enabled", Json::from(enabled)),
+        ("changed", Json::from(report.changed)),
+    ])
 }
 
-fn set_enabled(target: &Target, enabled: bool, do_reconfigure: bool) -> io::Result<()> {
-    let path = config_path_for(target);
-    let contents = read_kwinrc(target).unwrap_or_default();
+// -------------------------------
+// History
+// -------------------------------
 
-    let mut lines: Vec<String> = if contents.is_empty() {
-        Vec::new()
-    } else {
-        contents.lines().map(|s| s.to_string()).collect()
-    };
+fn describe_snapshot(snap: &Snapshot) -> String {
+    format!(
+        "{} ({}, {})",
+        snap.command,
+        history::format_time(snap.time),
+        snap.user
+    )
+}
 
-    let (hdr_idx, val_idx, _cur) = extract_plugins_enabled(&lines);
+fn print_history(snaps: &[Snapshot]) {
+    let w = snaps.len().to_string().len();
+    for (n, snap) in snaps.iter().enumerate() {
+        println!(
+            "{:>w$}  {}  {}  {}",
+            n + 1,
+            dim(&history::format_time(snap.time)),
+            col_left(&snap.user, 8, None),
+            snap.command,
+            w = w
+        );
+        println!("{:>w$}  {}", "", dim(&format!("before: {}", snap.summary())), w = w);
+    }
+}
 
-    let key = format!("{}Enabled", SCRIPT_ID);
-    let new_line = format!("{}={}", key, if enabled { "true" } else { "false" });
+// -------------------------------
+// Match verdict
+// -------------------------------
 
-    match (hdr_idx, val_idx) {
-        (Some(_h), Some(v)) => lines[v] = new_line,
-        (Some(h), None) => lines.insert(h + 1, new_line),
-        (None, _) => {
-            if !lines.is_empty() && !lines.last().unwrap().is_empty() {
-                lines.push(String::new());
-            }
-            lines.push(format!("[{}]", PLUGINS_GROUP));
-            lines.push(new_line);
+fn print_match(v: &rules::Verdict) {
+    match &v.matched {
+        Some((property, rule)) => {
+            line2(10, "entry", Some("36"), &rule.to_string(), false);
+            line2(10, "key", Some("36"), &format!("{} (on {})", rule.key(), property), true);
         }
+        None => line2(10, "entry", Some("36"), "(none)", true),
+    }
+    match (v.action, v.blocked) {
+        (Some(action), _) => println!("{}", green(&format!("forced ({})", action))),
+        (None, Some(b)) => println!(
+            "{} {}",
+            yellow(&format!("not forced: {}", b.as_str())),
+            dim(&format!("({})", b.describe()))
+        ),
+        (None, None) => println!("{}", yellow("not forced")),
     }
+}
 
-    let mut out = String::new();
-    for line in lines {
-        out.push_str(&line);
-        out.push('\n');
+fn match_json(v: &rules::Verdict) -> Json {
+    Json::obj(vec![
+        ("forced", Json::from(v.action.is_some())),
+        ("action", Json::from(v.action)),
+        ("blocked", Json::from(v.blocked.map(rules::Blocked::as_str))),
+        ("reason", Json::from(v.blocked.map(rules::Blocked::describe))),
+        ("property", Json::from(v.matched.as_ref().map(|(p, _)| *p))),
+        ("rule", v.matched.as_ref().map_or(Json::Null, |(_, r)| rule_json(r))),
+    ])
+}
+
+// -------------------------------
+// Doctor
+// -------------------------------
+
+/// Run all checks and print them; the command exits 1 if any failed.
+fn run_doctor(target: &Target) {
+    let checks = doctor::run(target);
+    let code = doctor::exit_code(&checks);
+    if code != 0 {
+        output::set_exit_code(code);
     }
 
-    atomic_write(&path, &out)?;
+    let count = |s: Status| checks.iter().filter(|c| c.status == s).count();
+    let failed = count(Status::Fail);
+    if output::json() {
+        let rows = checks
+            .iter()
+            .map(|c| {
+                Json::obj(vec![
+                    ("check", Json::from(c.name)),
+                    ("status", Json::from(c.status.as_str())),
+                    ("detail", Json::from(c.detail.as_str())),
+                ])
+            })
+            .collect();
+        output::data("checks", Json::Arr(rows));
+        if failed > 0 {
+            output::error("check-failed", &format!("{} check(s) failed", failed));
+        }
+        return;
+    }
 
-    if do_reconfigure {
-        reload_kwin_config(target);
+    let name_w = checks.iter().map(|c| display_width(c.name)).max().unwrap_or(0);
+    for c in &checks {
+        let tag = match c.status {
+            Status::Pass => green("pass"),
+            Status::Warn => yellow("warn"),
+            Status::Fail => soft_red("FAIL"),
+        };
+        println!("{}  {}  {}", tag, col_left(c.name, name_w, None), c.detail);
     }
 
-    Ok(())
+    info(&format!(
+        "{} passed, {} warning(s), {} failed",
+        count(Status::Pass),
+        count(Status::Warn),
+        failed
+    ));
 }
 
 // -------------------------------
-// Wrapper: auto class naming
+// Exec helper
 // -------------------------------
 
-fn basename(path: &str) -> &str {
-    path.rsplit('/').next().unwrap_or(path)
+/// How long `wrap --learn` waits for a window unless told otherwise.
+const LEARN_TIMEOUT_SECS: u64 = 30;
+
+/// What `wrap --learn` checks the window it finds against.
+struct Learn {
+    class: String,
+    key: String,
+    /// This wrap put `class` into the list (it was not configured before).
+    added: bool,
+    timeout: Duration,
 }
 
-fn auto_class_from_argv0(argv0: &str) -> String {
-    let base = basename(argv0);
-    let base = base.strip_suffix(".desktop").unwrap_or(base);
-    let base = base.strip_suffix(".sh").unwrap_or(base);
+/// Run the wrapped command under supervision: learn its window class if
+/// asked, give an ephemeral grant back when it exits, and pass its exit code
+/// on.
+fn run_supervised(
+    target: &Target,
+    grant: Option<grants::Grant>,
+    argv: &[String],
+    learn: Option<Learn>,
+    reconf: bool,
+) {
+    // As with exec, the JSON report goes out before the command runs; when
+    // learning, once the window has been seen.
+    if learn.is_none() {
+        output::finish(target_state(target));
+    }
+    let mut held = grant.clone();
+    let status = match wrap::spawn_supervised(argv) {
+        Ok(child) => {
+            if let Some(learn) = &learn {
+                let learned = learn_class(target, learn, child.id(), grant.is_some(), reconf);
+                held = held.or(learned);
+                output::finish(target_state(target));
+            }
+            wrap::wait_supervised(child)
+        }
+        Err(e) => Err(e),
+    };
 
-    let mut words = Vec::new();
-    let mut cur = String::new();
-    for ch in base.chars() {
-        if ch.is_ascii_alphanumeric() {
-            cur.push(ch);
-        } else if !cur.is_empty() {
-            words.push(cur.clone());
-            cur.clear();
+    // Releasing one grant gives up every class this process holds.
+    if let Some(grant) = &held {
+        match grants::release(target, grant) {
+            Ok(removed) => {
+                for class in &removed {
+                    info(&format!("removed class: {}", class));
+                }
+                if !removed.is_empty() && reconf {
+                    reconfigure(target);
+                }
+            }
+            Err(e) => fail(
+                "config-write",
+                &format!("wrap: failed to release class {}: {}", grant.class, e),
+            ),
         }
     }
-    if !cur.is_empty() {
-        words.push(cur);
+    match status {
+        Ok(st) => output::set_exit_code(wrap::exit_code(st)),
+        Err(e) => {
+            fail("exec", &format!("failed to run {}: {}", argv[0], e));
+            output::set_exit_code(127);
+        }
     }
+}
 
-    if words.is_empty() {
-        return "App".to_string();
+/// Wait for the first window of `pid`'s process tree and make sure the list
+/// matches it: add what the window is really called when the requested name
+/// does not match, and drop the requested name if this wrap added it. An
+/// ephemeral wrap holds the learned name as a grant instead, returned here.
+fn learn_class(
+    target: &Target,
+    learn: &Learn,
+    pid: u32,
+    ephemeral: bool,
+    reconf: bool,
+) -> Option<grants::Grant> {
+    info(&format!(
+        "waiting up to {}s for the command's first window...",
+        learn.timeout.as_secs()
+    ));
+    let w = match wrap::learn_window(target, pid, learn.timeout) {
+        Ok(Some(w)) => w,
+        Ok(None) => {
+            info("no window of the command appeared; nothing learned");
+            return None;
+        }
+        Err(e) => {
+            fail("kwin", &format!("wrap: could not watch windows: {}", e));
+            return None;
+        }
+    };
+    let matched = config::match_window(&w, std::slice::from_ref(&learn.key));
+    output::data("learned", window_json(&w, matched));
+    if let Some(property) = matched {
+        info(&format!("learned: {} matches the window's {}", learn.class, property));
+        return None;
     }
-
-    // echo -> EchoApp
-    let mut out = String::new();
-    let first = &words[0];
-    let mut chars = first.chars();
-    if let Some(c0) = chars.next() {
-        out.push(c0.to_ascii_uppercase());
-        for c in chars {
-            out.push(c.to_ascii_lowercase());
+    let Some(found) = wrap::learned_class(&w) else {
+        fail("kwin", "wrap: the window has no desktop file, class or name");
+        return None;
+    };
+    info(&format!(
+        "{} does not match the window it opened (desktopFileName={}, resourceClass={}, \
+         resourceName={})",
+        learn.class,
+        or_dash(&w.desktop_file),
+        or_dash(&w.resource_class),
+        or_dash(&w.resource_name)
+    ));
+    output::data("learned_class", Json::from(found.as_str()));
+
+    let mut grant = None;
+    let changed = if ephemeral {
+        match grants::acquire(target, &found) {
+            Ok(g) => {
+                let added = g.added;
+                grant = Some(g);
+                added
+            }
+            Err(e) => {
+                fail("config-write", &format!("wrap: failed to grant class: {}", e));
+                return None;
+            }
         }
+    } else {
+        let added = match add_class(target, &found) {
+            Ok(added) => added,
+            Err(e) => {
+                fail("config-write", &format!("wrap: failed to add {}: {}", found, e));
+                return None;
+            }
+        };
+        if learn.added && config::remove_class(target, &learn.class).unwrap_or(false) {
+            info(&format!("removed class: {}", learn.class));
+        }
+        added
+    };
+    if changed {
+        info(&format!("added learned class: {}", found));
+        if reconf {
+            reconfigure(target);
+        }
+    } else {
+        info(&format!("learned class already present: {}", found));
     }
-    out.push_str("App");
-    out
+    grant
 }
 
-// -------------------------------
-// Exec helper
-// -------------------------------
+fn or_dash(s: &str) -> &str {
+    if s.is_empty() { "-" } else { s }
+}
+
+/// Class name for a command line (or desktop id), saying where it came
+/// from; `None` after reporting a bad focusctl config.
+fn resolve_command(target: &Target, argv: &[String]) -> Option<String> {
+    let (inner, found) = match desktop::resolve_command(target, argv) {
+        Ok(r) => r,
+        Err(e) => {
+            fail("config-read", &format!("failed to read focusctl config: {}", e));
+            return None;
+        }
+    };
+    if !inner.via.is_empty() {
+        info(&format!(
+            "{} runs {} {}",
+            inner.via.join(" -> "),
+            if inner.app_id { "app" } else { "program" },
+            inner.program
+        ));
+    }
+    output::data("launchers", Json::strs(&inner.via));
+    let query = inner.program.as_str();
+    match found.source.entry() {
+        Some(e) => {
+            info(&format!(
+                "{} -> {} ({} of {})",
+                query,
+                found.class,
+                found.source.as_str(),
+                e.path.display()
+            ));
+            output::data(
+                "desktop_entry",
+                Json::obj(vec![
+                    ("id", Json::from(e.id.as_str())),
+                    ("path", Json::from(e.path.display().to_string())),
+                ]),
+            );
+        }
+        None if inner.app_id => {
+            info(&format!("no desktop entry for {}; using the app id", query));
+            output::data("desktop_entry", Json::Null);
+        }
+        None => {
+            info(&format!("no desktop entry runs {}; guessing {}", query, found.class));
+            output::data("desktop_entry", Json::Null);
+        }
+    }
+    output::data("class_source", Json::from(found.source.as_str()));
+    Some(found.class)
+}
 
 #[cfg(unix)]
 fn exec_replace(mut cmd: Command) -> io::Result<()> {
@@ -817,49 +1520,185 @@ fn exec_replace(mut cmd: Command) -> io::Result<()> {
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let _prog = args.get(0).cloned().unwrap_or_else(|| "focusctl".to_string());
 
-    // Parse global options
     let mut i = 1usize;
+    let target = select_target(&args, &mut i);
+    if let Some(target) = &target {
+        run_command(target, &args, i);
+        if dryrun::enabled() {
+            report_dry_run();
+        }
+    }
+
+    let code = output::finish(target.as_ref().map(target_state).unwrap_or_default());
+    if code != 0 {
+        std::process::exit(code);
+    }
+}
+
+/// Show what a `--dry-run` would have written: unified diffs on stdout, or
+/// a "diff" list in the JSON report.
+fn report_dry_run() {
+    let changes = dryrun::changes();
+    let diffs: Vec<(String, String)> = changes
+        .iter()
+        .map(|c| {
+            let path = c.path.display().to_string();
+            let from = if c.before.is_some() { path.as_str() } else { "/dev/null" };
+            let text = diff::unified(c.before.as_deref().unwrap_or(""), &c.after, from, &path);
+            (path.clone(), text)
+        })
+        .collect();
+
+    if output::json() {
+        output::data("dry_run", Json::from(true));
+        output::data(
+            "diff",
+            Json::Arr(
+                diffs
+                    .iter()
+                    .map(|(path, text)| {
+                        Json::obj(vec![
+                            ("path", Json::from(path.as_str())),
+                            ("diff", Json::from(text.as_str())),
+                        ])
+                    })
+                    .collect(),
+            ),
+        );
+        return;
+    }
+    if diffs.is_empty() {
+        info("no changes");
+        return;
+    }
+    for (_, text) in &diffs {
+        for line in text.lines() {
+            let shown = if line.starts_with("---") || line.starts_with("+++") {
+                bold(line)
+            } else if line.starts_with("@@") {
+                cyan(line)
+            } else if line.starts_with('+') {
+                green(line)
+            } else if line.starts_with('-') {
+                soft_red(line)
+            } else {
+                line.to_string()
+            };
+            println!("{}", shown);
+        }
+    }
+}
+
+/// Target, config path, classes, rules and enabled flag: the part of every
+/// `--json` report that does not depend on the command.
+fn target_state(target: &Target) -> Vec<(String, Json)> {
+    let classes: Json = match get_classes(target) {
+        Ok(classes) => Json::Arr(
+            classes
+                .iter()
+                .map(|c| {
+                    Json::obj(vec![
+                        ("class", Json::from(c.as_str())),
+                        ("key", Json::from(class_key(c))),
+                    ])
+                })
+                .collect(),
+        ),
+        Err(_) => Json::Null,
+    };
+    vec![
+        (
+            "target".to_string(),
+            Json::obj(vec![
+                ("uid", Json::from(target.uid)),
+                ("user", Json::from(target.user.as_str())),
+                ("home", Json::from(target.home.display().to_string())),
+            ]),
+        ),
+        (
+            "config".to_string(),
+            Json::from(config_path_for(target).display().to_string()),
+        ),
+        (
+            "config_source".to_string(),
+            Json::from(config::config_home_for(target).source),
+        ),
+        ("classes".to_string(), classes),
+        (
+            "rules".to_string(),
+            match get_rules(target) {
+                Ok(rules) => Json::Arr(rules.iter().map(rule_json).collect()),
+                Err(_) => Json::Null,
+            },
+        ),
+        (
+            "enabled".to_string(),
+            Json::from(get_enabled(target).ok().flatten()),
+        ),
+        (
+            "profile".to_string(),
+            match profile::active_profile(target).ok().flatten() {
+                Some(p) => Json::obj(vec![
+                    ("name", Json::from(p.name.as_str())),
+                    ("modified", Json::from(p.modified)),
+                ]),
+                None => Json::Null,
+            },
+        ),
+    ]
+}
+
+/// Parse global options (advancing `i` past them) and resolve the target.
+/// `None` means nothing more to do: help was shown or an error reported.
+fn select_target(args: &[String], i: &mut usize) -> Option<Target> {
     let mut target_uid: Option<u32> = None;
     let mut target_user: Option<String> = None;
     let mut session_auto = false;
 
-    while i < args.len() {
-        match args[i].as_str() {
+    while *i < args.len() {
+        match args[*i].as_str() {
             "--uid" => {
-                i += 1;
-                if i >= args.len() {
-                    err("--uid requires a value");
+                *i += 1;
+                if *i >= args.len() {
+                    fail("usage", "--uid requires a value");
                     usage();
-                    return;
+                    return None;
                 }
-                match args[i].parse::<u32>() {
+                match args[*i].parse::<u32>() {
                     Ok(x) => target_uid = Some(x),
                     Err(_) => {
-                        err("invalid uid");
-                        return;
+                        fail("usage", "invalid uid");
+                        return None;
                     }
                 }
-                i += 1;
+                *i += 1;
             }
             "--user" => {
-                i += 1;
-                if i >= args.len() {
-                    err("--user requires a value");
+                *i += 1;
+                if *i >= args.len() {
+                    fail("usage", "--user requires a value");
                     usage();
-                    return;
+                    return None;
                 }
-                target_user = Some(args[i].clone());
-                i += 1;
+                target_user = Some(args[*i].clone());
+                *i += 1;
             }
             "--session-auto" => {
                 session_auto = true;
-                i += 1;
+                *i += 1;
+            }
+            "--json" => {
+                output::set_json(true);
+                *i += 1;
+            }
+            "--dry-run" => {
+                dryrun::set_enabled(true);
+                *i += 1;
             }
             "--help" | "-h" => {
                 usage();
-                return;
+                return None;
             }
             _ => break,
         }
@@ -867,237 +1706,244 @@ fn main() {
 
     // Determine target user
     let target: Target = if let Some(name) = target_user.clone() {
-        match find_user_by_name(&name) {
+        match target::find_user_by_name(&name) {
             Ok(Some(t)) => t,
             Ok(None) => {
-                err(&format!("unknown user: {}", name));
-                return;
+                fail("target", &format!("unknown user: {}", name));
+                return None;
             }
             Err(e) => {
-                err(&format!("failed to read /etc/passwd: {}", e));
-                return;
+                fail("target", &format!("failed to read /etc/passwd: {}", e));
+                return None;
             }
         }
     } else if let Some(uid) = target_uid {
-        match find_user_by_uid(uid) {
+        match target::find_user_by_uid(uid) {
             Ok(Some(t)) => t,
             Ok(None) => {
-                err(&format!("unknown uid: {}", uid));
-                return;
+                fail("target", &format!("unknown uid: {}", uid));
+                return None;
             }
             Err(e) => {
-                err(&format!("failed to read /etc/passwd: {}", e));
-                return;
+                fail("target", &format!("failed to read /etc/passwd: {}", e));
+                return None;
             }
         }
     } else if session_auto {
-        if !have_cmd("loginctl") {
-            err("loginctl not available; cannot --session-auto");
-            return;
-        }
-
         // Pick the first Active user session (wayland/x11, class user).
-        let out = Command::new("loginctl")
-            .args(["list-sessions", "--no-legend"])
-            .output()
-            .ok();
-
-        let mut picked: Option<u32> = None;
-        if let Some(out) = out {
-            if out.status.success() {
-                let text = String::from_utf8_lossy(&out.stdout);
-                'outer: for line in text.lines() {
-                    let sid = match line.split_whitespace().next() {
-                        Some(x) => x,
-                        None => continue,
-                    };
-                    let active = Command::new("loginctl")
-                        .args(["show-session", sid, "-p", "Active", "--value"])
-                        .output()
-                        .ok()
-                        .and_then(|o| String::from_utf8(o.stdout).ok())
-                        .map(|s| s.trim().to_string())
-                        .unwrap_or_default();
-                    if active != "yes" {
-                        continue;
-                    }
-                    let class = Command::new("loginctl")
-                        .args(["show-session", sid, "-p", "Class", "--value"])
-                        .output()
-                        .ok()
-                        .and_then(|o| String::from_utf8(o.stdout).ok())
-                        .map(|s| s.trim().to_string())
-                        .unwrap_or_default();
-                    if class != "user" {
-                        continue;
-                    }
-                    let ty = Command::new("loginctl")
-                        .args(["show-session", sid, "-p", "Type", "--value"])
-                        .output()
-                        .ok()
-                        .and_then(|o| String::from_utf8(o.stdout).ok())
-                        .map(|s| s.trim().to_string())
-                        .unwrap_or_default();
-                    if ty != "wayland" && ty != "x11" {
-                        continue;
-                    }
-                    let user_uid = Command::new("loginctl")
-                        .args(["show-session", sid, "-p", "User", "--value"])
-                        .output()
-                        .ok()
-                        .and_then(|o| String::from_utf8(o.stdout).ok())
-                        .map(|s| s.trim().to_string())
-                        .unwrap_or_default();
-                    if let Ok(u) = user_uid.parse::<u32>() {
-                        picked = Some(u);
-                        break 'outer;
-                    }
-                }
+        let uid = match session::active_graphical(None) {
+            Ok(Some(s)) => s.uid,
+            Ok(None) => {
+                fail("session", "could not auto-detect active graphical session user");
+                return None;
             }
-        }
-
-        let uid = match picked {
-            Some(u) => u,
-            None => {
-                err("could not auto-detect active graphical session user");
-                return;
+            Err(e) => {
+                fail("session", &format!("could not list logind sessions: {}", e));
+                return None;
             }
         };
 
-        match find_user_by_uid(uid) {
+        match target::find_user_by_uid(uid) {
             Ok(Some(t)) => t,
             _ => {
-                err("could not resolve session uid to a user");
-                return;
+                fail("target", "could not resolve session uid to a user");
+                return None;
             }
         }
     } else {
         // Default: current user context
-        let uid = current_uid();
-        let user = current_user();
-        let home = current_home();
-        Target { uid, user, home }
+        target::current_target()
     };
 
     // Use Target.user so it isn't dead-code, and it’s genuinely useful for UX.
-    // Keep it subtle (dim).
-    info(&format!(
-        "target: {} (uid {})",
-        target.user,
-        target.uid
-    ));
+    // Keep it subtle (dim). The JSON report carries it in "target" instead.
+    if !output::json() {
+        info(&format!(
+            "target: {} (uid {})",
+            target.user,
+            target.uid
+        ));
+        let home = config::config_home_for(&target);
+        info(&format!("config: {} ({})", config_path_for(&target).display(), home.source));
+    }
+    Some(target)
+}
 
+fn run_command(target: &Target, args: &[String], mut i: usize) {
     // Remaining args: command...
     if i >= args.len() {
+        fail("usage", "missing command");
         usage();
         return;
     }
 
     let cmd = args[i].clone();
     i += 1;
+    output::set_command(&cmd);
 
     match cmd.as_str() {
         "list-classes" => {
             let mut show_keys = false;
+            let mut long = false;
             while i < args.len() {
                 match args[i].as_str() {
                     "--keys" | "-k" => show_keys = true,
+                    "--long" | "-l" => long = true,
                     _ => break,
                 }
                 i += 1;
             }
 
-            match get_classes(&target) {
-                Ok(classes) => {
-                    if classes.is_empty() {
+            match effective_rules(target) {
+                Ok(_) if output::json() => {}
+                Ok(entries) => {
+                    if let Ok(Some(p)) = profile::active_profile(target) {
+                        info(&profile_label(&p));
+                    }
+                    if entries.is_empty() {
                         println!("(no forced classes configured)");
+                    } else if long {
+                        let stored = get_rules(target).unwrap_or_default();
+                        let mode = schema::lookup("mode").expect("schema key");
+                        let stored_mode = get_script_value(target, mode.name).ok().flatten();
+                        print_rules(&entries, &stored, &mode.effective(stored_mode.as_deref()));
                     } else if show_keys {
-                        for c in classes {
-                            println!("{:<24} -> {}", c, class_key(&c));
+                        for r in entries {
+                            println!("{:<24} -> {}", r.to_string(), r.key());
                         }
                     } else {
-                        for c in classes {
-                            println!("{}", c);
+                        for r in entries {
+                            println!("{}", r);
                         }
                     }
                 }
-                Err(e) => err(&format!("failed to read config: {}", e)),
+                Err(e) => fail("config-read", &format!("failed to read config: {}", e)),
             }
         }
 
-        "list-keys" => match get_classes(&target) {
-            Ok(classes) => {
-                if classes.is_empty() {
+        "list-keys" => match effective_rules(target) {
+            Ok(_) if output::json() => {}
+            Ok(entries) => {
+                if entries.is_empty() {
                     println!("(no forced classes configured)");
                 } else {
-                    for c in classes {
-                        println!("{:<24} -> {}", c, class_key(&c));
+                    for r in entries {
+                        println!("{:<24} -> {}", r.class, r.key());
                     }
                 }
             }
-            Err(e) => err(&format!("failed to read config: {}", e)),
+            Err(e) => fail("config-read", &format!("failed to read config: {}", e)),
         },
 
         "add-class" => {
             let class = match args.get(i) {
                 Some(c) => c.clone(),
                 None => {
-                    err("add-class requires <window-class>");
+                    fail("usage", "add-class requires <window-class>");
                     return;
                 }
             };
+            i += 1;
 
-            let input = class.trim().to_string();
-            let ikey = class_key(&input);
-            if ikey.is_empty() {
-                err("empty class");
-                return;
+            let mut rule = Rule::plain(&class);
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--mode" => {
+                        let spec = schema::lookup("mode").expect("schema key");
+                        let raw = match args.get(i + 1) {
+                            Some(v) => v,
+                            None => {
+                                fail("usage", "--mode requires activate|raise");
+                                return;
+                            }
+                        };
+                        match spec.normalize(raw) {
+                            Ok(v) => rule.mode = rules::parse_mode(&v),
+                            Err(e) => {
+                                fail("invalid-value", &e);
+                                return;
+                            }
+                        }
+                        i += 1;
+                    }
+                    "--dialogs-only" | "--normal-only" if rule.only.is_some() => {
+                        fail("usage", "--dialogs-only and --normal-only are exclusive");
+                        return;
+                    }
+                    "--dialogs-only" => rule.only = Some(Only::Dialogs),
+                    "--normal-only" => rule.only = Some(Only::Normal),
+                    other => {
+                        fail("usage", &format!("unknown add-class option: {}", other));
+                        return;
+                    }
+                }
+                i += 1;
             }
 
-            let mut classes = get_classes(&target).unwrap_or_default();
-            let exists = classes.iter().any(|c| class_key(c) == ikey);
-
-            if exists {
-                info("class already present");
-                return;
+            if rule.is_plain() {
+                report_add_class(target, &class);
+            } else {
+                report_add_rule(target, &rule);
             }
+        }
 
-            classes.push(input);
-            if let Err(e) = set_classes(&target, &classes, true) {
-                err(&format!("failed to write config: {}", e));
-            } else {
-                info("added class");
+        "pick" => {
+            let mut field: Option<usize> = None;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--desktop" => field = Some(0),
+                    "--class" => field = Some(1),
+                    "--name" => field = Some(2),
+                    other => {
+                        fail("usage", &format!("unknown pick option: {}", other));
+                        return;
+                    }
+                }
+                i += 1;
             }
+
+            info("click the window to whitelist (Esc to cancel)...");
+            let picked = match bus::pick_kwin_window(target) {
+                Ok(Some(w)) => w,
+                Ok(None) => {
+                    info("cancelled");
+                    return;
+                }
+                Err(e) => {
+                    fail("kwin", &format!("could not query window: {}", e));
+                    return;
+                }
+            };
+
+            output::data("picked", window_json(&picked, None));
+            let choice = match field {
+                Some(n) => picked.candidates()[n].1.to_string(),
+                None => match choose_candidate(&picked) {
+                    Some(c) => c,
+                    None => return,
+                },
+            };
+            output::data("class", Json::from(choice.as_str()));
+            report_add_class(target, &choice);
         }
 
         "remove-class" => {
             let class = match args.get(i) {
                 Some(c) => c.clone(),
                 None => {
-                    err("remove-class requires <window-class>");
+                    fail("usage", "remove-class requires <window-class>");
                     return;
                 }
             };
 
-            let tkey = class_key(&class);
-            if tkey.is_empty() {
-                err("empty class");
-                return;
-            }
-
-            let mut classes = get_classes(&target).unwrap_or_default();
-            let before = classes.len();
-            classes.retain(|c| class_key(c) != tkey);
-
-            if classes.len() == before {
-                info("class not found");
-                return;
-            }
-
-            if let Err(e) = set_classes(&target, &classes, true) {
-                err(&format!("failed to write config: {}", e));
-            } else {
-                info("removed class");
+            match config::remove_class(target, &class) {
+                Ok(true) => {
+                    info("removed class");
+                    reconfigure(target);
+                }
+                Ok(false) => info("class not found"),
+                Err(e) if e.kind() == io::ErrorKind::InvalidInput => fail("usage", &e.to_string()),
+                Err(e) => fail("config-write", &format!("failed to write config: {}", e)),
             }
         }
 
@@ -1105,65 +1951,383 @@ fn main() {
             let spec = match args.get(i) {
                 Some(s) => s.clone(),
                 None => {
-                    err("set-classes requires a list like 'a;b;c'");
+                    fail("usage", "set-classes requires a list like 'a;b;c'");
                     return;
                 }
             };
 
-            let classes = parse_classes(&spec);
-            if let Err(e) = set_classes(&target, &classes, true) {
-                err(&format!("failed to write config: {}", e));
+            let classes = match check_classes(&spec) {
+                Ok(c) => c,
+                Err(e) => {
+                    fail("invalid-value", &e);
+                    return;
+                }
+            };
+            if let Err(e) = set_classes(target, &classes) {
+                fail("config-write", &format!("failed to write config: {}", e));
             } else {
                 info("set classes");
+                reconfigure(target);
             }
         }
 
         "clear" => {
-            let classes: Vec<String> = Vec::new();
-            if let Err(e) = set_classes(&target, &classes, true) {
-                err(&format!("failed to write config: {}", e));
+            if let Err(e) = config::clear_classes(target) {
+                fail("config-write", &format!("failed to write config: {}", e));
             } else {
-                info("cleared classes");
+                info("cleared classes and rules");
+                reconfigure(target);
             }
         }
 
         "enable" => {
-            if let Err(e) = set_enabled(&target, true, true) {
-                err(&format!("failed to enable script: {}", e));
+            if let Err(e) = set_enabled(target, true) {
+                fail("config-write", &format!("failed to enable script: {}", e));
             } else {
                 info(&format!("enabled {}", SCRIPT_ID));
+                reconfigure(target);
             }
         }
 
         "disable" => {
-            if let Err(e) = set_enabled(&target, false, true) {
-                err(&format!("failed to disable script: {}", e));
+            if let Err(e) = set_enabled(target, false) {
+                fail("config-write", &format!("failed to disable script: {}", e));
             } else {
                 info(&format!("disabled {}", SCRIPT_ID));
+                reconfigure(target);
             }
         }
 
-        "enabled" => match get_enabled(&target) {
+        "enabled" => match get_enabled(target) {
+            Ok(_) if output::json() => {}
             Ok(Some(true)) => println!("true"),
             Ok(Some(false)) => println!("false"),
             Ok(None) => println!("(unset)"),
-            Err(e) => err(&format!("failed to read enabled flag: {}", e)),
+            Err(e) => fail("config-read", &format!("failed to read enabled flag: {}", e)),
         },
 
         "reconfigure" => {
-            reload_kwin_config(&target);
+            reconfigure(target);
+        }
+
+        "doctor" => run_doctor(target),
+
+        "windows" => {
+            let mut show_keys = false;
+            let mut only_matching = false;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--keys" | "-k" => show_keys = true,
+                    "--matching" | "-m" => only_matching = true,
+                    other => {
+                        fail("usage", &format!("unknown windows option: {}", other));
+                        return;
+                    }
+                }
+                i += 1;
+            }
+
+            let windows = match bus::list_kwin_windows(target) {
+                Ok(w) => w,
+                Err(e) => {
+                    fail("kwin", &format!("could not list KWin windows: {}", e));
+                    return;
+                }
+            };
+            let keys: Vec<String> = effective_rules(target)
+                .unwrap_or_default()
+                .iter()
+                .map(|r| r.key())
+                .collect();
+
+            if output::json() {
+                let rows = windows
+                    .iter()
+                    .filter_map(|w| {
+                        let matched = match_window(w, &keys);
+                        if only_matching && matched.is_none() {
+                            return None;
+                        }
+                        Some(window_json(w, matched))
+                    })
+                    .collect();
+                output::data("windows", Json::Arr(rows));
+            } else {
+                print_windows(&windows, &keys, show_keys, only_matching);
+            }
+        }
+
+        "match" => {
+            // Like grep: errors below exit MATCH_ERROR, not "no match".
+            output::set_exit_code(MATCH_ERROR);
+            let mut w = kwin::WindowInfo {
+                normal: true,
+                ..Default::default()
+            };
+            let mut minimized = false;
+            let mut active = false;
+            while i < args.len() {
+                let field = match args[i].as_str() {
+                    "--desktop-file" => &mut w.desktop_file,
+                    "--class" => &mut w.resource_class,
+                    "--name" => &mut w.resource_name,
+                    "--dialog" => {
+                        w.normal = false;
+                        w.dialog = true;
+                        i += 1;
+                        continue;
+                    }
+                    "--minimized" => {
+                        minimized = true;
+                        i += 1;
+                        continue;
+                    }
+                    "--active" => {
+                        active = true;
+                        i += 1;
+                        continue;
+                    }
+                    other => {
+                        fail("usage", &format!("unknown match option: {}", other));
+                        return;
+                    }
+                };
+                match args.get(i + 1) {
+                    Some(v) => *field = v.clone(),
+                    None => {
+                        fail("usage", &format!("{} requires a value", args[i]));
+                        return;
+                    }
+                }
+                i += 2;
+            }
+            if w.candidates().iter().all(|(_, v)| v.is_empty()) {
+                fail("usage", "match requires --desktop-file, --class or --name");
+                return;
+            }
+
+            let entries = match effective_rules(target) {
+                Ok(e) => e,
+                Err(e) => {
+                    fail("config-read", &format!("failed to read config: {}", e));
+                    return;
+                }
+            };
+            let mode = schema::lookup("mode").expect("schema key");
+            let stored_mode = get_script_value(target, mode.name).ok().flatten();
+            let global_mode = mode.effective(stored_mode.as_deref());
+            let verdict = rules::evaluate(&entries, &global_mode, &w, minimized, active);
+
+            if output::json() {
+                output::data("match", match_json(&verdict));
+            } else {
+                print_match(&verdict);
+            }
+            output::set_exit_code(if verdict.action.is_none() {
+                MATCH_NONE
+            } else {
+                0
+            });
+        }
+
+        "resolve" => {
+            let argv = match args.get(i).map(String::as_str) {
+                Some("--") => &args[i + 1..],
+                _ => &args[i..],
+            };
+            if argv.is_empty() {
+                fail("usage", "resolve requires <program|desktop-id> or a command line");
+                return;
+            }
+            if let Some(class) = resolve_command(target, argv) {
+                output::data("class", Json::from(class.as_str()));
+                if !output::json() {
+                    println!("{}", class);
+                }
+            }
+        }
+
+        "profile" => run_profile(target, &args[i..]),
+        "export" => run_export(target, &args[i..]),
+        "import" => run_import(target, &args[i..]),
+        "apply" => run_apply(target, &args[i..]),
+        "render" => run_render(&args[i..]),
+
+        "history" => {
+            let snaps = history::list_snapshots(target);
+            if output::json() {
+                let rows = snaps.iter().map(Snapshot::to_json).collect();
+                output::data("history", Json::Arr(rows));
+            } else if snaps.is_empty() {
+                println!("(no history)");
+            } else {
+                print_history(&snaps);
+            }
+        }
+
+        "undo" => match history::undo(target) {
+            Ok((snap, changed)) => {
+                info(&format!("undid: {}", describe_snapshot(&snap)));
+                if changed {
+                    reconfigure(target);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => fail("usage", "nothing to undo"),
+            Err(e) => fail("config-write", &format!("failed to write config: {}", e)),
+        },
+
+        "restore" => {
+            let n = match args.get(i).map(|a| a.parse::<usize>()) {
+                Some(Ok(n)) => n,
+                _ => {
+                    fail("usage", "restore requires <n> (see focusctl history)");
+                    return;
+                }
+            };
+            match history::restore(target, n) {
+                Ok((snap, changed)) => {
+                    info(&format!("restored the state before: {}", describe_snapshot(&snap)));
+                    if changed {
+                        reconfigure(target);
+                    } else {
+                        info("(already current)");
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    fail("usage", &format!("{} (see focusctl history)", e))
+                }
+                Err(e) => fail("config-write", &format!("failed to write config: {}", e)),
+            }
+        }
+
+        "get" => match args.get(i) {
+            Some(name) => {
+                let spec = match schema::lookup(name) {
+                    Some(k) => k,
+                    None => {
+                        fail("unknown-key", &format!("unknown key: {}", name));
+                        return;
+                    }
+                };
+                match get_script_value(target, spec.name) {
+                    Ok(v) if output::json() => {
+                        output::data("setting", setting_json(spec.name, v, true))
+                    }
+                    Ok(Some(v)) => println!("{}", spec.effective(Some(&v))),
+                    Ok(None) => {
+                        println!("{}", spec.default);
+                        info("(unset, script default)");
+                    }
+                    Err(e) => fail("config-read", &format!("failed to read config: {}", e)),
+                }
+            }
+            None => {
+                let doc = match read_document(target) {
+                    Ok(d) => d,
+                    Err(e) => {
+                        fail("config-read", &format!("failed to read config: {}", e));
+                        return;
+                    }
+                };
+                if output::json() {
+                    let mut settings: Vec<Json> = schema::SCRIPT_KEYS
+                        .iter()
+                        .map(|k| setting_json(k.name, doc.get(GROUP_NAME, k.name), true))
+                        .collect();
+                    for extra in doc.keys(GROUP_NAME) {
+                        if schema::lookup(&extra).is_none() {
+                            let v = doc.get(GROUP_NAME, &extra);
+                            settings.push(setting_json(&extra, v, false));
+                        }
+                    }
+                    output::data("settings", Json::Arr(settings));
+                    return;
+                }
+                for k in schema::SCRIPT_KEYS {
+                    match doc.get(GROUP_NAME, k.name) {
+                        Some(v) => println!("{:<20} = {}", k.name, v),
+                        None => println!("{:<20} = {} (default)", k.name, k.default),
+                    }
+                }
+                for extra in doc.keys(GROUP_NAME) {
+                    if schema::lookup(&extra).is_none() {
+                        let v = doc.get(GROUP_NAME, &extra).unwrap_or_default();
+                        println!("{:<20} = {} (not used by the script)", extra, v);
+                    }
+                }
+            }
+        },
+
+        "set" => {
+            let (name, raw) = match (args.get(i), args.get(i + 1)) {
+                (Some(n), Some(v)) => (n.clone(), v.clone()),
+                _ => {
+                    fail("usage", "set requires <key> <value>");
+                    return;
+                }
+            };
+            let spec = match schema::lookup(&name) {
+                Some(k) => k,
+                None => {
+                    fail("unknown-key", &format!("unknown key: {}", name));
+                    return;
+                }
+            };
+            report_set_value(target, spec, &raw);
+        }
+
+        "unset" => {
+            let name = match args.get(i) {
+                Some(n) => n.clone(),
+                None => {
+                    fail("usage", "unset requires <key>");
+                    return;
+                }
+            };
+            let spec = match schema::lookup(&name) {
+                Some(k) => k,
+                None => {
+                    fail("unknown-key", &format!("unknown key: {}", name));
+                    return;
+                }
+            };
+            match unset_script_value(target, spec.name) {
+                Ok(true) => {
+                    info(&format!("unset {}", spec.name));
+                    reconfigure(target);
+                }
+                Ok(false) => info(&format!("{} was not set", spec.name)),
+                Err(e) => fail("config-write", &format!("failed to write config: {}", e)),
+            }
+        }
+
+        "mode" | "debug" => {
+            let spec = schema::lookup(&cmd).expect("schema key");
+            match args.get(i) {
+                None => match get_script_value(target, spec.name) {
+                    Ok(v) if output::json() => {
+                        output::data("setting", setting_json(spec.name, v, true))
+                    }
+                    Ok(v) => println!("{}", spec.effective(v.as_deref())),
+                    Err(e) => fail("config-read", &format!("failed to read config: {}", e)),
+                },
+                Some(raw) => report_set_value(target, spec, raw),
+            }
         }
 
         "wrap" => {
-            // wrap <ClassName>|--auto [--dry-run] [--no-enable] [--no-reconfigure] -- <command...>
+            // wrap <ClassName>|--auto [--dry-run] [--ephemeral] [--learn [--learn-timeout <s>]]
+            //      [--no-enable] [--no-reconfigure] -- <command...>
             let mut dry_run = false;
+            let mut ephemeral = false;
+            let mut learn_timeout: Option<u64> = None;
             let mut no_enable = false;
             let mut no_reconf = false;
 
             let class_or_auto = match args.get(i) {
                 Some(s) => s.clone(),
                 None => {
-                    err("wrap requires <ClassName>|--auto and '-- <command...>'");
+                    fail("usage", "wrap requires <ClassName>|--auto and '-- <command...>'");
                     usage();
                     return;
                 }
@@ -1182,6 +2346,20 @@ fn main() {
             while i < args.len() {
                 match args[i].as_str() {
                     "--dry-run" => dry_run = true,
+                    "--ephemeral" => ephemeral = true,
+                    "--learn" => {
+                        learn_timeout.get_or_insert(LEARN_TIMEOUT_SECS);
+                    }
+                    "--learn-timeout" => {
+                        i += 1;
+                        match args.get(i).and_then(|v| v.parse::<u64>().ok()) {
+                            Some(secs) if secs > 0 => learn_timeout = Some(secs),
+                            _ => {
+                                fail("usage", "--learn-timeout requires a number of seconds");
+                                return;
+                            }
+                        }
+                    }
                     "--no-enable" => no_enable = true,
                     "--no-reconfigure" => no_reconf = true,
                     "--" => {
@@ -1189,7 +2367,7 @@ fn main() {
                         break;
                     }
                     _ => {
-                        err(&format!("unknown wrap option: {}", args[i]));
+                        fail("usage", &format!("unknown wrap option: {}", args[i]));
                         return;
                     }
                 }
@@ -1197,72 +2375,117 @@ fn main() {
             }
 
             if i >= args.len() {
-                err("wrap: missing command after '--'");
+                fail("usage", "wrap: missing command after '--'");
                 return;
             }
 
             let cmd_argv: Vec<String> = args[i..].to_vec();
-            let argv0 = cmd_argv.get(0).cloned().unwrap_or_default();
 
             let final_class = if auto {
-                auto_class_from_argv0(&argv0)
+                match resolve_command(target, &cmd_argv) {
+                    Some(class) => class,
+                    None => return,
+                }
             } else {
                 class_name.unwrap_or_else(|| "App".to_string())
             };
 
-            let key = class_key(&final_class);
-            if key.is_empty() {
-                err("wrap: empty class name");
-                return;
-            }
+            let key = match config::check_class(&final_class) {
+                Ok(k) => k,
+                Err(e) => {
+                    fail("usage", &format!("wrap: {}", e));
+                    return;
+                }
+            };
+            output::data("class", Json::from(final_class.as_str()));
+            output::data("exec", Json::strs(&cmd_argv));
 
-            // Ensure the class exists in config (preserve spelling).
-            let mut classes = get_classes(&target).unwrap_or_default();
-            let exists = classes.iter().any(|c| class_key(c) == key);
+            // Same as the global option; the steps below only touch the overlay.
+            if dry_run && !dryrun::enabled() {
+                dryrun::set_enabled(true);
+            }
 
-            if dry_run {
-                info(&format!(
-                    "[dry-run] would ensure integration for class: {}",
-                    final_class
-                ));
-                if !no_enable {
-                    info("[dry-run] would enable script");
+            // An ephemeral grant needs a live wrapper to hold it, so a dry run
+            // shows the plain add instead.
+            let (grant, added) = if ephemeral && !dryrun::enabled() {
+                match grants::acquire(target, &final_class) {
+                    Ok(g) => {
+                        for class in &g.swept {
+                            info(&format!("removed class left by an exited wrapper: {}", class));
+                        }
+                        if g.added {
+                            info(&format!("added class until the command exits: {}", final_class));
+                        } else {
+                            info("class already present");
+                        }
+                        let added = g.added;
+                        (Some(g), added)
+                    }
+                    Err(e) => {
+                        fail("config-write", &format!("wrap: failed to grant class: {}", e));
+                        return;
+                    }
                 }
-                if !no_reconf {
-                    info("[dry-run] would request KWin reconfigure");
+            } else {
+                // Ensure the class exists in config (preserve spelling), in
+                // one locked edit; a rule for the same key counts.
+                match add_class(target, &final_class) {
+                    Ok(added) => (None, added),
+                    Err(e) => {
+                        fail("config-write", &format!("wrap: failed to write class list: {}", e));
+                        return;
+                    }
                 }
-                info(&format!("[dry-run] would exec: {:?}", cmd_argv));
-                return;
+            };
+
+            if !no_enable {
+                let _ = set_enabled(target, true);
             }
 
-            if !exists {
-                classes.push(final_class.clone());
-                if let Err(e) = set_classes(&target, &classes, false) {
-                    err(&format!("wrap: failed to write class list: {}", e));
-                    return;
-                }
+            if !no_reconf {
+                reconfigure(target);
             }
 
-            if !no_enable {
-                let _ = set_enabled(&target, true, false);
+            if dryrun::enabled() {
+                if learn_timeout.is_some() {
+                    info("would add the class of the command's first window if it differs");
+                }
+                if ephemeral {
+                    info(&format!(
+                        "would run {:?}, then remove the class unless another wrapper holds it",
+                        cmd_argv
+                    ));
+                } else {
+                    info(&format!("would exec: {:?}", cmd_argv));
+                }
+                return;
             }
 
-            if !no_reconf {
-                reload_kwin_config(&target);
+            if grant.is_some() || learn_timeout.is_some() {
+                let learn = learn_timeout.map(|secs| Learn {
+                    class: final_class.clone(),
+                    key: key.clone(),
+                    added,
+                    timeout: Duration::from_secs(secs),
+                });
+                run_supervised(target, grant, &cmd_argv, learn, !no_reconf);
+                return;
             }
 
-            // Exec the command
+            // Exec the command; the JSON report has to go out before we are replaced.
+            output::finish(target_state(target));
             let mut c = Command::new(&cmd_argv[0]);
             if cmd_argv.len() > 1 {
                 c.args(&cmd_argv[1..]);
             }
 
             if let Err(e) = exec_replace(c) {
-                err(&format!("exec failed: {}", e));
+                fail("exec", &format!("exec failed: {}", e));
             }
         }
 
         _ => {
+            fail("usage", &format!("unknown command: {}", cmd));
             usage();
         }
     }
@@ -0,0 +1,115 @@
+// -------------------------------
+// Output mode (pretty vs --json)
+// -------------------------------
+// By default commands talk to humans: data on stdout, progress and errors on
+// stderr. With `--json` the same calls are collected into one report object
+// that is printed on stdout when the command finishes.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use focusctl::json::Json;
+
+static JSON_MODE: AtomicBool = AtomicBool::new(false);
+static REPORT: Mutex<Report> = Mutex::new(Report::new());
+
+struct Report {
+    command: Option<String>,
+    actions: Vec<String>,
+    errors: Vec<(String, String)>,
+    data: Vec<(String, Json)>,
+    exit_code: i32,
+    emitted: bool,
+}
+
+impl Report {
+    const fn new() -> Report {
+        Report {
+            command: None,
+            actions: Vec::new(),
+            errors: Vec::new(),
+            data: Vec::new(),
+            exit_code: 0,
+            emitted: false,
+        }
+    }
+}
+
+fn report() -> std::sync::MutexGuard<'static, Report> {
+    REPORT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn set_json(on: bool) {
+    JSON_MODE.store(on, Ordering::Relaxed);
+}
+
+/// True while a `--json` report is being collected (and not yet printed).
+pub fn json() -> bool {
+    JSON_MODE.load(Ordering::Relaxed) && !report().emitted
+}
+
+pub fn set_command(cmd: &str) {
+    report().command = Some(cmd.to_string());
+}
+
+/// Record something the command did (or decided not to do).
+pub fn action(msg: &str) {
+    report().actions.push(msg.to_string());
+}
+
+/// Record an error with a stable, machine-readable code.
+pub fn error(code: &str, msg: &str) {
+    report().errors.push((code.to_string(), msg.to_string()));
+}
+
+/// Attach command-specific data; a later value for the same key wins.
+pub fn data(key: &str, value: Json) {
+    let mut r = report();
+    r.data.retain(|(k, _)| k != key);
+    r.data.push((key.to_string(), value));
+}
+
+pub fn set_exit_code(code: i32) {
+    report().exit_code = code;
+}
+
+/// Exit 1 for a failed command, unless it already chose a status.
+pub fn set_failed() {
+    let mut r = report();
+    if r.exit_code == 0 {
+        r.exit_code = 1;
+    }
+}
+
+/// Print the report once (`--json` only) and return the process exit code.
+/// `state` describes the target and is placed before the command's data.
+pub fn finish(state: Vec<(String, Json)>) -> i32 {
+    let json_mode = JSON_MODE.load(Ordering::Relaxed);
+    let mut r = report();
+    if json_mode && !r.emitted {
+        r.emitted = true;
+        let mut obj = vec![
+            ("ok".to_string(), Json::from(r.errors.is_empty())),
+            ("command".to_string(), Json::from(r.command.clone())),
+        ];
+        obj.extend(state);
+        obj.extend(r.data.iter().cloned());
+        obj.push(("actions".to_string(), Json::strs(&r.actions)));
+        obj.push((
+            "errors".to_string(),
+            Json::Arr(
+                r.errors
+                    .iter()
+                    .map(|(code, msg)| {
+                        Json::obj(vec![
+                            ("code", Json::from(code.as_str())),
+                            ("message", Json::from(msg.as_str())),
+                        ])
+                    })
+                    .collect(),
+            ),
+        ));
+        println!("{}", Json::Obj(obj));
+    }
+    r.exit_code
+}
@@ -0,0 +1,444 @@
+// -------------------------------
+// Glob + regex class patterns
+// -------------------------------
+// Class entries may be wildcards (`proclet-*`) or anchored regexes
+// (`/proclet-(work|home)/`). Both compile to the same regex source, which
+// the script hands to `RegExp(..., "i")`; this module matches the subset of
+// that syntax we accept, so focusctl and the script agree on every entry:
+//
+//   literals, `\` escapes, `.`, `[...]` / `[^...]` with ranges, `\d` `\w`
+//   `\s` (and upper-case negations), groups `(...)` / `(?:...)`, `|`, and
+//   the greedy quantifiers `*` `+` `?`.
+//
+// Both sides backtrack, so a repeated group must match one way only: it may
+// not contain a quantifier, and alternatives of any group inside it must
+// start with different literal characters. `(work|home)+` is fine; `(a*)*`
+// and `(a|aa)*` are rejected, as they take exponential time on near misses.
+//
+// Patterns are always anchored: `^` / `$` at the ends are accepted and
+// implied. Entries are split on `;`, `,` and whitespace, so those cannot
+// appear in a pattern; `:` can, inside a `/regex/` only.
+
+/// True if `s` (a class value, without field prefix) is a glob.
+pub fn is_glob(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// True if `s` (a class value, without field prefix) is a `/regex/`.
+pub fn is_regex(s: &str) -> bool {
+    s.starts_with('/')
+}
+
+/// Regex source (unanchored) for a glob: `*` -> `.*`, `?` -> `.`, `[...]`
+/// kept as a class (`[!...]` negates).
+pub fn glob_to_regex(glob: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => out.push_str(".*"),
+            '?' => out.push('.'),
+            '[' => {
+                out.push('[');
+                if chars.peek() == Some(&'!') || chars.peek() == Some(&'^') {
+                    chars.next();
+                    out.push('^');
+                }
+                let mut closed = false;
+                let mut first = true;
+                for d in chars.by_ref() {
+                    if d == ']' && !first {
+                        closed = true;
+                        break;
+                    }
+                    if d == '\\' || d == '[' || (d == ']' && first) {
+                        out.push('\\');
+                    }
+                    out.push(d);
+                    first = false;
+                }
+                if !closed {
+                    return Err(format!("glob '{}': unclosed '['", glob));
+                }
+                out.push(']');
+            }
+            c if c.is_ascii_punctuation() && c != '_' && c != '-' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+/// Strip the implied anchors from a `/regex/` body.
+pub fn strip_anchors(src: &str) -> &str {
+    let src = src.strip_prefix('^').unwrap_or(src);
+    match src.strip_suffix('$') {
+        Some(rest) if !rest.ends_with('\\') => rest,
+        _ => src,
+    }
+}
+
+/// Lowercase everything but escape letters (`\D` must stay `\D`).
+pub fn lowercase_source(src: &str) -> String {
+    let mut out = String::new();
+    let mut escaped = false;
+    for c in src.chars() {
+        if escaped {
+            out.push(c);
+            escaped = false;
+        } else {
+            if c == '\\' {
+                escaped = true;
+            }
+            out.extend(c.to_lowercase());
+        }
+    }
+    out
+}
+
+// -------------------------------
+// Matcher
+// -------------------------------
+
+#[derive(Clone, Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        items: Vec<Item>,
+        negated: bool,
+    },
+    Group(Vec<Vec<Node>>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Item {
+    Range(char, char),
+    Digit(bool),
+    Word(bool),
+    Space(bool),
+}
+
+impl Item {
+    fn test(self, c: char) -> bool {
+        match self {
+            Item::Range(lo, hi) => (lo..=hi).contains(&c),
+            Item::Digit(neg) => c.is_ascii_digit() != neg,
+            Item::Word(neg) => (c.is_ascii_alphanumeric() || c == '_') != neg,
+            Item::Space(neg) => c.is_whitespace() != neg,
+        }
+    }
+}
+
+/// A compiled, anchored, case-insensitive pattern.
+#[derive(Clone, Debug)]
+pub struct Regex {
+    alts: Vec<Vec<Node>>,
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn err(&self, what: &str) -> String {
+        format!("regex '{}': {} at offset {}", self.src, what, self.pos)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn alts(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alts = vec![self.seq()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alts.push(self.seq()?);
+        }
+        Ok(alts)
+    }
+
+    fn seq(&mut self) -> Result<Vec<Node>, String> {
+        let mut seq = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            seq.push(self.quantified(atom)?);
+        }
+        Ok(seq)
+    }
+
+    fn quantified(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => return Err(self.err("counted repetition is not supported")),
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+        if matches!(self.peek(), Some('*' | '+' | '?' | '{')) {
+            return Err(self.err("stacked or lazy quantifiers are not supported"));
+        }
+        if matches!(atom, Node::Group(_)) && !unambiguous(&atom) {
+            return Err(self.err(
+                "a repeated group may not contain quantifiers or alternatives that start alike",
+            ));
+        }
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+        })
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.peek().ok_or_else(|| self.err("unexpected end"))?;
+        self.pos += 1;
+        match c {
+            '.' => Ok(Node::Any),
+            '(' => {
+                if self.peek() == Some('?') {
+                    if self.chars.get(self.pos + 1) != Some(&':') {
+                        return Err(self.err("only (?:...) groups are supported"));
+                    }
+                    self.pos += 2;
+                }
+                let alts = self.alts()?;
+                if self.peek() != Some(')') {
+                    return Err(self.err("unclosed '('"));
+                }
+                self.pos += 1;
+                Ok(Node::Group(alts))
+            }
+            '[' => self.class(),
+            '\\' => match self.escape()? {
+                Item::Range(lo, _) => Ok(Node::Char(lo)),
+                item => Ok(Node::Class {
+                    items: vec![item],
+                    negated: false,
+                }),
+            },
+            '*' | '+' | '?' | '{' => Err(self.err("nothing to repeat")),
+            ')' => Err(self.err("unmatched ')'")),
+            '^' | '$' => Err(self.err("anchors are only allowed at the ends")),
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    /// After a `\`: a class shorthand or an escaped punctuation character.
+    fn escape(&mut self) -> Result<Item, String> {
+        let c = self.peek().ok_or_else(|| self.err("trailing '\\'"))?;
+        self.pos += 1;
+        Ok(match c {
+            'd' | 'D' => Item::Digit(c == 'D'),
+            'w' | 'W' => Item::Word(c == 'W'),
+            's' | 'S' => Item::Space(c == 'S'),
+            c if c.is_ascii_punctuation() => Item::Range(c, c),
+            _ => return Err(self.err(&format!("unsupported escape '\\{}'", c))),
+        })
+    }
+
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut items = Vec::new();
+        loop {
+            let c = self.peek().ok_or_else(|| self.err("unclosed '['"))?;
+            self.pos += 1;
+            let lo = match c {
+                ']' => break,
+                '\\' => match self.escape()? {
+                    Item::Range(c, _) => c,
+                    item => {
+                        items.push(item);
+                        continue;
+                    }
+                },
+                '[' => return Err(self.err("nested '[' must be escaped")),
+                c => c,
+            };
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&n| n != ']') {
+                self.pos += 1;
+                let hi = match self.peek() {
+                    Some('\\') => {
+                        self.pos += 1;
+                        match self.escape()? {
+                            Item::Range(c, _) => c,
+                            _ => return Err(self.err("bad range")),
+                        }
+                    }
+                    Some(h) => {
+                        self.pos += 1;
+                        h
+                    }
+                    None => return Err(self.err("unclosed '['")),
+                };
+                if hi < lo {
+                    return Err(self.err("range out of order"));
+                }
+                items.push(Item::Range(lo, hi));
+            } else {
+                items.push(Item::Range(lo, lo));
+            }
+        }
+        Ok(Node::Class { items, negated })
+    }
+}
+
+/// True if `node` can match a given string only one way: no quantifiers,
+/// and the non-empty alternatives of every group start with distinct
+/// literal characters.
+fn unambiguous(node: &Node) -> bool {
+    match node {
+        Node::Repeat { .. } => false,
+        Node::Group(alts) => {
+            let starts: Vec<&Node> = alts.iter().filter_map(|seq| seq.first()).collect();
+            if starts.len() > 1 {
+                let mut firsts = Vec::new();
+                for node in starts {
+                    match node {
+                        Node::Char(c) if !firsts.contains(c) => firsts.push(*c),
+                        _ => return false,
+                    }
+                }
+            }
+            alts.iter().flatten().all(unambiguous)
+        }
+        _ => true,
+    }
+}
+
+impl Regex {
+    /// Parse a regex body (anchors optional). Literals are matched
+    /// case-insensitively, like the script's `"i"` flag.
+    pub fn parse(src: &str) -> Result<Regex, String> {
+        let body = strip_anchors(src);
+        let mut p = Parser {
+            src,
+            chars: lowercase_source(body).chars().collect(),
+            pos: 0,
+        };
+        let alts = p.alts()?;
+        if p.pos != p.chars.len() {
+            return Err(p.err("unmatched ')'"));
+        }
+        Ok(Regex { alts })
+    }
+
+    /// Whole-string match of `s`.
+    pub fn is_match(&self, s: &str) -> bool {
+        let chars: Vec<char> = s.to_lowercase().chars().collect();
+        match_alts(&self.alts, &chars, 0, &|p| p == chars.len())
+    }
+}
+
+fn match_alts(alts: &[Vec<Node>], s: &[char], pos: usize, k: &dyn Fn(usize) -> bool) -> bool {
+    alts.iter().any(|seq| match_seq(seq, s, pos, k))
+}
+
+fn match_seq(seq: &[Node], s: &[char], pos: usize, k: &dyn Fn(usize) -> bool) -> bool {
+    match seq.split_first() {
+        None => k(pos),
+        Some((node, rest)) => match_node(node, s, pos, &|p| match_seq(rest, s, p, k)),
+    }
+}
+
+fn match_node(node: &Node, s: &[char], pos: usize, k: &dyn Fn(usize) -> bool) -> bool {
+    let one = |test: &dyn Fn(char) -> bool| pos < s.len() && test(s[pos]) && k(pos + 1);
+    match node {
+        Node::Char(c) => one(&|x| x == *c),
+        Node::Any => one(&|x| x != '\n'),
+        Node::Class { items, negated } => one(&|x| items.iter().any(|i| i.test(x)) != *negated),
+        Node::Group(alts) => match_alts(alts, s, pos, k),
+        Node::Repeat { node, min, max } => match_repeat(node, *min, *max, 0, s, pos, k),
+    }
+}
+
+/// Greedy: try one more repetition first, then the rest of the pattern.
+fn match_repeat(
+    node: &Node,
+    min: usize,
+    max: Option<usize>,
+    count: usize,
+    s: &[char],
+    pos: usize,
+    k: &dyn Fn(usize) -> bool,
+) -> bool {
+    if max.is_none_or(|m| count < m) {
+        let more = match_node(node, s, pos, &|p| {
+            // An empty repetition only counts towards `min`.
+            (p != pos || count < min) && match_repeat(node, min, max, count + 1, s, p, k)
+        });
+        if more {
+            return true;
+        }
+    }
+    count >= min && k(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m(re: &str, s: &str) -> bool {
+        Regex::parse(re).unwrap().is_match(s)
+    }
+
+    #[test]
+    fn globs_compile_to_anchored_regexes() {
+        assert_eq!(glob_to_regex("proclet-*").unwrap(), "proclet-.*");
+        assert_eq!(glob_to_regex("a?.b[!0-9]").unwrap(), "a.\\.b[^0-9]");
+        assert!(glob_to_regex("a[bc").is_err());
+        let re = Regex::parse(&glob_to_regex("proclet-*").unwrap()).unwrap();
+        assert!(re.is_match("proclet-chrome-work"));
+        assert!(!re.is_match("xproclet-chrome"));
+    }
+
+    #[test]
+    fn regex_subset_matches_like_the_script() {
+        assert!(m("^proclet-(work|home)$", "Proclet-Work"));
+        assert!(!m("proclet-(work|home)", "proclet-workx"));
+        assert!(m("org\\.kde\\.[a-z]+", "org.kde.dolphin"));
+        assert!(!m("org\\.kde\\.[a-z]+", "org.kde."));
+        assert!(m("[A-Z]\\d?x*", "b"));
+        assert!(m("(?:a|)+b", "aab"));
+        assert!(m("(?:x(?:a|b)|y)+", "xayxb"));
+        assert!(m("\\W\\w", "-a"));
+        assert!(!m("[^a-c]", "B"));
+    }
+
+    #[test]
+    fn unsupported_syntax_is_rejected() {
+        for bad in [
+            "a{2}", "a*?", "(?=a)", "\\bfoo", "a)", "(a", "[a", "a^b", "*a",
+        ] {
+            assert!(Regex::parse(bad).is_err(), "{}", bad);
+        }
+        // Repeated groups that could match a string more than one way.
+        for bad in [
+            "(a*)*",
+            "(a|aa)*",
+            "(?:a|A)+",
+            "(x(a|a))+",
+            "(.|a)+",
+            "(ab?)+",
+        ] {
+            assert!(Regex::parse(bad).is_err(), "{}", bad);
+        }
+    }
+}
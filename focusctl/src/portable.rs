@@ -0,0 +1,1004 @@
+// -------------------------------
+// Portable class-list files
+// -------------------------------
+// `focusctl export` / `import`: the helper's entries and script settings in a
+// file that can be shared and checked into a repo. Entries keep their stored
+// spelling and rule options; settings are the schema keys other than the two
+// lists (`mode`, `debug`). The `[Plugins]` enabled flag stays per machine.
+//
+//   # kwin-focus-helper classes (focusctl export)
+//
+//   [settings]
+//   mode = "raise"
+//
+//   [[classes]]
+//   class = "firefox"
+//   mode = "raise"
+//   only = "dialogs"
+//
+// JSON holds the same object (`{"settings": {...}, "classes": [{...}]}`);
+// plain is one entry per line in rule syntax (`firefox:raise:dialogs`) with
+// `setting mode=raise` lines, which no entry can look like.
+//
+// `focusctl apply` reads the same files as a desired state, which is easier
+// to write flat; an `enabled` key sets the `[Plugins]` flag too:
+//
+//   enabled = true
+//   mode = "raise"
+//   classes = ["ProcletChrome", "firefox:raise:dialogs"]
+
+use std::fmt::Write as _;
+use std::io;
+
+use crate::config::{
+    GROUP_NAME, KEY_NAME, PLUGINS_GROUP, RULES_KEY, check_class, class_key, doc_classes,
+    doc_effective_rules, doc_rules, enabled_key, join_classes, read_document, update_document,
+};
+use crate::json::{self, Json};
+use crate::kconfig::Document;
+use crate::rules::{Only, Rule, join_rules, parse_mode, parse_rule};
+use crate::schema::{self, Kind, SCRIPT_KEYS};
+use crate::target::Target;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Json,
+    Plain,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Option<Format> {
+        match s.trim().to_lowercase().as_str() {
+            "toml" => Some(Format::Toml),
+            "json" => Some(Format::Json),
+            "plain" | "text" | "txt" => Some(Format::Plain),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Format::Toml => "toml",
+            Format::Json => "json",
+            Format::Plain => "plain",
+        }
+    }
+
+    /// From a file name's extension, else from the first line that is not
+    /// blank or a comment.
+    pub fn detect(path: &str, text: &str) -> Format {
+        let ext = path.rsplit_once('.').map(|(_, e)| e).unwrap_or("");
+        if let Some(f) = Format::parse(ext) {
+            return f;
+        }
+        let body = text
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty() && !l.starts_with('#'))
+            .unwrap_or("");
+        let toml_key = body.split_once('=').is_some_and(|(k, _)| {
+            let k = k.trim();
+            !k.is_empty()
+                && k.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        });
+        if body.starts_with('{') {
+            Format::Json
+        } else if toml_key || body == "[[classes]]" || body == "[settings]" {
+            Format::Toml
+        } else {
+            Format::Plain
+        }
+    }
+}
+
+/// What an export file holds.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Portable {
+    /// Rules and plain classes (a plain class is a `Rule` without options),
+    /// the way the script combines them.
+    pub entries: Vec<Rule>,
+    /// Stored values of the other script keys that are set.
+    pub settings: Vec<(String, String)>,
+    /// The `[Plugins]` flag, when the file sets it (`export` never does).
+    pub enabled: Option<bool>,
+}
+
+/// Script keys that travel as settings.
+fn setting_specs() -> impl Iterator<Item = &'static schema::KeySpec> {
+    SCRIPT_KEYS
+        .iter()
+        .filter(|k| !matches!(k.kind, Kind::ClassList | Kind::RuleList))
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// -------------------------------
+// Export
+// -------------------------------
+
+fn doc_portable(doc: &Document) -> Portable {
+    Portable {
+        entries: doc_effective_rules(doc),
+        settings: setting_specs()
+            .filter_map(|k| Some((k.name.to_string(), doc.get(GROUP_NAME, k.name)?)))
+            .collect(),
+        enabled: None,
+    }
+}
+
+/// The target's entries and settings.
+pub fn export(target: &Target) -> io::Result<Portable> {
+    Ok(doc_portable(&read_document(target)?))
+}
+
+impl Portable {
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Toml => self.to_toml(),
+            Format::Json => self.to_json_text(),
+            Format::Plain => self.to_plain(),
+        }
+    }
+
+    fn to_toml(&self) -> String {
+        let mut out = String::from("# kwin-focus-helper classes (focusctl export)\n");
+        if let Some(on) = self.enabled {
+            let _ = writeln!(out, "\nenabled = {}", on);
+        }
+        if !self.settings.is_empty() {
+            out.push_str("\n[settings]\n");
+            for (k, v) in &self.settings {
+                let _ = writeln!(out, "{} = {}", k, toml_setting(k, v));
+            }
+        }
+        for r in &self.entries {
+            let _ = writeln!(out, "\n[[classes]]\nclass = {}", toml_str(&r.class));
+            if let Some(m) = r.mode {
+                let _ = writeln!(out, "mode = {}", toml_str(m));
+            }
+            if let Some(o) = r.only {
+                let _ = writeln!(out, "only = {}", toml_str(o.as_str()));
+            }
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> Json {
+        let mut kv = Vec::new();
+        if let Some(on) = self.enabled {
+            kv.push(("enabled", Json::from(on)));
+        }
+        kv.extend([
+            (
+                "settings",
+                Json::Obj(
+                    self.settings
+                        .iter()
+                        .map(|(k, v)| (k.clone(), setting_json(k, v)))
+                        .collect(),
+                ),
+            ),
+            (
+                "classes",
+                Json::Arr(self.entries.iter().map(entry_json).collect()),
+            ),
+        ]);
+        Json::obj(kv)
+    }
+
+    /// `to_json`, one entry per line so the file diffs well.
+    fn to_json_text(&self) -> String {
+        let mut out = String::from("{\n");
+        if let Some(on) = self.enabled {
+            let _ = writeln!(out, "  \"enabled\": {},", on);
+        }
+        let settings = self
+            .to_json()
+            .get("settings")
+            .cloned()
+            .unwrap_or(Json::Null);
+        let _ = writeln!(out, "  \"settings\": {},", settings);
+        let entries: Vec<String> = self
+            .entries
+            .iter()
+            .map(|r| format!("    {}", entry_json(r)))
+            .collect();
+        if entries.is_empty() {
+            out.push_str("  \"classes\": []\n}\n");
+        } else {
+            let _ = write!(out, "  \"classes\": [\n{}\n  ]\n}}\n", entries.join(",\n"));
+        }
+        out
+    }
+
+    fn to_plain(&self) -> String {
+        let mut out = String::new();
+        if let Some(on) = self.enabled {
+            let _ = writeln!(out, "setting enabled={}", on);
+        }
+        for (k, v) in &self.settings {
+            let _ = writeln!(out, "setting {}={}", k, v);
+        }
+        for r in &self.entries {
+            let _ = writeln!(out, "{}", r);
+        }
+        out
+    }
+}
+
+fn entry_json(r: &Rule) -> Json {
+    let mut kv = vec![("class", Json::from(r.class.as_str()))];
+    if let Some(m) = r.mode {
+        kv.push(("mode", Json::from(m)));
+    }
+    if let Some(o) = r.only {
+        kv.push(("only", Json::from(o.as_str())));
+    }
+    Json::obj(kv)
+}
+
+fn is_bool_key(key: &str) -> bool {
+    schema::lookup(key).is_some_and(|k| k.kind == Kind::Bool)
+}
+
+fn setting_json(key: &str, value: &str) -> Json {
+    match value {
+        "true" | "false" if is_bool_key(key) => Json::Bool(value == "true"),
+        _ => Json::from(value),
+    }
+}
+
+fn toml_setting(key: &str, value: &str) -> String {
+    match value {
+        "true" | "false" if is_bool_key(key) => value.to_string(),
+        _ => toml_str(value),
+    }
+}
+
+fn toml_str(s: &str) -> String {
+    // TOML basic strings use JSON's escapes.
+    Json::from(s).to_string()
+}
+
+// -------------------------------
+// Reading files
+// -------------------------------
+
+impl Portable {
+    pub fn parse(text: &str, format: Format) -> io::Result<Portable> {
+        let mut p = match format {
+            Format::Toml => parse_toml(text)?,
+            Format::Json => parse_json(text)?,
+            Format::Plain => parse_plain(text)?,
+        };
+        for r in &p.entries {
+            // Separators would split the entry once it is in kwinrc.
+            if r.class.contains(|c: char| c == ';' || c == ',' || c.is_whitespace()) {
+                return Err(invalid(format!(
+                    "'{}': entries cannot contain ';', ',' or spaces",
+                    r.class
+                )));
+            }
+            check_class(&r.class).map_err(invalid)?;
+        }
+        // Every format reads `enabled` where the settings are.
+        while let Some(i) = p
+            .settings
+            .iter()
+            .position(|(k, _)| k.trim().eq_ignore_ascii_case("enabled"))
+        {
+            let (_, v) = p.settings.remove(i);
+            p.enabled = Some(match v.trim().to_lowercase().as_str() {
+                "true" | "on" | "yes" | "1" => true,
+                "false" | "off" | "no" | "0" => false,
+                _ => {
+                    return Err(invalid(format!(
+                        "invalid value for enabled: '{}' (expected: true|false)",
+                        v
+                    )));
+                }
+            });
+        }
+        check_settings(&p.settings)?;
+        Ok(p)
+    }
+}
+
+fn entry_from_parts(
+    class: &str,
+    mode: Option<&str>,
+    only: Option<&str>,
+    at: &str,
+) -> io::Result<Rule> {
+    let mode = match mode {
+        Some(m) => Some(parse_mode(m).ok_or_else(|| {
+            invalid(format!(
+                "{}: invalid mode '{}' (expected: {})",
+                at,
+                m,
+                schema::MODES.join("|")
+            ))
+        })?),
+        None => None,
+    };
+    let only = match only {
+        Some(o) => Some(Only::parse(o).ok_or_else(|| {
+            invalid(format!(
+                "{}: invalid only '{}' (expected: normal|dialogs)",
+                at, o
+            ))
+        })?),
+        None => None,
+    };
+    Ok(Rule {
+        class: class.trim().to_string(),
+        mode,
+        only,
+    })
+}
+
+fn parse_json(text: &str) -> io::Result<Portable> {
+    let doc = json::parse(text)?;
+    let mut p = Portable::default();
+    match doc.get("enabled") {
+        Some(Json::Bool(b)) => p.settings.push(("enabled".to_string(), b.to_string())),
+        None | Some(Json::Null) => {}
+        Some(_) => return Err(invalid("enabled: expected true or false".to_string())),
+    }
+    match doc.get("settings") {
+        Some(Json::Obj(kv)) => {
+            for (k, v) in kv {
+                let value = match v {
+                    Json::Str(s) => s.clone(),
+                    Json::Bool(b) => b.to_string(),
+                    _ => return Err(invalid(format!("settings.{}: expected a string", k))),
+                };
+                p.settings.push((k.clone(), value));
+            }
+        }
+        None | Some(Json::Null) => {}
+        Some(_) => return Err(invalid("settings: expected an object".to_string())),
+    }
+    let entries = match doc.get("classes") {
+        Some(Json::Arr(a)) => a.as_slice(),
+        None => &[],
+        Some(_) => return Err(invalid("classes: expected an array".to_string())),
+    };
+    for (i, e) in entries.iter().enumerate() {
+        let at = format!("classes[{}]", i);
+        let rule = match e {
+            // A bare string is an entry in rule syntax.
+            Json::Str(s) => parse_rule(s).map_err(invalid)?,
+            Json::Obj(_) => {
+                let field = |k: &str| -> io::Result<Option<&str>> {
+                    match e.get(k) {
+                        None | Some(Json::Null) => Ok(None),
+                        Some(Json::Str(s)) => Ok(Some(s)),
+                        Some(_) => Err(invalid(format!("{}.{}: expected a string", at, k))),
+                    }
+                };
+                let class =
+                    field("class")?.ok_or_else(|| invalid(format!("{}: missing \"class\"", at)))?;
+                entry_from_parts(class, field("mode")?, field("only")?, &at)?
+            }
+            _ => return Err(invalid(format!("{}: expected an object", at))),
+        };
+        p.entries.push(rule);
+    }
+    Ok(p)
+}
+
+fn parse_plain(text: &str) -> io::Result<Portable> {
+    let mut p = Portable::default();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(setting) = line.strip_prefix("setting ") {
+            let (k, v) = setting
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("line {}: expected setting key=value", n + 1)))?;
+            p.settings
+                .push((k.trim().to_string(), v.trim().to_string()));
+            continue;
+        }
+        p.entries
+            .push(parse_rule(line).map_err(|e| invalid(format!("line {}: {}", n + 1, e)))?);
+    }
+    Ok(p)
+}
+
+/// The subset of TOML `to_toml` writes, plus what people add by hand:
+/// comments, `'literal'` strings, `[[classes]]` / `[settings]` in any order,
+/// and the flat form: settings at the top level and `classes = [...]`, an
+/// array of entries in rule syntax that may span lines.
+fn parse_toml(text: &str) -> io::Result<Portable> {
+    enum Section {
+        Top,
+        Settings,
+        Class(Vec<(String, String)>, usize),
+    }
+
+    fn finish(section: Section, p: &mut Portable) -> io::Result<()> {
+        if let Section::Class(kv, line) = section {
+            let at = format!("line {}", line);
+            let get = |k: &str| kv.iter().find(|(key, _)| key == k).map(|(_, v)| v.as_str());
+            if let Some((k, _)) = kv
+                .iter()
+                .find(|(k, _)| !matches!(k.as_str(), "class" | "mode" | "only"))
+            {
+                return Err(invalid(format!("{}: unknown class field '{}'", at, k)));
+            }
+            let class = get("class")
+                .ok_or_else(|| invalid(format!("{}: [[classes]] without class", at)))?;
+            p.entries
+                .push(entry_from_parts(class, get("mode"), get("only"), &at)?);
+        }
+        Ok(())
+    }
+
+    let mut p = Portable::default();
+    let mut section = Section::Top;
+    let mut lines = text.lines().enumerate();
+    while let Some((n, raw)) = lines.next() {
+        let n = n + 1;
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line {
+            "[[classes]]" => {
+                finish(
+                    std::mem::replace(&mut section, Section::Class(Vec::new(), n)),
+                    &mut p,
+                )?;
+                continue;
+            }
+            "[settings]" => {
+                finish(std::mem::replace(&mut section, Section::Settings), &mut p)?;
+                continue;
+            }
+            _ if line.starts_with('[') => {
+                return Err(invalid(format!("line {}: unknown table {}", n, line)));
+            }
+            _ => {}
+        }
+        let (k, v) = line
+            .split_once('=')
+            .ok_or_else(|| invalid(format!("line {}: expected key = value", n)))?;
+        let key = k.trim().trim_matches('"').to_string();
+        let at = |e: String| invalid(format!("line {}: {}", n, e));
+
+        if matches!(section, Section::Top) && key == "classes" {
+            let mut array = v.trim().to_string();
+            let items = loop {
+                if let Some(items) = toml_array(&array).map_err(at)? {
+                    break items;
+                }
+                let Some((_, more)) = lines.next() else {
+                    return Err(at("unterminated array".to_string()));
+                };
+                array.push('\n');
+                array.push_str(more);
+            };
+            for item in items {
+                p.entries.push(parse_rule(&item).map_err(at)?);
+            }
+            continue;
+        }
+        let value = toml_scalar(v.trim()).map_err(at)?;
+        match &mut section {
+            Section::Top | Section::Settings => p.settings.push((key, value)),
+            Section::Class(kv, _) => kv.push((key, value)),
+        }
+    }
+    finish(section, &mut p)?;
+    Ok(p)
+}
+
+/// A TOML string or boolean at the start of `v`, as text, and what follows.
+fn toml_value(v: &str) -> Result<(String, &str), String> {
+    if let Some(body) = v.strip_prefix('\'') {
+        let end = body.find('\'').ok_or("unterminated string")?;
+        return Ok((body[..end].to_string(), &body[end + 1..]));
+    }
+    if v.starts_with('"') {
+        // Basic strings share JSON's escapes; find the closing quote.
+        let bytes = v.as_bytes();
+        let mut end = 1;
+        while end < bytes.len() && bytes[end] != b'"' {
+            end += if bytes[end] == b'\\' { 2 } else { 1 };
+        }
+        if end >= bytes.len() {
+            return Err("unterminated string".to_string());
+        }
+        let s = json::parse(&v[..=end]).map_err(|e| e.to_string())?;
+        return Ok((s.as_str().unwrap_or_default().to_string(), &v[end + 1..]));
+    }
+    let end = v
+        .find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+        .unwrap_or(v.len());
+    match &v[..end] {
+        word @ ("true" | "false") => Ok((word.to_string(), &v[end..])),
+        _ => Err(format!("expected a string or boolean, got {}", v.trim())),
+    }
+}
+
+/// A single value; a trailing `# comment` is allowed.
+fn toml_scalar(v: &str) -> Result<String, String> {
+    let (value, rest) = toml_value(v)?;
+    let rest = rest.trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(format!("unexpected '{}' after the value", rest));
+    }
+    Ok(value)
+}
+
+/// An array of strings, `Ok(None)` while its `]` is still missing.
+fn toml_array(v: &str) -> Result<Option<Vec<String>>, String> {
+    let mut rest = v
+        .strip_prefix('[')
+        .ok_or_else(|| format!("expected an array, got {}", v.trim()))?;
+    let mut items = Vec::new();
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.starts_with('#') {
+            match rest.find('\n') {
+                Some(i) => rest = &rest[i..],
+                None => return Ok(None),
+            }
+            continue;
+        }
+        if rest.is_empty() {
+            return Ok(None);
+        }
+        if let Some(after) = rest.strip_prefix(']') {
+            let after = after.trim();
+            if !after.is_empty() && !after.starts_with('#') {
+                return Err(format!("unexpected '{}' after the array", after));
+            }
+            return Ok(Some(items));
+        }
+        let (item, after) = toml_value(rest)?;
+        items.push(item);
+        rest = after;
+    }
+}
+
+// -------------------------------
+// Import
+// -------------------------------
+
+/// Why an entry from the file was not added.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Skipped {
+    /// Its match key is already configured (as with `add-class`).
+    Present,
+    /// An earlier entry in the file has the same match key.
+    Duplicate,
+}
+
+impl Skipped {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Skipped::Present => "already configured",
+            Skipped::Duplicate => "duplicate in file",
+        }
+    }
+}
+
+/// What importing or applying a file does to kwinrc.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub added: Vec<Rule>,
+    pub skipped: Vec<(Rule, Skipped)>,
+    pub removed: Vec<Rule>,
+    /// Settings whose stored value changes: name, before, after (`None` =
+    /// unset).
+    pub settings: Vec<(String, Option<String>, Option<String>)>,
+    /// The `[Plugins]` flag when it changes: stored value before, after.
+    pub enabled: Option<(Option<String>, bool)>,
+    /// Whether kwinrc changes.
+    pub changed: bool,
+}
+
+/// Settings from a file, validated and normalized like `focusctl set`.
+fn check_settings(settings: &[(String, String)]) -> io::Result<Vec<(String, String)>> {
+    let mut out: Vec<(String, String)> = Vec::new();
+    for (k, v) in settings {
+        let spec = setting_specs()
+            .find(|s| s.name.eq_ignore_ascii_case(k.trim()))
+            .ok_or_else(|| {
+                let known: Vec<&str> = setting_specs().map(|s| s.name).collect();
+                invalid(format!(
+                    "unknown setting: {} (expected: enabled, {})",
+                    k,
+                    known.join(", ")
+                ))
+            })?;
+        let value = spec.normalize(v).map_err(invalid)?;
+        out.retain(|(name, _)| name != spec.name);
+        out.push((spec.name.to_string(), value));
+    }
+    Ok(out)
+}
+
+/// Apply `file` to `doc`; `settings` are the file's, checked.
+fn apply_file(
+    doc: &mut Document,
+    file: &Portable,
+    settings: &[(String, String)],
+    replace: bool,
+) -> io::Result<ImportReport> {
+    let mut report = ImportReport::default();
+    let current = doc_effective_rules(doc);
+    let mut rules = if replace { Vec::new() } else { doc_rules(doc) };
+    let mut classes = if replace {
+        Vec::new()
+    } else {
+        doc_classes(doc)
+    };
+    let mut seen: Vec<String> = Vec::new();
+    let mut kept: Vec<&Rule> = Vec::new();
+
+    for entry in &file.entries {
+        let key = class_key(&entry.class);
+        if seen.contains(&key) {
+            report.skipped.push((entry.clone(), Skipped::Duplicate));
+            continue;
+        }
+        seen.push(key.clone());
+        // Replacing keeps an entry only when it is stored the same way.
+        match current.iter().find(|r| r.key() == key) {
+            Some(r) if !replace || r == entry => {
+                report.skipped.push((entry.clone(), Skipped::Present));
+                kept.push(r);
+                if !replace {
+                    continue;
+                }
+            }
+            _ => report.added.push(entry.clone()),
+        }
+        if entry.is_plain() {
+            classes.push(entry.class.clone());
+        } else {
+            rules.push(entry.clone());
+        }
+    }
+    if replace {
+        report.removed = current
+            .iter()
+            .filter(|r| !kept.contains(r))
+            .cloned()
+            .collect();
+    }
+
+    let mut changed = false;
+    if classes != doc_classes(doc) {
+        changed |= doc.set(GROUP_NAME, KEY_NAME, &join_classes(&classes))?;
+    }
+    if rules != doc_rules(doc) {
+        changed |= if rules.is_empty() {
+            doc.remove(GROUP_NAME, RULES_KEY)?
+        } else {
+            doc.set(GROUP_NAME, RULES_KEY, &join_rules(&rules))?
+        };
+    }
+
+    for spec in setting_specs() {
+        let before = doc.get(GROUP_NAME, spec.name);
+        let after = match settings.iter().find(|(k, _)| k == spec.name) {
+            Some((_, v)) => Some(v.clone()),
+            None if replace => None,
+            None => before.clone(),
+        };
+        if after != before {
+            match &after {
+                Some(v) => doc.set(GROUP_NAME, spec.name, v)?,
+                None => doc.remove(GROUP_NAME, spec.name)?,
+            };
+            report.settings.push((spec.name.to_string(), before, after));
+            changed = true;
+        }
+    }
+
+    if let Some(on) = file.enabled {
+        let before = doc.get(PLUGINS_GROUP, &enabled_key());
+        if doc.set(PLUGINS_GROUP, &enabled_key(), &on.to_string())? {
+            report.enabled = Some((before, on));
+            changed = true;
+        }
+    }
+    report.changed = changed;
+    Ok(report)
+}
+
+/// Apply `file` to kwinrc in one write. Merging adds entries whose match key
+/// is not configured yet and sets the file's settings; replacing makes the
+/// entries and settings exactly the file's. Either way a later entry with the
+/// same key as an earlier one is skipped, and `enabled` is set when the file
+/// has it. Settings that do not validate are an `InvalidData` error and
+/// nothing is written.
+pub fn import(target: &Target, file: &Portable, replace: bool) -> io::Result<ImportReport> {
+    let settings = check_settings(&file.settings)?;
+    let mut report = ImportReport::default();
+    update_document(target, |doc| {
+        report = apply_file(doc, file, &settings, replace)?;
+        Ok(report.changed)
+    })?;
+    Ok(report)
+}
+
+/// What `import` would do, without writing.
+pub fn plan(target: &Target, file: &Portable, replace: bool) -> io::Result<ImportReport> {
+    let settings = check_settings(&file.settings)?;
+    apply_file(&mut read_document(target)?, file, &settings, replace)
+}
+
+/// The kwinrc groups that hold exactly `file`'s state, for tools that write
+/// kwinrc themselves.
+pub fn render_kwinrc(file: &Portable) -> io::Result<String> {
+    let settings = check_settings(&file.settings)?;
+    let mut doc = Document::default();
+    apply_file(&mut doc, file, &settings, true)?;
+    Ok(doc.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{add_class, add_rule, get_classes, get_rules, get_script_value};
+    use std::fs;
+
+    fn temp_target(tag: &str) -> Target {
+        let home =
+            std::env::temp_dir().join(format!("focusctl-portable-{}-{}", tag, std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        fs::create_dir_all(home.join(".config")).unwrap();
+        Target {
+            uid: 0,
+            user: "test".into(),
+            home,
+        }
+    }
+
+    fn sample() -> Portable {
+        Portable {
+            entries: vec![
+                parse_rule("firefox:raise:dialogs").unwrap(),
+                Rule::plain("ProcletChrome"),
+                Rule::plain("class:/Work-(?:A|B)/"),
+            ],
+            settings: vec![
+                ("mode".to_string(), "raise".to_string()),
+                ("debug".to_string(), "true".to_string()),
+            ],
+            enabled: Some(true),
+        }
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let p = sample();
+        for f in [Format::Toml, Format::Json, Format::Plain] {
+            let text = p.render(f);
+            assert_eq!(Portable::parse(&text, f).unwrap(), p, "{}", text);
+            assert_eq!(Format::detect("-", &text), f, "{}", text);
+        }
+        assert_eq!(Format::detect("team.json", ""), Format::Json);
+        assert_eq!(
+            Portable::parse("", Format::Toml).unwrap(),
+            Portable::default()
+        );
+    }
+
+    #[test]
+    fn hand_written_toml_is_read() {
+        let text = "\
+# shared list
+[[classes]]
+class = 'Proclet-*'   # every proclet app
+
+[settings]
+debug = false
+
+[[classes]]
+class = \"firefox\"
+only = \"dialogs\"
+";
+        let p = Portable::parse(text, Format::Toml).unwrap();
+        assert_eq!(p.entries[0], Rule::plain("Proclet-*"));
+        assert_eq!(p.entries[1].only, Some(Only::Dialogs));
+        assert_eq!(p.settings, [("debug".to_string(), "false".to_string())]);
+
+        for bad in [
+            "class = \"x\"",
+            "[[classes]]\nmode = \"raise\"",
+            "[[classes]]\nclass = \"a\"\nmode = \"fast\"",
+            "[[classes]]\nclass = \"a\"\ncolour = \"red\"",
+            "[[classes]]\nclass = \"bogus:x\"",
+            "[[classes]]\nclass = \"a",
+            "[[classes]]\nclass = \"a b\"",
+            "[other]",
+        ] {
+            let e = Portable::parse(bad, Format::Toml).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn merge_dedupes_by_key_and_reports() {
+        let t = temp_target("merge");
+        add_class(&t, "Firefox.desktop").unwrap();
+        let file = Portable {
+            entries: vec![
+                Rule::plain("firefox"),
+                Rule::plain("ProcletChrome"),
+                parse_rule("procletchrome:raise").unwrap(),
+                parse_rule("steam:normal").unwrap(),
+            ],
+            settings: vec![("Mode".to_string(), "Raise".to_string())],
+            enabled: None,
+        };
+        let r = import(&t, &file, false).unwrap();
+        assert!(r.changed);
+        assert_eq!(r.added, [file.entries[1].clone(), file.entries[3].clone()]);
+        assert_eq!(
+            r.skipped,
+            [
+                (file.entries[0].clone(), Skipped::Present),
+                (file.entries[2].clone(), Skipped::Duplicate)
+            ]
+        );
+        assert_eq!(r.removed, []);
+        assert_eq!(
+            r.settings,
+            [("mode".to_string(), None, Some("raise".to_string()))]
+        );
+        assert_eq!(
+            get_classes(&t).unwrap(),
+            ["Firefox.desktop", "ProcletChrome"]
+        );
+        assert_eq!(get_rules(&t).unwrap(), [file.entries[3].clone()]);
+
+        // Importing the same file again changes nothing.
+        let r = import(&t, &file, false).unwrap();
+        assert!(!r.changed);
+        assert_eq!(r.added, []);
+
+        let bad = Portable {
+            settings: vec![("colour".to_string(), "red".to_string())],
+            ..file
+        };
+        assert_eq!(
+            import(&t, &bad, false).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let _ = fs::remove_dir_all(&t.home);
+    }
+
+    #[test]
+    fn replace_makes_kwinrc_match_the_file() {
+        let t = temp_target("replace");
+        add_class(&t, "steam").unwrap();
+        add_class(&t, "ProcletChrome").unwrap();
+        add_rule(&t, &parse_rule("firefox:raise").unwrap()).unwrap();
+        crate::config::set_script_value(&t, "debug", "true").unwrap();
+
+        let file = Portable {
+            entries: vec![Rule::plain("procletchrome"), Rule::plain("ProcletChrome")],
+            settings: vec![("mode".to_string(), "raise".to_string())],
+            enabled: None,
+        };
+        let r = import(&t, &file, true).unwrap();
+        assert!(r.changed);
+        assert_eq!(r.added, [Rule::plain("procletchrome")]);
+        assert_eq!(
+            r.skipped,
+            [(Rule::plain("ProcletChrome"), Skipped::Duplicate)]
+        );
+        assert_eq!(
+            r.removed,
+            [
+                parse_rule("firefox:raise").unwrap(),
+                Rule::plain("steam"),
+                Rule::plain("ProcletChrome")
+            ]
+        );
+        assert_eq!(get_classes(&t).unwrap(), ["procletchrome"]);
+        assert_eq!(get_rules(&t).unwrap(), []);
+        assert_eq!(get_script_value(&t, "debug").unwrap(), None);
+        assert_eq!(export(&t).unwrap().settings, file.settings);
+
+        // Replacing with what is already there keeps everything.
+        let r = import(&t, &export(&t).unwrap(), true).unwrap();
+        assert!(!r.changed);
+        assert_eq!(r.added, []);
+        assert_eq!(r.removed, []);
+        assert_eq!(r.skipped.len(), 1);
+        let _ = fs::remove_dir_all(&t.home);
+    }
+
+    #[test]
+    fn flat_toml_is_a_desired_state() {
+        let text = "\
+enabled = true
+mode = \"raise\"
+debug = false   # quiet
+classes = [
+    \"ProcletChrome\",  # the browser
+    'firefox:raise:dialogs', \"steam\"
+]
+";
+        let p = Portable::parse(text, Format::Toml).unwrap();
+        assert_eq!(p.enabled, Some(true));
+        assert_eq!(
+            p.settings,
+            [
+                ("mode".to_string(), "raise".to_string()),
+                ("debug".to_string(), "false".to_string())
+            ]
+        );
+        assert_eq!(
+            p.entries,
+            [
+                Rule::plain("ProcletChrome"),
+                parse_rule("firefox:raise:dialogs").unwrap(),
+                Rule::plain("steam")
+            ]
+        );
+        assert_eq!(Format::detect("desired.toml", text), Format::Toml);
+        assert!(Portable::parse("classes = [\"a\"", Format::Toml).is_err());
+        assert!(Portable::parse("classes = [\"bogus:x\"]", Format::Toml).is_err());
+        assert!(Portable::parse("enabled = \"maybe\"", Format::Toml).is_err());
+    }
+
+    #[test]
+    fn plan_then_apply_is_idempotent() {
+        let t = temp_target("apply");
+        add_class(&t, "steam").unwrap();
+        let file = Portable::parse(
+            "enabled = true\nmode = \"raise\"\nclasses = [\"firefox:raise\", \"steam\"]\n",
+            Format::Toml,
+        )
+        .unwrap();
+
+        let planned = plan(&t, &file, true).unwrap();
+        assert!(planned.changed);
+        assert_eq!(planned.added, [parse_rule("firefox:raise").unwrap()]);
+        assert_eq!(planned.removed, []);
+        assert_eq!(planned.enabled, Some((None, true)));
+        // Planning writes nothing.
+        assert_eq!(get_rules(&t).unwrap(), []);
+
+        assert_eq!(import(&t, &file, true).unwrap(), planned);
+        assert_eq!(crate::config::get_enabled(&t).unwrap(), Some(true));
+        let again = plan(&t, &file, true).unwrap();
+        assert!(!again.changed);
+        assert_eq!((again.added.len(), again.settings.len()), (0, 0));
+        let _ = fs::remove_dir_all(&t.home);
+    }
+
+    #[test]
+    fn render_writes_only_the_helper_groups() {
+        let file = Portable::parse(
+            "enabled = false\nclasses = [\"a\", \"b:dialogs\"]\ndebug = \"on\"\n",
+            Format::Toml,
+        )
+        .unwrap();
+        let doc = Document::parse(&render_kwinrc(&file).unwrap());
+        assert_eq!(doc.get(GROUP_NAME, KEY_NAME).as_deref(), Some("a"));
+        assert_eq!(doc.get(GROUP_NAME, RULES_KEY).as_deref(), Some("b:dialogs"));
+        assert_eq!(doc.get(GROUP_NAME, "debug").as_deref(), Some("true"));
+        assert_eq!(doc.get(GROUP_NAME, "mode"), None);
+        assert_eq!(
+            doc.get(PLUGINS_GROUP, &enabled_key()).as_deref(),
+            Some("false")
+        );
+    }
+}
@@ -0,0 +1,279 @@
+// -------------------------------
+// Named class-list profiles
+// -------------------------------
+// Saved class lists in a focusctl-owned file next to kwinrc:
+//
+//   ~/.config/focusctl/profiles
+//
+//   [General]
+//   active=browser
+//
+//   [Profiles]
+//   browser=proclet-*;firefox
+//   vm=virt-manager;looking-glass-client
+//
+// Same KConfig syntax as kwinrc, so the same document model edits it. Using a
+// profile writes its list with `config::set_classes` and records it as
+// active; kwinrc stays the source of truth, so later edits there show up as
+// drift instead of being overwritten.
+
+use std::io;
+use std::path::PathBuf;
+
+use crate::config::{
+    self, check_classes, class_key, config_dir_for, get_classes, join_classes, parse_classes,
+};
+use crate::fileops;
+use crate::kconfig::Document;
+use crate::target::Target;
+
+const GENERAL_GROUP: &str = "General";
+const PROFILES_GROUP: &str = "Profiles";
+const ACTIVE_KEY: &str = "active";
+
+/// A saved class list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub classes: Vec<String>,
+}
+
+/// The profile last applied with `use_profile`, and whether kwinrc still
+/// holds its list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ActiveProfile {
+    pub name: String,
+    pub modified: bool,
+}
+
+pub fn profiles_path_for(target: &Target) -> PathBuf {
+    config_dir_for(target).join("focusctl").join("profiles")
+}
+
+fn read_profiles(target: &Target) -> io::Result<Document> {
+    match fileops::as_target(target, || fileops::read(&profiles_path_for(target))) {
+        Ok(text) => Ok(Document::parse(&text)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Document::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Locked read-modify-write of the profiles file; `edit` returns whether it
+/// changed the document.
+fn update_profiles(
+    target: &Target,
+    mut edit: impl FnMut(&mut Document) -> io::Result<bool>,
+) -> io::Result<bool> {
+    fileops::as_target(target, || {
+        fileops::update(&profiles_path_for(target), |text| {
+            let mut doc = Document::parse(text.unwrap_or(""));
+            Ok(edit(&mut doc)?.then(|| doc.to_string()))
+        })
+    })
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// Profile names are plain words: letters, digits, `-`, `_` and `.`.
+pub fn check_profile_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("empty profile name".to_string());
+    }
+    if name.starts_with('.')
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(format!(
+            "invalid profile name: {} (use letters, digits, '-', '_' and '.')",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Saved profiles in file order.
+pub fn list_profiles(target: &Target) -> io::Result<Vec<Profile>> {
+    let doc = read_profiles(target)?;
+    Ok(doc
+        .keys(PROFILES_GROUP)
+        .into_iter()
+        .filter_map(|name| {
+            let value = doc.get(PROFILES_GROUP, &name)?;
+            Some(Profile {
+                name,
+                classes: parse_classes(&value),
+            })
+        })
+        .collect())
+}
+
+pub fn get_profile(target: &Target, name: &str) -> io::Result<Option<Profile>> {
+    let doc = read_profiles(target)?;
+    Ok(doc.get(PROFILES_GROUP, name).map(|value| Profile {
+        name: name.to_string(),
+        classes: parse_classes(&value),
+    }))
+}
+
+/// Store `classes` under `name`. An existing profile is only replaced with
+/// `replace`; otherwise it is an `AlreadyExists` error. Entries `check_class`
+/// rejects are an `InvalidInput` error. Returns whether the file changed.
+pub fn save_profile(
+    target: &Target,
+    name: &str,
+    classes: &[String],
+    replace: bool,
+) -> io::Result<bool> {
+    check_profile_name(name).map_err(invalid)?;
+    let value = join_classes(classes);
+    check_classes(&value).map_err(invalid)?;
+
+    update_profiles(target, |doc| {
+        if !replace && doc.get(PROFILES_GROUP, name).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("profile already exists: {}", name),
+            ));
+        }
+        doc.set(PROFILES_GROUP, name, &value)
+    })
+}
+
+/// Remove a profile (and forget it as active). Returns whether it existed.
+pub fn delete_profile(target: &Target, name: &str) -> io::Result<bool> {
+    update_profiles(target, |doc| {
+        let changed = doc.remove(PROFILES_GROUP, name)?;
+        if changed && doc.get(GENERAL_GROUP, ACTIVE_KEY).as_deref() == Some(name) {
+            doc.remove(GENERAL_GROUP, ACTIVE_KEY)?;
+        }
+        Ok(changed)
+    })
+}
+
+/// Write the profile's list to kwinrc with `set_classes` and mark it active.
+/// Returns whether kwinrc changed; an unknown profile is a `NotFound` error.
+pub fn use_profile(target: &Target, name: &str) -> io::Result<bool> {
+    let doc = read_profiles(target)?;
+    let value = doc.get(PROFILES_GROUP, name).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no such profile: {}", name),
+        )
+    })?;
+
+    let changed = config::set_classes(target, &parse_classes(&value))?;
+    update_profiles(target, |doc| doc.set(GENERAL_GROUP, ACTIVE_KEY, name))?;
+    Ok(changed)
+}
+
+/// The active profile, if it still exists. It counts as modified when
+/// kwinrc's class list no longer has the same match keys in the same order.
+pub fn active_profile(target: &Target) -> io::Result<Option<ActiveProfile>> {
+    let doc = read_profiles(target)?;
+    let Some(name) = doc.get(GENERAL_GROUP, ACTIVE_KEY) else {
+        return Ok(None);
+    };
+    let Some(value) = doc.get(PROFILES_GROUP, &name) else {
+        return Ok(None);
+    };
+
+    let keys =
+        |classes: &[String]| -> Vec<String> { classes.iter().map(|c| class_key(c)).collect() };
+    let modified = keys(&parse_classes(&value)) != keys(&get_classes(target)?);
+    Ok(Some(ActiveProfile { name, modified }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_target(tag: &str) -> Target {
+        let home =
+            std::env::temp_dir().join(format!("focusctl-profile-{}-{}", tag, std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        fs::create_dir_all(home.join(".config")).unwrap();
+        Target {
+            uid: 0,
+            user: "test".into(),
+            home,
+        }
+    }
+
+    fn list(s: &str) -> Vec<String> {
+        parse_classes(s)
+    }
+
+    #[test]
+    fn profile_names_are_plain_words() {
+        assert!(check_profile_name("vm-day_2.b").is_ok());
+        assert!(check_profile_name("").is_err());
+        assert!(check_profile_name(".hidden").is_err());
+        assert!(check_profile_name("a b").is_err());
+        assert!(check_profile_name("a[de]").is_err());
+        assert!(check_profile_name("a=b").is_err());
+    }
+
+    #[test]
+    fn save_list_and_delete() {
+        let t = temp_target("crud");
+        assert!(save_profile(&t, "browser", &list("proclet-*;Firefox"), false).unwrap());
+        assert!(save_profile(&t, "vm", &list("virt-manager"), false).unwrap());
+
+        let e = save_profile(&t, "vm", &list("x"), false).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert!(save_profile(&t, "vm", &list("virt-manager;looking-glass-client"), true).unwrap());
+        let e = save_profile(&t, "bad", &list("class:a:b"), false).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+
+        let names: Vec<String> = list_profiles(&t)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(names, ["browser", "vm"]);
+        assert_eq!(
+            get_profile(&t, "browser").unwrap().unwrap().classes,
+            ["proclet-*", "Firefox"]
+        );
+
+        assert!(delete_profile(&t, "browser").unwrap());
+        assert!(!delete_profile(&t, "browser").unwrap());
+        assert_eq!(get_profile(&t, "browser").unwrap(), None);
+        let _ = fs::remove_dir_all(&t.home);
+    }
+
+    #[test]
+    fn use_writes_kwinrc_and_tracks_drift() {
+        let t = temp_target("use");
+        save_profile(&t, "vm", &list("virt-manager;Looking-Glass-Client"), false).unwrap();
+        assert_eq!(active_profile(&t).unwrap(), None);
+        assert_eq!(
+            use_profile(&t, "nope").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        assert!(use_profile(&t, "vm").unwrap());
+        assert_eq!(
+            get_classes(&t).unwrap(),
+            ["virt-manager", "Looking-Glass-Client"]
+        );
+        let active = active_profile(&t).unwrap().unwrap();
+        assert_eq!((active.name.as_str(), active.modified), ("vm", false));
+
+        // Spelling is not drift; a different list is.
+        config::set_classes(&t, &list("Virt-Manager;looking-glass-client.desktop")).unwrap();
+        assert!(!active_profile(&t).unwrap().unwrap().modified);
+        config::add_class(&t, "firefox").unwrap();
+        assert!(active_profile(&t).unwrap().unwrap().modified);
+
+        assert!(use_profile(&t, "vm").unwrap());
+        assert!(!active_profile(&t).unwrap().unwrap().modified);
+        delete_profile(&t, "vm").unwrap();
+        assert_eq!(active_profile(&t).unwrap(), None);
+        let _ = fs::remove_dir_all(&t.home);
+    }
+}
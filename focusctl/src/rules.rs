@@ -0,0 +1,402 @@
+// -------------------------------
+// Per-class rules
+// -------------------------------
+// `forceFocusRules` gives individual classes their own mode and window-type
+// filter, next to the plain `forceFocusClasses` list (whose entries use the
+// global `mode` and apply to every eligible window):
+//
+//   forceFocusRules=chrome:activate;firefox:raise:dialogs
+//
+// An entry is `<class>[:<mode>][:<only>]`: options are peeled off the right
+// end, so the class itself (which may carry a `field:` prefix, see
+// `config::check_class`) is everything before the first recognized option.
+// `parseRule()` in contents/code/main.js reads entries the same way.
+
+use std::fmt;
+
+use crate::config::{check_class, class_key, match_key, parse_classes};
+use crate::kwin::WindowInfo;
+use crate::schema::MODES;
+
+/// Restrict a rule to one kind of window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Only {
+    /// `normal`: only normal (top-level application) windows.
+    Normal,
+    /// `dialogs`: only dialogs.
+    Dialogs,
+}
+
+impl Only {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Only::Normal => "normal",
+            Only::Dialogs => "dialogs",
+        }
+    }
+
+    /// Accepts the canonical words plus `normal-only`, `dialog`, `dialogs-only`.
+    pub fn parse(s: &str) -> Option<Only> {
+        match s.trim().to_lowercase().as_str() {
+            "normal" | "normal-only" => Some(Only::Normal),
+            "dialog" | "dialogs" | "dialogs-only" => Some(Only::Dialogs),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    /// Stored spelling.
+    pub class: String,
+    /// `None` = use the global `mode`.
+    pub mode: Option<&'static str>,
+    /// `None` = every eligible window.
+    pub only: Option<Only>,
+}
+
+impl Rule {
+    /// A `forceFocusClasses` entry: global mode, no filter.
+    pub fn plain(class: &str) -> Rule {
+        Rule {
+            class: class.trim().to_string(),
+            mode: None,
+            only: None,
+        }
+    }
+
+    pub fn key(&self) -> String {
+        class_key(&self.class)
+    }
+
+    /// True when the rule adds nothing over a plain class entry.
+    pub fn is_plain(&self) -> bool {
+        self.mode.is_none() && self.only.is_none()
+    }
+}
+
+/// `class[:mode][:only]`
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.class)?;
+        if let Some(m) = self.mode {
+            write!(f, ":{}", m)?;
+        }
+        if let Some(o) = self.only {
+            write!(f, ":{}", o.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// `activate` / `raise`, case-insensitive.
+pub fn parse_mode(s: &str) -> Option<&'static str> {
+    let lower = s.trim().to_lowercase();
+    MODES.iter().copied().find(|m| *m == lower)
+}
+
+/// Parse one entry, rejecting anything the script would silently ignore.
+pub fn parse_rule(entry: &str) -> Result<Rule, String> {
+    let mut parts: Vec<&str> = entry.trim().split(':').collect();
+    let mut mode = None;
+    let mut only = None;
+
+    while parts.len() > 1 {
+        let last = parts[parts.len() - 1];
+        if let Some(m) = parse_mode(last) {
+            if mode.replace(m).is_some() {
+                return Err(format!("rule '{}': more than one mode", entry));
+            }
+        } else if let Some(o) = Only::parse(last) {
+            if only.replace(o).is_some() {
+                return Err(format!("rule '{}': more than one window filter", entry));
+            }
+        } else {
+            break;
+        }
+        parts.pop();
+    }
+
+    // What is left is a class entry, possibly field-limited (`class:foo`).
+    let class = parts.join(":");
+    if let Err(e) = check_class(&class) {
+        return Err(match class.rsplit_once(':') {
+            Some((_, opt)) if !opt.trim().is_empty() => format!(
+                "rule '{}': unknown option '{}' (expected: {}|normal|dialogs)",
+                entry,
+                opt,
+                MODES.join("|")
+            ),
+            _ => format!("rule '{}': {}", entry, e),
+        });
+    }
+    Ok(Rule {
+        class: class.trim().to_string(),
+        mode,
+        only,
+    })
+}
+
+/// Strict: every entry must parse.
+pub fn check_rules(value: &str) -> Result<Vec<Rule>, String> {
+    parse_classes(value).iter().map(|e| parse_rule(e)).collect()
+}
+
+/// Lenient, like the script: entries that do not parse are skipped.
+pub fn parse_rules(value: &str) -> Vec<Rule> {
+    parse_classes(value)
+        .iter()
+        .filter_map(|e| parse_rule(e).ok())
+        .collect()
+}
+
+pub fn join_rules(rules: &[Rule]) -> String {
+    rules
+        .iter()
+        .map(|r| r.to_string())
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// What the script evaluates: rules first, then plain classes whose key no
+/// rule already covers. Later duplicates of a key are dropped.
+pub fn effective(rules: &[Rule], classes: &[String]) -> Vec<Rule> {
+    let mut out: Vec<Rule> = Vec::new();
+    let all = rules
+        .iter()
+        .cloned()
+        .chain(classes.iter().map(|c| Rule::plain(c)));
+    for r in all {
+        let k = r.key();
+        if !k.is_empty() && !out.iter().any(|o| o.key() == k) {
+            out.push(r);
+        }
+    }
+    out
+}
+
+// -------------------------------
+// Evaluation (what forceNow() does)
+// -------------------------------
+
+/// The check in `forceNow()` that stops a window from being forced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Blocked {
+    /// No configured entry matches.
+    NoMatch,
+    /// `isEligibleWindow()`: neither a normal window nor a dialog.
+    NotEligible,
+    /// `forceNow()` leaves minimized windows alone.
+    Minimized,
+    /// The matching rule is limited to the other window type.
+    Only(Only),
+    /// The window already has focus.
+    AlreadyActive,
+}
+
+impl Blocked {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Blocked::NoMatch => "no-match",
+            Blocked::NotEligible => "not-eligible",
+            Blocked::Minimized => "minimized",
+            Blocked::Only(Only::Dialogs) => "dialogs-only",
+            Blocked::Only(Only::Normal) => "normal-only",
+            Blocked::AlreadyActive => "already-active",
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Blocked::NoMatch => "no configured class or rule matches",
+            Blocked::NotEligible => "isEligibleWindow: neither a normal window nor a dialog",
+            Blocked::Minimized => "window is minimized",
+            Blocked::Only(Only::Dialogs) => "rule only applies to dialogs",
+            Blocked::Only(Only::Normal) => "rule only applies to normal windows",
+            Blocked::AlreadyActive => "window is already active",
+        }
+    }
+}
+
+/// Outcome of `evaluate`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Verdict {
+    /// The matching entry and the property it matched on.
+    pub matched: Option<(&'static str, Rule)>,
+    pub blocked: Option<Blocked>,
+    /// `activate` or `raise` when the window would be forced.
+    pub action: Option<&'static str>,
+}
+
+/// Run a window through the same steps as `forceNow()`: `matchForced()`,
+/// `isEligibleWindow()`, minimized, the rule's window filter, already
+/// active. `entries` is what `config::effective_rules` returns; rules
+/// without a mode use `global_mode`.
+pub fn evaluate(
+    entries: &[Rule],
+    global_mode: &str,
+    w: &WindowInfo,
+    minimized: bool,
+    active: bool,
+) -> Verdict {
+    let keys: Vec<String> = entries.iter().map(|r| r.key()).collect();
+    let matched = match_key(w, &keys).and_then(|(property, key)| {
+        let rule = entries.iter().find(|r| r.key() == key)?;
+        Some((property, rule.clone()))
+    });
+    let Some((_, rule)) = &matched else {
+        return Verdict {
+            matched,
+            blocked: Some(Blocked::NoMatch),
+            action: None,
+        };
+    };
+
+    let blocked = if !(w.normal || w.dialog) {
+        Some(Blocked::NotEligible)
+    } else if minimized {
+        Some(Blocked::Minimized)
+    } else if rule.only == Some(Only::Dialogs) && !w.dialog {
+        Some(Blocked::Only(Only::Dialogs))
+    } else if rule.only == Some(Only::Normal) && !w.normal {
+        Some(Blocked::Only(Only::Normal))
+    } else if active {
+        Some(Blocked::AlreadyActive)
+    } else {
+        None
+    };
+    let action = match blocked {
+        Some(_) => None,
+        None => rule.mode.or(parse_mode(global_mode)).or(Some("activate")),
+    };
+    Verdict {
+        matched,
+        blocked,
+        action,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::{self, Json};
+    use crate::schema;
+
+    /// Shared with `tests/match-vectors.js`, which runs them against main.js.
+    const VECTORS: &str = include_str!("../tests/fixtures/match-vectors.json");
+
+    fn opt_str(v: &Json, key: &str) -> Option<String> {
+        v.get(key).and_then(Json::as_str).map(str::to_string)
+    }
+
+    #[test]
+    fn options_are_peeled_from_the_right() {
+        let r = parse_rule("Firefox:Raise:dialogs").unwrap();
+        assert_eq!(r.class, "Firefox");
+        assert_eq!(r.mode, Some("raise"));
+        assert_eq!(r.only, Some(Only::Dialogs));
+        assert_eq!(r.to_string(), "Firefox:raise:dialogs");
+
+        let r = parse_rule("chrome:normal-only:activate").unwrap();
+        assert_eq!((r.mode, r.only), (Some("activate"), Some(Only::Normal)));
+        assert_eq!(r.to_string(), "chrome:activate:normal");
+
+        assert!(parse_rule("chrome").unwrap().is_plain());
+
+        let r = parse_rule("resourceClass:ProcletChrome:raise").unwrap();
+        assert_eq!(r.class, "resourceClass:ProcletChrome");
+        assert_eq!(r.key(), "class:procletchrome");
+    }
+
+    #[test]
+    fn bad_rules_are_rejected_strictly_and_skipped_leniently() {
+        assert!(parse_rule("chrome:fast").is_err());
+        assert!(parse_rule("chrome:raise:activate").is_err());
+        assert!(parse_rule(":raise").is_err());
+        assert!(check_rules("a:raise;b:bogus").is_err());
+        assert_eq!(parse_rules("a:raise;b:bogus;c").len(), 2);
+    }
+
+    #[test]
+    fn rules_win_over_plain_classes() {
+        let rules = parse_rules("chrome:raise;Chrome:activate");
+        let classes = vec!["chrome.desktop".to_string(), "firefox".to_string()];
+        let eff = effective(&rules, &classes);
+        assert_eq!(join_rules(&eff), "chrome:raise;firefox");
+    }
+
+    #[test]
+    fn class_keys_match_the_conformance_vectors() {
+        let v = json::parse(VECTORS).unwrap();
+        for k in v.get("keys").and_then(Json::as_array).unwrap() {
+            let entry = opt_str(k, "entry").unwrap();
+            assert_eq!(class_key(&entry), opt_str(k, "key").unwrap(), "{:?}", entry);
+        }
+    }
+
+    #[test]
+    fn evaluate_matches_the_conformance_vectors() {
+        let v = json::parse(VECTORS).unwrap();
+        let mode = schema::lookup("mode").unwrap();
+        for c in v.get("cases").and_then(Json::as_array).unwrap() {
+            let name = opt_str(c, "name").unwrap();
+            let entries = effective(
+                &parse_rules(&opt_str(c, "rules").unwrap_or_default()),
+                &parse_classes(&opt_str(c, "classes").unwrap_or_default()),
+            );
+            let global = mode.effective(opt_str(c, "mode").as_deref());
+
+            let win = c.get("window").unwrap();
+            let flag = |k: &str| win.get(k).and_then(Json::as_bool).unwrap_or(false);
+            let w = WindowInfo {
+                desktop_file: opt_str(win, "desktopFileName").unwrap_or_default(),
+                resource_class: opt_str(win, "resourceClass").unwrap_or_default(),
+                resource_name: opt_str(win, "resourceName").unwrap_or_default(),
+                normal: flag("normalWindow"),
+                dialog: flag("dialog"),
+                ..Default::default()
+            };
+
+            let got = evaluate(&entries, &global, &w, flag("minimized"), flag("active"));
+            assert_eq!(
+                got.matched.map(|(_, r)| r.key()),
+                opt_str(c, "entry"),
+                "{}",
+                name
+            );
+            assert_eq!(
+                got.blocked.map(|b| b.as_str().to_string()),
+                opt_str(c, "blocked"),
+                "{}",
+                name
+            );
+            assert_eq!(
+                got.action.map(str::to_string),
+                opt_str(c, "action"),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn script_matches_the_conformance_vectors() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        let out = match std::process::Command::new("node")
+            .arg(dir.join("tests/match-vectors.js"))
+            .output()
+        {
+            Ok(out) => out,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                eprintln!("node not available; skipping");
+                return;
+            }
+            Err(e) => panic!("running node: {}", e),
+        };
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stdout)
+        );
+    }
+}
@@ -0,0 +1,196 @@
+// -------------------------------
+// Script config schema
+// -------------------------------
+// Typed description of the keys `reloadConfig()` in contents/code/main.js
+// reads from `[Script-kwin-focus-helper]`. Values are validated and normalized
+// here so that whatever we write is something the script actually accepts.
+
+use crate::config::{check_classes, class_key, join_classes, parse_classes};
+use crate::rules::{check_rules, join_rules, parse_rules};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// `;`-joined class list (input separators: ';' ',' whitespace).
+    ClassList,
+    /// `;`-joined `class[:mode][:only]` rules (see `rules`).
+    RuleList,
+    /// One of a fixed set of lowercase words.
+    Enum(&'static [&'static str]),
+    /// The script only treats the literal `true` as true.
+    Bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct KeySpec {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub default: &'static str,
+    pub help: &'static str,
+}
+
+pub const MODES: &[&str] = &["activate", "raise"];
+
+pub const SCRIPT_KEYS: &[KeySpec] = &[
+    KeySpec {
+        name: "forceFocusClasses",
+        kind: Kind::ClassList,
+        default: "",
+        help: "Window classes allowed to take focus",
+    },
+    KeySpec {
+        name: "forceFocusRules",
+        kind: Kind::RuleList,
+        default: "",
+        help: "Per-class rules: class[:activate|raise][:normal|dialogs]",
+    },
+    KeySpec {
+        name: "mode",
+        kind: Kind::Enum(MODES),
+        default: "activate",
+        help: "activate = raise + focus, raise = stacking only",
+    },
+    KeySpec {
+        name: "debug",
+        kind: Kind::Bool,
+        default: "false",
+        help: "Log decisions to the KWin journal",
+    },
+];
+
+/// Case-insensitive lookup (`forcefocusclasses` finds `forceFocusClasses`).
+pub fn lookup(name: &str) -> Option<&'static KeySpec> {
+    SCRIPT_KEYS
+        .iter()
+        .find(|k| k.name.eq_ignore_ascii_case(name.trim()))
+}
+
+impl KeySpec {
+    /// Validate user input and return the exact string to store.
+    pub fn normalize(&self, raw: &str) -> Result<String, String> {
+        let v = raw.trim();
+        match self.kind {
+            Kind::ClassList => check_classes(v).map(|c| join_classes(&c)),
+            Kind::RuleList => check_rules(v).map(|r| join_rules(&r)),
+            Kind::Enum(allowed) => {
+                // Same normalization as normClass() in the script.
+                let lower = v.to_lowercase();
+                let lower = lower.strip_suffix(".desktop").unwrap_or(&lower);
+                if allowed.contains(&lower) {
+                    Ok(lower.to_string())
+                } else {
+                    Err(format!(
+                        "invalid value for {}: '{}' (expected: {})",
+                        self.name,
+                        v,
+                        allowed.join("|")
+                    ))
+                }
+            }
+            Kind::Bool => match v.to_lowercase().as_str() {
+                "true" | "on" | "yes" | "1" => Ok("true".to_string()),
+                "false" | "off" | "no" | "0" => Ok("false".to_string()),
+                _ => Err(format!(
+                    "invalid value for {}: '{}' (expected: true|false|on|off)",
+                    self.name, v
+                )),
+            },
+        }
+    }
+
+    /// How the script interprets a stored value (falls back like reloadConfig()).
+    pub fn effective(&self, stored: Option<&str>) -> String {
+        let Some(v) = stored else {
+            return self.default.to_string();
+        };
+        match self.kind {
+            // `safeStr(rawDebug).toLowerCase() === "true"`
+            Kind::Bool => (v.to_lowercase() == "true").to_string(),
+            // The script skips entries it cannot read.
+            Kind::ClassList => {
+                let mut classes = parse_classes(v);
+                classes.retain(|c| !class_key(c).is_empty());
+                join_classes(&classes)
+            }
+            Kind::RuleList => join_rules(&parse_rules(v)),
+            _ => self.normalize(v).unwrap_or_else(|_| self.default.to_string()),
+        }
+    }
+
+    pub fn value_hint(&self) -> String {
+        match self.kind {
+            Kind::ClassList => "<c1;c2;...>".to_string(),
+            Kind::RuleList => "<class[:mode][:only];...>".to_string(),
+            Kind::Enum(allowed) => allowed.join("|"),
+            Kind::Bool => "true|false".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_is_case_insensitive() {
+        assert_eq!(lookup("MODE").unwrap().name, "mode");
+        assert_eq!(
+            lookup("forcefocusclasses").unwrap().name,
+            "forceFocusClasses"
+        );
+        assert!(lookup("nope").is_none());
+    }
+
+    #[test]
+    fn mode_accepts_the_script_enum_only() {
+        let mode = lookup("mode").unwrap();
+        assert_eq!(mode.normalize(" Raise ").unwrap(), "raise");
+        assert_eq!(mode.normalize("activate").unwrap(), "activate");
+        assert!(mode.normalize("focus").is_err());
+    }
+
+    #[test]
+    fn bools_are_stored_as_literal_true_false() {
+        let debug = lookup("debug").unwrap();
+        assert_eq!(debug.normalize("on").unwrap(), "true");
+        assert_eq!(debug.normalize("OFF").unwrap(), "false");
+        assert!(debug.normalize("maybe").is_err());
+        // The script reads anything but "true" as false.
+        assert_eq!(debug.effective(Some("garbage")), "false");
+        assert_eq!(debug.effective(Some("on")), "false");
+        assert_eq!(debug.effective(Some("TRUE")), "true");
+        assert_eq!(debug.effective(None), "false");
+    }
+
+    #[test]
+    fn effective_mode_falls_back_like_the_script() {
+        let mode = lookup("mode").unwrap();
+        assert_eq!(mode.effective(Some("weird")), "activate");
+        assert_eq!(mode.effective(Some("RAISE")), "raise");
+    }
+
+    #[test]
+    fn class_lists_are_rejoined() {
+        let classes = lookup("forceFocusClasses").unwrap();
+        assert_eq!(classes.normalize("a, b;c  d").unwrap(), "a;b;c;d");
+        assert_eq!(
+            classes.normalize("resourceClass:Foo desktop:bar").unwrap(),
+            "resourceClass:Foo;desktop:bar"
+        );
+        assert!(classes.normalize("a;nope:b").is_err());
+        assert_eq!(classes.effective(Some("a;nope:b")), "a");
+    }
+
+    #[test]
+    fn rule_lists_are_validated_and_canonicalized() {
+        let rules = lookup("forcefocusrules").unwrap();
+        assert_eq!(
+            rules
+                .normalize("chrome:Activate, firefox:dialogs-only:raise")
+                .unwrap(),
+            "chrome:activate;firefox:raise:dialogs"
+        );
+        assert!(rules.normalize("chrome:focus").is_err());
+        // The script skips a bad entry, not the whole key.
+        assert_eq!(rules.effective(Some("a:raise;b:nope")), "a:raise");
+    }
+}
@@ -0,0 +1,426 @@
+// -------------------------------
+// logind session discovery
+// -------------------------------
+// Reads systemd-logind's state files (`/run/systemd/sessions/*`,
+// `/run/systemd/users/<uid>`) directly. If they are not there (non-systemd
+// layout, restricted /run), falls back to a single `loginctl show-session`
+// call for all sessions instead of one process per property.
+//
+// Also reads the environment a user's session runs with, for settings such as
+// XDG_CONFIG_HOME that only exist there.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const SESSIONS_DIR: &str = "/run/systemd/sessions";
+const USERS_DIR: &str = "/run/systemd/users";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    pub id: String,
+    pub uid: u32,
+    /// `wayland`, `x11`, `tty`, `unspecified`, ...
+    pub ty: String,
+    /// `user`, `greeter`, `manager`, ...
+    pub class: String,
+    /// `active`, `online`, `closing`
+    pub state: String,
+    pub active: bool,
+    pub seat: Option<String>,
+    pub desktop: Option<String>,
+    pub runtime_dir: PathBuf,
+}
+
+impl Session {
+    pub fn is_graphical(&self) -> bool {
+        self.ty == "wayland" || self.ty == "x11"
+    }
+
+    /// The kind of session KWin runs in for a real user.
+    pub fn is_active_graphical(&self) -> bool {
+        self.active
+            && self.class == "user"
+            && self.is_graphical()
+            && (self.state == "active" || self.state == "online")
+    }
+
+    pub fn bus_address(&self) -> String {
+        format!("unix:path={}", self.runtime_dir.join("bus").display())
+    }
+}
+
+/// All sessions known to logind, sorted by id.
+pub fn list() -> io::Result<Vec<Session>> {
+    let mut out = match read_state_dir(Path::new(SESSIONS_DIR)) {
+        Ok(v) => v,
+        Err(_) => via_loginctl()?,
+    };
+    out.sort_by_key(|s| natural_id(&s.id));
+    Ok(out)
+}
+
+/// First active graphical user session, optionally for a specific uid.
+pub fn active_graphical(uid: Option<u32>) -> io::Result<Option<Session>> {
+    Ok(list()?
+        .into_iter()
+        .find(|s| s.is_active_graphical() && uid.is_none_or(|u| s.uid == u)))
+}
+
+fn natural_id(id: &str) -> (u64, String) {
+    (id.parse().unwrap_or(u64::MAX), id.to_string())
+}
+
+fn read_state_dir(dir: &Path) -> io::Result<Vec<Session>> {
+    let mut out = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // `<id>.ref` are FIFOs, not state files.
+        if name.contains('.') {
+            continue;
+        }
+        let Ok(text) = fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        if let Some(s) = parse_state_file(&name, &text, runtime_dir_for) {
+            out.push(s);
+        }
+    }
+    Ok(out)
+}
+
+/// Parse one `/run/systemd/sessions/<id>` file.
+fn parse_state_file(id: &str, text: &str, runtime: impl Fn(u32) -> PathBuf) -> Option<Session> {
+    let get = |k: &str| -> Option<String> {
+        text.lines()
+            .filter(|l| !l.starts_with('#'))
+            .find_map(|l| l.strip_prefix(k)?.strip_prefix('='))
+            .map(|v| v.trim().to_string())
+    };
+
+    let uid: u32 = get("UID")?.parse().ok()?;
+    Some(Session {
+        id: id.to_string(),
+        uid,
+        ty: get("TYPE").unwrap_or_default(),
+        class: get("CLASS").unwrap_or_default(),
+        state: get("STATE").unwrap_or_default(),
+        active: get("ACTIVE").as_deref() == Some("1"),
+        seat: get("SEAT").filter(|s| !s.is_empty()),
+        desktop: get("DESKTOP").filter(|s| !s.is_empty()),
+        runtime_dir: runtime(uid),
+    })
+}
+
+/// `RUNTIME=` from `/run/systemd/users/<uid>`, else `/run/user/<uid>`.
+pub(crate) fn runtime_dir_for(uid: u32) -> PathBuf {
+    fs::read_to_string(Path::new(USERS_DIR).join(uid.to_string()))
+        .ok()
+        .and_then(|t| {
+            t.lines()
+                .find_map(|l| l.strip_prefix("RUNTIME="))
+                .map(|v| PathBuf::from(v.trim()))
+        })
+        .unwrap_or_else(|| PathBuf::from(format!("/run/user/{}", uid)))
+}
+
+const LOGINCTL_PROPS: &[&str] = &[
+    "Id", "User", "Type", "Class", "State", "Active", "Seat", "Desktop",
+];
+
+fn via_loginctl() -> io::Result<Vec<Session>> {
+    let out = Command::new("loginctl")
+        .args(["list-sessions", "--no-legend"])
+        .output()?;
+    if !out.status.success() {
+        return Ok(Vec::new());
+    }
+    let ids: Vec<String> = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .filter_map(|l| l.split_whitespace().next().map(|s| s.to_string()))
+        .collect();
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // One process for every session and property.
+    let mut cmd = Command::new("loginctl");
+    cmd.arg("show-session").args(&ids);
+    for p in LOGINCTL_PROPS {
+        cmd.args(["-p", p]);
+    }
+    let out = cmd.output()?;
+    Ok(parse_show_session(
+        &String::from_utf8_lossy(&out.stdout),
+        runtime_dir_for,
+    ))
+}
+
+/// `loginctl show-session a b ...` prints one `Key=Value` block per session,
+/// separated by blank lines.
+fn parse_show_session(text: &str, runtime: impl Fn(u32) -> PathBuf) -> Vec<Session> {
+    let mut out = Vec::new();
+    for block in text.split("\n\n") {
+        let get = |k: &str| -> Option<String> {
+            block
+                .lines()
+                .find_map(|l| l.strip_prefix(k)?.strip_prefix('='))
+                .map(|v| v.trim().to_string())
+        };
+        let (Some(id), Some(uid)) = (get("Id"), get("User").and_then(|u| u.parse().ok())) else {
+            continue;
+        };
+        out.push(Session {
+            id,
+            uid,
+            ty: get("Type").unwrap_or_default(),
+            class: get("Class").unwrap_or_default(),
+            state: get("State").unwrap_or_default(),
+            active: get("Active").as_deref() == Some("yes"),
+            seat: get("Seat").filter(|s| !s.is_empty()),
+            desktop: get("Desktop").filter(|s| !s.is_empty()),
+            runtime_dir: runtime(uid),
+        });
+    }
+    out
+}
+
+// -------------------------------
+// Session environment
+// -------------------------------
+// A session's environment is what its compositor and shell were started with:
+// `/proc/<pid>/environ` of a running `kwin_wayland`, `kwin_x11` or
+// `plasmashell` (readable by root and, unless the process dropped
+// dumpability, by its user), else the user's systemd manager
+// (`systemctl --user show-environment`).
+
+/// Session processes, most authoritative first.
+const SESSION_PROCESSES: &[&str] = &["kwin_wayland", "kwin_x11", "plasmashell"];
+
+/// Environment variables of a user's running session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionEnv {
+    /// Where they were read from: `kwin_wayland pid 1203`, `systemd --user`.
+    pub source: String,
+    pub vars: Vec<(String, String)>,
+}
+
+impl SessionEnv {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// The environment of `uid`'s running session, if one can be read.
+pub fn environment(uid: u32, user: &str) -> Option<SessionEnv> {
+    process_environment(uid).or_else(|| systemd_environment(uid, user))
+}
+
+fn process_environment(uid: u32) -> Option<SessionEnv> {
+    let mut found: Vec<(usize, u32)> = Vec::new();
+    for entry in fs::read_dir("/proc").ok()?.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let Ok(comm) = fs::read_to_string(entry.path().join("comm")) else {
+            continue;
+        };
+        let Some(rank) = SESSION_PROCESSES.iter().position(|p| *p == comm.trim()) else {
+            continue;
+        };
+        // The real uid: a non-dumpable process's /proc entry belongs to root.
+        let status = fs::read_to_string(entry.path().join("status")).unwrap_or_default();
+        if status_uid(&status) == Some(uid) {
+            found.push((rank, pid));
+        }
+    }
+    found.sort();
+
+    found.into_iter().find_map(|(rank, pid)| {
+        let bytes = fs::read(format!("/proc/{}/environ", pid)).ok()?;
+        Some(SessionEnv {
+            source: format!("{} pid {}", SESSION_PROCESSES[rank], pid),
+            vars: parse_environ(&bytes),
+        })
+    })
+}
+
+/// Real uid from `/proc/<pid>/status` (`Uid:\treal\teffective\t...`).
+fn status_uid(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|l| l.strip_prefix("Uid:"))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// `KEY=value` entries separated by NUL bytes.
+fn parse_environ(bytes: &[u8]) -> Vec<(String, String)> {
+    bytes
+        .split(|b| *b == 0)
+        .filter_map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            let (k, v) = entry.split_once('=')?;
+            Some((k.to_string(), v.to_string()))
+        })
+        .collect()
+}
+
+fn systemd_environment(uid: u32, user: &str) -> Option<SessionEnv> {
+    // Only ask a manager that is running; otherwise systemctl just fails
+    // after trying to connect.
+    if !runtime_dir_for(uid).join("systemd").is_dir() {
+        return None;
+    }
+    let mut cmd = Command::new("systemctl");
+    cmd.arg("--user");
+    if crate::creds::euid() != uid {
+        cmd.arg(format!("--machine={}@.host", user));
+    }
+    let out = cmd.arg("show-environment").output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    Some(SessionEnv {
+        source: "systemd --user".to_string(),
+        vars: parse_show_environment(&String::from_utf8_lossy(&out.stdout)),
+    })
+}
+
+/// `systemctl show-environment` prints `KEY=value`, quoting values that need
+/// it as `$'...'`.
+fn parse_show_environment(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .filter_map(|l| {
+            let (k, v) = l.split_once('=')?;
+            let v = match v.strip_prefix("$'").and_then(|v| v.strip_suffix('\'')) {
+                Some(quoted) => quoted.replace("\\'", "'").replace("\\\\", "\\"),
+                None => v.to_string(),
+            };
+            Some((k.to_string(), v))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLASMA_SESSION: &str = "\
+# This is private data. Do not parse.
+UID=1000
+USER=darko
+ACTIVE=1
+IS_DISPLAY=1
+STATE=active
+REMOTE=0
+TYPE=wayland
+ORIGINAL_TYPE=wayland
+CLASS=user
+SCOPE=session-2.scope
+FIFO=/run/systemd/sessions/2.ref
+SEAT=seat0
+TTY=tty2
+SERVICE=sddm
+DESKTOP=KDE
+VTNR=2
+LEADER=1203
+";
+
+    fn fake_runtime(uid: u32) -> PathBuf {
+        PathBuf::from(format!("/tmp/rt-{}", uid))
+    }
+
+    #[test]
+    fn parses_state_file() {
+        let s = parse_state_file("2", PLASMA_SESSION, fake_runtime).unwrap();
+        assert_eq!(
+            s,
+            Session {
+                id: "2".into(),
+                uid: 1000,
+                ty: "wayland".into(),
+                class: "user".into(),
+                state: "active".into(),
+                active: true,
+                seat: Some("seat0".into()),
+                desktop: Some("KDE".into()),
+                runtime_dir: "/tmp/rt-1000".into(),
+            }
+        );
+        assert!(s.is_active_graphical());
+        assert_eq!(s.bus_address(), "unix:path=/tmp/rt-1000/bus");
+    }
+
+    #[test]
+    fn ssh_and_greeter_sessions_are_not_graphical_targets() {
+        let ssh = "UID=1000\nACTIVE=1\nSTATE=active\nTYPE=tty\nCLASS=user\n";
+        let greeter = "UID=964\nACTIVE=1\nSTATE=active\nTYPE=wayland\nCLASS=greeter\n";
+        assert!(
+            !parse_state_file("5", ssh, fake_runtime)
+                .unwrap()
+                .is_active_graphical()
+        );
+        assert!(
+            !parse_state_file("c1", greeter, fake_runtime)
+                .unwrap()
+                .is_active_graphical()
+        );
+        assert!(parse_state_file("x", "ACTIVE=1\n", fake_runtime).is_none());
+    }
+
+    #[test]
+    fn parses_batched_loginctl_output() {
+        let text = "Id=2\nUser=1000\nType=wayland\nClass=user\nState=active\nActive=yes\n\
+                    Seat=seat0\nDesktop=KDE\n\n\
+                    Id=7\nUser=1001\nType=tty\nClass=user\nState=online\nActive=no\nSeat=\n\
+                    Desktop=\n";
+        let v = parse_show_session(text, fake_runtime);
+        assert_eq!(v.len(), 2);
+        assert!(v[0].is_active_graphical());
+        assert_eq!(v[1].uid, 1001);
+        assert_eq!(v[1].seat, None);
+        assert!(!v[1].active);
+    }
+
+    #[test]
+    fn ids_sort_numerically() {
+        let mut ids = vec!["10", "c2", "2"];
+        ids.sort_by_key(|i| natural_id(i));
+        assert_eq!(ids, vec!["2", "10", "c2"]);
+    }
+
+    #[test]
+    fn parses_session_environments() {
+        let v = parse_environ(b"HOME=/home/darko\0XDG_CONFIG_HOME=/home/darko/.cfg\0A=b=c\0\0");
+        assert_eq!(
+            v,
+            [
+                ("HOME".to_string(), "/home/darko".to_string()),
+                (
+                    "XDG_CONFIG_HOME".to_string(),
+                    "/home/darko/.cfg".to_string()
+                ),
+                ("A".to_string(), "b=c".to_string()),
+            ]
+        );
+
+        let v = parse_show_environment("HOME=/home/darko\nXDG_CONFIG_HOME=$'/home/darko/my cfg'\n");
+        assert_eq!(v[1].1, "/home/darko/my cfg");
+
+        let status = "Name:\tplasmashell\nUmask:\t0022\nUid:\t1000\t1000\t1000\t1000\n";
+        assert_eq!(status_uid(status), Some(1000));
+        assert_eq!(status_uid("Name:\tx\n"), None);
+    }
+}
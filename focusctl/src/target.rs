@@ -0,0 +1,114 @@
+// -------------------------------
+// Target selection (uid/user/auto)
+// -------------------------------
+// Whose KWin configuration and session we operate on. Usually the current
+// user; when running as root it can be any user from /etc/passwd.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::creds;
+use crate::session;
+
+/// A user whose kwinrc and graphical session focusctl operates on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub uid: u32,
+    pub user: String,
+    pub home: PathBuf,
+}
+
+/// (name, uid, gid, home) per /etc/passwd entry.
+fn parse_passwd() -> io::Result<Vec<(String, u32, u32, PathBuf)>> {
+    let s = fs::read_to_string("/etc/passwd")?;
+    let mut out = Vec::new();
+    for line in s.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // name:pw:uid:gid:gecos:home:shell
+        let parts: Vec<&str> = line.split(':').collect();
+        if parts.len() < 7 {
+            continue;
+        }
+        let name = parts[0].to_string();
+        let uid: u32 = match parts[2].parse() {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        let gid: u32 = match parts[3].parse() {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        let home = PathBuf::from(parts[5]);
+        out.push((name, uid, gid, home));
+    }
+    Ok(out)
+}
+
+/// Look a user up by login name in /etc/passwd.
+pub fn find_user_by_name(name: &str) -> io::Result<Option<Target>> {
+    for (n, uid, _, home) in parse_passwd()? {
+        if n == name {
+            return Ok(Some(Target { uid, user: n, home }));
+        }
+    }
+    Ok(None)
+}
+
+/// Look a user up by uid in /etc/passwd.
+pub fn find_user_by_uid(uid: u32) -> io::Result<Option<Target>> {
+    for (n, u, _, home) in parse_passwd()? {
+        if u == uid {
+            return Ok(Some(Target {
+                uid: u,
+                user: n,
+                home,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// Primary group of `uid` from /etc/passwd.
+pub fn primary_gid(uid: u32) -> io::Result<Option<u32>> {
+    Ok(parse_passwd()?
+        .into_iter()
+        .find(|(_, u, _, _)| *u == uid)
+        .map(|(_, _, gid, _)| gid))
+}
+
+/// The user of the first active graphical session (root-friendly).
+/// `Ok(None)` if there is no such session or its uid is not in /etc/passwd.
+pub fn find_session_user() -> io::Result<Option<Target>> {
+    match session::active_graphical(None)? {
+        Some(s) => find_user_by_uid(s.uid),
+        None => Ok(None),
+    }
+}
+
+/// The current user, from the environment (no /etc/passwd lookup).
+pub fn current_target() -> Target {
+    Target {
+        uid: current_uid(),
+        user: current_user(),
+        home: current_home(),
+    }
+}
+
+/// The effective uid of this process.
+pub fn current_uid() -> u32 {
+    creds::euid()
+}
+
+fn current_user() -> String {
+    env::var("USER").unwrap_or_else(|_| "unknown".to_string())
+}
+
+fn current_home() -> PathBuf {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
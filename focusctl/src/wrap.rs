@@ -0,0 +1,414 @@
+// -------------------------------
+// Wrapper: auto class naming
+// -------------------------------
+// `focusctl wrap --auto` derives a stable class name from the wrapped
+// command instead of asking the caller for one.
+
+use std::ffi::c_void;
+use std::fs;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::bus;
+use crate::kwin::WindowInfo;
+use crate::target::Target;
+
+unsafe extern "C" {
+    fn sigaction(signum: i32, act: *const SigAction, old: *mut SigAction) -> i32;
+    fn pthread_sigmask(how: i32, set: *const SigSet, old: *mut SigSet) -> i32;
+    fn kill(pid: i32, sig: i32) -> i32;
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Fallback class name for `wrap --auto` when no desktop entry runs argv[0]
+/// (see `desktop::resolve_class`): the first word of its basename,
+/// capitalized, plus `App` (`echo` -> `EchoApp`).
+pub fn auto_class_from_argv0(argv0: &str) -> String {
+    let base = basename(argv0);
+    let base = base.strip_suffix(".desktop").unwrap_or(base);
+    let base = base.strip_suffix(".sh").unwrap_or(base);
+
+    let mut words = Vec::new();
+    let mut cur = String::new();
+    for ch in base.chars() {
+        if ch.is_ascii_alphanumeric() {
+            cur.push(ch);
+        } else if !cur.is_empty() {
+            words.push(cur.clone());
+            cur.clear();
+        }
+    }
+    if !cur.is_empty() {
+        words.push(cur);
+    }
+
+    if words.is_empty() {
+        return "App".to_string();
+    }
+
+    // echo -> EchoApp
+    let mut out = String::new();
+    let first = &words[0];
+    let mut chars = first.chars();
+    if let Some(c0) = chars.next() {
+        out.push(c0.to_ascii_uppercase());
+        for c in chars {
+            out.push(c.to_ascii_lowercase());
+        }
+    }
+    out.push_str("App");
+    out
+}
+
+// -------------------------------
+// Supervised child (wrap --ephemeral, --learn)
+// -------------------------------
+// An ephemeral wrap has to outlive its command to take the class away
+// again, and a learning one to watch its windows appear, so instead of
+// exec'ing they run the command as a child and pass
+// on signals sent to the wrapper. Signals the terminal sends (Ctrl-C, a
+// hangup) already reach the child through its process group; they are not
+// sent a second time. The signals are blocked from installing the handlers
+// until the child's pid is known, so one arriving in between is held back
+// and forwarded instead of lost. The child gets the wrapper's own mask back.
+
+const SIGHUP: i32 = 1;
+const SIGINT: i32 = 2;
+const SIGQUIT: i32 = 3;
+const SIGUSR1: i32 = 10;
+const SIGUSR2: i32 = 12;
+const SIGTERM: i32 = 15;
+const FORWARDED: [i32; 6] = [SIGHUP, SIGINT, SIGQUIT, SIGUSR1, SIGUSR2, SIGTERM];
+
+const SIG_BLOCK: i32 = 0;
+const SIG_SETMASK: i32 = 2;
+const SA_SIGINFO: i32 = 4;
+const SA_RESTART: i32 = 0x1000_0000;
+/// `si_code` above this came from the kernel (the tty), not from kill(2).
+const SI_USER: i32 = 0;
+
+/// glibc's `sigset_t`.
+type SigSet = [u64; 16];
+
+/// glibc's `struct sigaction` on Linux.
+#[repr(C)]
+struct SigAction {
+    handler: usize,
+    mask: SigSet,
+    flags: i32,
+    restorer: usize,
+}
+
+/// The start of `siginfo_t`.
+#[repr(C)]
+struct SigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+}
+
+/// Pid signals go to; 0 while no child is running.
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward(sig: i32, info: *const SigInfo, _: *mut c_void) {
+    let pid = CHILD.load(Ordering::SeqCst);
+    // SAFETY: the kernel passes a valid siginfo with SA_SIGINFO.
+    let from_tty = !info.is_null() && unsafe { (*info).code } > SI_USER;
+    if pid > 0 && !from_tty {
+        // SAFETY: kill(2) is async-signal-safe.
+        unsafe { kill(pid, sig) };
+    }
+}
+
+fn install_forwarding() -> io::Result<()> {
+    let act = SigAction {
+        handler: forward as extern "C" fn(i32, *const SigInfo, *mut c_void) as usize,
+        mask: [0; 16],
+        flags: SA_SIGINFO | SA_RESTART,
+        restorer: 0,
+    };
+    for sig in FORWARDED {
+        // SAFETY: `act` is a valid, fully initialised sigaction.
+        if unsafe { sigaction(sig, &act, std::ptr::null_mut()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Change this thread's signal mask; returns the previous one.
+fn set_mask(how: i32, set: &SigSet) -> io::Result<SigSet> {
+    let mut old = [0; 16];
+    // SAFETY: both pointers refer to valid sigset_t-sized buffers.
+    match unsafe { pthread_sigmask(how, set, &mut old) } {
+        0 => Ok(old),
+        e => Err(io::Error::from_raw_os_error(e)),
+    }
+}
+
+/// Start `argv` as a child and forward HUP, INT, QUIT, USR1, USR2 and TERM
+/// sent to this process to it until `wait_supervised`. The handlers stay
+/// installed but do nothing once the child is gone.
+pub fn spawn_supervised(argv: &[String]) -> io::Result<Child> {
+    let (prog, args) = argv
+        .split_first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+    let mut forwarded = [0; 16];
+    for sig in FORWARDED {
+        forwarded[0] |= 1 << (sig - 1);
+    }
+    let old = set_mask(SIG_BLOCK, &forwarded)?;
+    let mut command = Command::new(prog);
+    command.args(args);
+    // SAFETY: pthread_sigmask is async-signal-safe, so fine between fork
+    // and exec.
+    unsafe {
+        command.pre_exec(move || set_mask(SIG_SETMASK, &old).map(drop));
+    }
+    let child = install_forwarding().and_then(|()| command.spawn());
+    if let Ok(child) = &child {
+        CHILD.store(child.id() as i32, Ordering::SeqCst);
+    }
+    // Anything that came in meanwhile is delivered, and forwarded, now.
+    set_mask(SIG_SETMASK, &old)?;
+    child
+}
+
+pub fn wait_supervised(mut child: Child) -> io::Result<ExitStatus> {
+    let status = child.wait();
+    CHILD.store(0, Ordering::SeqCst);
+    status
+}
+
+/// `spawn_supervised`, then `wait_supervised`.
+pub fn run_supervised(argv: &[String]) -> io::Result<ExitStatus> {
+    wait_supervised(spawn_supervised(argv)?)
+}
+
+/// Exit code to pass on for `status`: the child's own, or 128 + the signal
+/// that killed it, as shells report it.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status
+        .code()
+        .or_else(|| status.signal().map(|s| 128 + s))
+        .unwrap_or(1)
+}
+
+// -------------------------------
+// Learning the real class (wrap --learn)
+// -------------------------------
+// The name a user passes (or `--auto` makes up) is often not what the app
+// puts on its windows. `learn_window` polls KWin until a window owned by the
+// wrapped command's process tree appears. Processes are remembered once
+// seen, so a launcher that forks the real app and exits still counts; a
+// command that hands over to an already running instance (or to a process
+// outside its tree) shows no window, and nothing is learned.
+
+/// How often KWin is asked for its windows while learning.
+const LEARN_INTERVAL: Duration = Duration::from_millis(500);
+
+/// `(pid, parent pid, zombie)` for every process in /proc.
+fn processes() -> Vec<(u32, u32, bool)> {
+    let Ok(dir) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    dir.flatten()
+        .filter_map(|e| {
+            let pid: u32 = e.file_name().to_str()?.parse().ok()?;
+            let stat = fs::read_to_string(e.path().join("stat")).ok()?;
+            // Fields after the command name: state, ppid, ...
+            let mut rest = stat[stat.rfind(')')? + 1..].split_whitespace();
+            let zombie = rest.next()? == "Z";
+            Some((pid, rest.next()?.parse().ok()?, zombie))
+        })
+        .collect()
+}
+
+/// Add every live descendant of a pid in `tree` to it; returns whether any
+/// of them is still running.
+fn grow_tree(tree: &mut Vec<u32>, procs: &[(u32, u32, bool)]) -> bool {
+    loop {
+        let before = tree.len();
+        for (pid, ppid, _) in procs {
+            if tree.contains(ppid) && !tree.contains(pid) {
+                tree.push(*pid);
+            }
+        }
+        if tree.len() == before {
+            break;
+        }
+    }
+    procs
+        .iter()
+        .any(|(pid, _, zombie)| !zombie && tree.contains(pid))
+}
+
+/// The name to store for a learned window: its best non-empty candidate,
+/// in the script's order (desktop file, class, name).
+pub fn learned_class(w: &WindowInfo) -> Option<String> {
+    w.candidates()
+        .iter()
+        .map(|(_, v)| v.trim())
+        .find(|v| !v.is_empty())
+        .map(str::to_string)
+}
+
+/// Wait up to `timeout` for a window of `root` or its descendants. `None`
+/// when none appeared, or the whole tree exited first.
+pub fn learn_window(
+    target: &Target,
+    root: u32,
+    timeout: Duration,
+) -> io::Result<Option<WindowInfo>> {
+    let mut tree = vec![root];
+    let deadline = Instant::now() + timeout;
+    bus::watch_kwin_windows(target, LEARN_INTERVAL, |windows| {
+        let running = grow_tree(&mut tree, &processes());
+        if let Some(w) = window_of(&tree, windows) {
+            return Some(Some(w.clone()));
+        }
+        (!running || Instant::now() >= deadline).then_some(None)
+    })
+}
+
+/// The window to learn from among those owned by `tree`: a normal one if
+/// there is one (not a splash screen or a dialog), else the first.
+fn window_of<'w>(tree: &[u32], windows: &'w [WindowInfo]) -> Option<&'w WindowInfo> {
+    let mut ours = windows
+        .iter()
+        .filter(|w| w.pid != 0 && tree.contains(&w.pid));
+    let first = ours.clone().next();
+    ours.find(|w| w.normal).or(first)
+}
+
+/// Tests that change process-wide state (also used by other modules' tests).
+#[cfg(test)]
+pub(crate) mod test_support {
+    use std::process::Command;
+
+    /// Run the test `path` (`wrap::tests::name`) again in a child test
+    /// process, where signal handlers or credentials it changes do not touch
+    /// the harness running the others. Returns false when already in that
+    /// child.
+    pub fn in_own_process(path: &str) -> bool {
+        const VAR: &str = "FOCUSCTL_TEST_OWN_PROCESS";
+        if std::env::var_os(VAR).is_some() {
+            return false;
+        }
+        let out = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", path, "--nocapture"])
+            .env(VAR, "1")
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(
+            out.status.success() && stdout.contains("1 passed"),
+            "{}{}",
+            stdout,
+            String::from_utf8_lossy(&out.stderr)
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_support::in_own_process;
+    use super::*;
+
+    #[test]
+    fn auto_class_uses_first_word_of_basename() {
+        assert_eq!(auto_class_from_argv0("echo"), "EchoApp");
+        assert_eq!(
+            auto_class_from_argv0("/usr/bin/google-chrome-stable"),
+            "GoogleApp"
+        );
+        assert_eq!(auto_class_from_argv0("./run-me.sh"), "RunApp");
+        assert_eq!(auto_class_from_argv0("---"), "App");
+    }
+
+    #[test]
+    fn supervised_exit_codes_are_passed_on() {
+        if in_own_process("wrap::tests::supervised_exit_codes_are_passed_on") {
+            return;
+        }
+        let sh = |script: &str| {
+            let argv = ["sh", "-c", script].map(String::from);
+            exit_code(run_supervised(&argv).unwrap())
+        };
+        assert_eq!(sh("exit 0"), 0);
+        assert_eq!(sh("exit 7"), 7);
+        assert_eq!(sh("kill -TERM $$"), 128 + SIGTERM);
+        // The forwarded signals are not left blocked in the child.
+        assert_eq!(sh("grep -q '^SigBlk:\\s*0*$' /proc/self/status"), 0);
+    }
+
+    #[test]
+    fn signals_sent_to_the_wrapper_reach_the_child() {
+        if in_own_process("wrap::tests::signals_sent_to_the_wrapper_reach_the_child") {
+            return;
+        }
+        unsafe extern "C" {
+            fn raise(sig: i32) -> i32;
+        }
+        let argv = ["sleep", "30"].map(String::from);
+        let child = spawn_supervised(&argv).unwrap();
+        // SAFETY: the handler installed above forwards and returns.
+        assert_eq!(unsafe { raise(SIGUSR1) }, 0);
+        assert_eq!(exit_code(wait_supervised(child).unwrap()), 128 + SIGUSR1);
+    }
+
+    #[test]
+    fn the_tree_keeps_orphaned_descendants() {
+        // 10 forked 11 and exited; 11 forked 12. Then 11 exits and 12 is
+        // reparented to init.
+        let mut tree = vec![10];
+        assert!(grow_tree(
+            &mut tree,
+            &[(10, 1, true), (11, 10, false), (12, 11, false)]
+        ));
+        assert_eq!(tree, [10, 11, 12]);
+        assert!(grow_tree(&mut tree, &[(12, 1, false), (13, 1, false)]));
+        assert_eq!(tree, [10, 11, 12]);
+        assert!(!grow_tree(&mut tree, &[(13, 1, false)]));
+    }
+
+    #[test]
+    fn normal_windows_of_the_tree_are_learned_first() {
+        let window = |pid: u32, class: &str, normal: bool| WindowInfo {
+            pid,
+            resource_class: class.into(),
+            normal,
+            ..WindowInfo::default()
+        };
+        let windows = [
+            window(5, "plasmashell", true),
+            window(11, "splash", false),
+            window(12, "app", true),
+        ];
+        let class = |tree: &[u32]| window_of(tree, &windows).map(|w| w.resource_class.as_str());
+        assert_eq!(class(&[10, 11, 12]), Some("app"));
+        assert_eq!(class(&[10, 11]), Some("splash"));
+        assert_eq!(class(&[10]), None);
+    }
+
+    #[test]
+    fn learned_class_is_the_best_candidate() {
+        let mut w = WindowInfo {
+            resource_class: "Google-chrome".into(),
+            resource_name: "google-chrome".into(),
+            ..WindowInfo::default()
+        };
+        assert_eq!(learned_class(&w).as_deref(), Some("Google-chrome"));
+        w.desktop_file = "google-chrome".into();
+        assert_eq!(learned_class(&w).as_deref(), Some("google-chrome"));
+        assert_eq!(learned_class(&WindowInfo::default()), None);
+    }
+}
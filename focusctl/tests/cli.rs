@@ -0,0 +1,147 @@
+// -------------------------------
+// The focusctl binary
+// -------------------------------
+// End-to-end runs of the built CLI against a throwaway HOME, for behaviour
+// that only exists at the process level: exit codes and separate processes
+// racing on one kwinrc.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn temp_home(tag: &str) -> PathBuf {
+    let home = std::env::temp_dir().join(format!("focusctl-cli-{}-{}", tag, std::process::id()));
+    let _ = fs::remove_dir_all(&home);
+    fs::create_dir_all(home.join(".config")).unwrap();
+    fs::create_dir_all(home.join("run")).unwrap();
+    home
+}
+
+/// focusctl with its environment pointed at `home` and no session around.
+fn focusctl(home: &Path) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_focusctl"));
+    cmd.env("HOME", home)
+        .env("XDG_RUNTIME_DIR", home.join("run"))
+        .env(
+            "DBUS_SESSION_BUS_ADDRESS",
+            format!("unix:path={}/run/bus", home.display()),
+        )
+        .env("NO_COLOR", "1")
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("XDG_DATA_HOME");
+    cmd
+}
+
+fn run(home: &Path, args: &[&str]) -> Output {
+    focusctl(home).args(args).output().unwrap()
+}
+
+#[test]
+fn doctor_exits_non_zero_when_a_check_fails() {
+    let home = temp_home("doctor");
+    // No session bus here, so at least that check fails.
+    let out = run(&home, &["doctor"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("FAIL"));
+
+    let out = run(&home, &["--json", "doctor"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("\"ok\":false"));
+    let _ = fs::remove_dir_all(&home);
+}
+
+#[test]
+fn failed_commands_exit_non_zero() {
+    let home = temp_home("fail");
+    let out = run(&home, &["get", "noSuchKey"]);
+    assert_eq!(out.status.code(), Some(1));
+    let out = run(&home, &["--json", "profile", "use", "missing"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("\"ok\":false"));
+    assert_eq!(run(&home, &["get", "mode"]).status.code(), Some(0));
+
+    // Bad usage: no command, or one that does not exist.
+    assert_eq!(run(&home, &[]).status.code(), Some(1));
+    assert_eq!(run(&home, &["bogus"]).status.code(), Some(1));
+    let out = run(&home, &["--json", "bogus"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("\"ok\":false"));
+
+    // match keeps 1 for "not forced"; its errors exit 2.
+    assert_eq!(
+        run(&home, &["match", "--class", "firefox"]).status.code(),
+        Some(1)
+    );
+    assert_eq!(run(&home, &["match"]).status.code(), Some(2));
+    let _ = fs::remove_dir_all(&home);
+}
+
+#[test]
+fn edits_that_change_nothing_do_not_reconfigure() {
+    // No KWin answers here, so only a reconfigure can fail these.
+    let home = temp_home("noop");
+    assert_eq!(run(&home, &["unset", "debug"]).status.code(), Some(0));
+    assert_eq!(run(&home, &["mode", "raise"]).status.code(), Some(1));
+    assert_eq!(run(&home, &["mode", "raise"]).status.code(), Some(0));
+    assert_eq!(run(&home, &["set", "mode", "raise"]).status.code(), Some(0));
+    assert_eq!(run(&home, &["unset", "mode"]).status.code(), Some(1));
+    let _ = fs::remove_dir_all(&home);
+}
+
+#[test]
+fn concurrent_add_class_and_wrap_runs_keep_every_class() {
+    let home = temp_home("race");
+    let classes: Vec<String> = (0..16).map(|n| format!("RaceClass{}", n)).collect();
+    let children: Vec<_> = classes
+        .iter()
+        .enumerate()
+        .map(|(n, class)| {
+            let mut cmd = focusctl(&home);
+            if n % 2 == 0 {
+                cmd.args(["add-class", class]);
+            } else {
+                cmd.args(["wrap", class, "--no-reconfigure", "--", "true"]);
+            }
+            cmd.stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    for mut child in children {
+        child.wait().unwrap();
+    }
+
+    let out = run(&home, &["list-classes"]);
+    let listed = String::from_utf8_lossy(&out.stdout);
+    for class in &classes {
+        assert!(
+            listed.lines().any(|l| l.trim() == class),
+            "{} lost:\n{}",
+            class,
+            listed
+        );
+    }
+    let _ = fs::remove_dir_all(&home);
+}
+
+#[test]
+fn apply_dry_runs_apply_nothing_and_exit_zero() {
+    let home = temp_home("apply");
+    let state = home.join("focus.toml");
+    fs::write(&state, "classes = [\"firefox\"]\n").unwrap();
+    let state = state.to_str().unwrap();
+
+    let out = run(&home, &["--dry-run", "apply", state]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("+forceFocusClasses=firefox"));
+    assert!(!home.join(".config/kwinrc").exists());
+
+    // Applied, but there is no KWin to reload it: an error, not "changed".
+    let out = run(&home, &["--json", "apply", state]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("\"ok\":false"));
+    assert!(home.join(".config/kwinrc").exists());
+    assert_eq!(run(&home, &["apply", state]).status.code(), Some(0));
+    let _ = fs::remove_dir_all(&home);
+}
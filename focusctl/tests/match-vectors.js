@@ -0,0 +1,102 @@
+// Run tests/fixtures/match-vectors.json against contents/code/main.js.
+//
+//   node focusctl/tests/match-vectors.js [main.js] [vectors.json]
+//
+// The script runs in a fresh sandbox per vector with a stub `workspace`,
+// debug logging on, and every timer fired at once. Its decisions are read
+// back from the debug log, so this also pins the log lines focusctl's docs
+// tell users to grep for. `rules::tests` in focusctl runs the same vectors
+// against the Rust side (and this file, when node is installed).
+
+"use strict";
+
+const fs = require("fs");
+const path = require("path");
+const vm = require("vm");
+
+const script = fs.readFileSync(
+  process.argv[2] || path.join(__dirname, "../../contents/code/main.js"),
+  "utf8"
+);
+const vectors = JSON.parse(
+  fs.readFileSync(process.argv[3] || path.join(__dirname, "fixtures/match-vectors.json"), "utf8")
+);
+
+const SKIPS = {
+  "not eligible": "not-eligible",
+  "minimized": "minimized",
+  "dialogs only": "dialogs-only",
+  "normal only": "normal-only",
+  "already active": "already-active",
+};
+
+// Load main.js with `config`, optionally hand it `win` as a new window.
+function run(config, win) {
+  const logs = [];
+  const handlers = {};
+  const workspace = {
+    activeWindow: null,
+    windowAdded: { connect: (f) => { handlers.added = f; } },
+    windowActivated: { connect: (f) => { handlers.activated = f; } },
+    raiseWindow: () => {},
+    activateWindow: (w) => { workspace.activeWindow = w; },
+  };
+  const sandbox = {
+    workspace,
+    readConfig: (k, d) => (config[k] === undefined ? d : config[k]),
+    print: (msg) => logs.push(String(msg)),
+    setTimeout: (f, delay) => { if (delay === 0) f(); },
+  };
+  vm.runInNewContext(script, sandbox);
+  if (win) {
+    if (win.active) workspace.activeWindow = win;
+    handlers.added(win);
+  }
+  return logs;
+}
+
+let failed = 0;
+function check(name, got, want) {
+  if (JSON.stringify(got) !== JSON.stringify(want)) {
+    failed++;
+    console.log(`FAIL ${name}\n  got:  ${JSON.stringify(got)}\n  want: ${JSON.stringify(want)}`);
+  }
+}
+
+for (const v of vectors.keys) {
+  const logs = run({ forceFocusClasses: v.entry, debug: "true" });
+  const line = logs.find((l) => l.includes("config reloaded:"));
+  const m = /forced=\[(.*)\], mode=/.exec(line || "");
+  check(`key ${JSON.stringify(v.entry)}`, m ? m[1] : null, v.key);
+}
+
+for (const c of vectors.cases) {
+  const config = {
+    forceFocusClasses: c.classes || "",
+    forceFocusRules: c.rules || "",
+    mode: c.mode || "activate",
+    debug: "true",
+  };
+  const logs = run(config, Object.assign({}, c.window));
+  const got = { entry: null, blocked: "no-match", action: null };
+  for (const l of logs) {
+    let m = /: apply (\w+): class=(\S+) \(/.exec(l);
+    if (m) {
+      got.action = m[1];
+      got.entry = m[2];
+      got.blocked = null;
+      break;
+    }
+    m = /: skip \(([^)]+)\): (\S+) \(/.exec(l);
+    if (m) {
+      got.blocked = SKIPS[m[1]] || m[1];
+      got.entry = m[2];
+      break;
+    }
+  }
+  check(c.name, got, { entry: c.entry, blocked: c.blocked, action: c.action });
+}
+
+const total = vectors.keys.length + vectors.cases.length;
+console.log(`${total - failed}/${total} match vectors passed`);
+process.exit(failed ? 1 : 0);